@@ -0,0 +1,20 @@
+#include <stdio.h>
+
+static inline __attribute__((always_inline)) int square(int x) {
+    int result = x * x;
+    return result;
+}
+
+static inline __attribute__((always_inline)) int sum_of_squares(int a, int b) {
+    int sum = square(a);
+    sum += square(b);
+    return sum;
+}
+
+int main() {
+    int total = sum_of_squares(3, 4);
+    printf("total = %d\n", total);
+    total = square(total);
+    printf("squared = %d\n", total);
+    return 0;
+}
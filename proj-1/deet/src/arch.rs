@@ -0,0 +1,458 @@
+//! What debugging depends on the processor for: the registers and how they are read, the
+//! breakpoint instruction and where the pc is once it traps, where a function finds its return
+//! address and arguments on entry, the system call registers and the DWARF register numbers.
+//! deet supports x86-64 Linux, and uses the backend of the processor it is built for. The
+//! aarch64 backend is experimental: it builds, and its unit tests pass, but deet hasn't been run
+//! against programs on an aarch64 machine yet. On both, a frame pointer points at the caller's
+//! frame pointer, followed by the return address.
+
+use nix::libc;
+use nix::unistd::Pid;
+use std::mem::{size_of, MaybeUninit};
+
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::*;
+
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::*;
+
+/// Reads the register set `note_type` (e.g. NT_PRSTATUS) of the stopped process `pid`, which is
+/// laid out as a `T`, with PTRACE_GETREGSET.
+fn get_regset<T>(pid: Pid, note_type: libc::c_int) -> Result<T, nix::Error> {
+    let mut regs = MaybeUninit::<T>::zeroed();
+    let mut iov = libc::iovec {
+        iov_base: regs.as_mut_ptr() as *mut libc::c_void,
+        iov_len: size_of::<T>(),
+    };
+    let result = unsafe { libc::ptrace(libc::PTRACE_GETREGSET, pid.as_raw(), note_type as usize as *mut libc::c_void, &mut iov) };
+    nix::Error::result(result)?;
+    // the registers are plain integers, so the zeroed rest of a short set is still valid
+    Ok(unsafe { regs.assume_init() })
+}
+
+/// Writes the register set `note_type` of the stopped process `pid` with PTRACE_SETREGSET.
+fn set_regset<T>(pid: Pid, note_type: libc::c_int, regs: &T) -> Result<(), nix::Error> {
+    let mut iov = libc::iovec {
+        iov_base: regs as *const T as *mut libc::c_void,
+        iov_len: size_of::<T>(),
+    };
+    let result = unsafe { libc::ptrace(libc::PTRACE_SETREGSET, pid.as_raw(), note_type as usize as *mut libc::c_void, &mut iov) };
+    nix::Error::result(result).map(|_| ())
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use super::{get_regset, set_regset};
+    use nix::libc::{self, user_fpregs_struct, user_regs_struct};
+    use nix::sys::ptrace;
+    use nix::unistd::Pid;
+
+    /// `int3`
+    pub const TRAP: [u8; 1] = [0xcc];
+
+    /// How far past a breakpoint's address the pc is once it has trapped.
+    pub const TRAP_PC_OFFSET: usize = 1;
+
+    /// `syscall`
+    pub const SYSCALL: [u8; 2] = [0x0f, 0x05];
+
+    pub const DWARF_FRAME_POINTER: gimli::Register = gimli::X86_64::RBP;
+    pub const DWARF_STACK_POINTER: gimli::Register = gimli::X86_64::RSP;
+
+    /// Where Linux loads position-independent executables when address randomization is
+    /// disabled, as `Inferior::new` does. It is only assumed until the program runs, when
+    /// where it was actually loaded is read.
+    pub const PIE_LOAD_ADDRESS: usize = 0x5555_5555_4000;
+
+    /// The registers of the gdb remote protocol's `g` packet, in the order gdb expects when the
+    /// stub does not describe its own, with their sizes in bytes.
+    pub const GDB_REGISTERS: &[(&str, usize)] = &[
+        ("rax", 8), ("rbx", 8), ("rcx", 8), ("rdx", 8), ("rsi", 8), ("rdi", 8), ("rbp", 8), ("rsp", 8),
+        ("r8", 8), ("r9", 8), ("r10", 8), ("r11", 8), ("r12", 8), ("r13", 8), ("r14", 8), ("r15", 8),
+        ("rip", 8), ("eflags", 4), ("cs", 4), ("ss", 4), ("ds", 4), ("es", 4), ("fs", 4), ("gs", 4),
+    ];
+
+    #[derive(Clone, Copy)]
+    pub struct Registers(user_regs_struct);
+
+    impl Default for Registers {
+        fn default() -> Registers {
+            Registers(unsafe { std::mem::zeroed() })
+        }
+    }
+
+    impl Registers {
+        pub fn read(pid: Pid) -> Result<Registers, nix::Error> {
+            get_regset(pid, libc::NT_PRSTATUS).map(Registers)
+        }
+
+        pub fn write(&self, pid: Pid) -> Result<(), nix::Error> {
+            set_regset(pid, libc::NT_PRSTATUS, &self.0)
+        }
+
+        pub fn pc(&self) -> usize {
+            self.0.rip as usize
+        }
+
+        pub fn set_pc(&mut self, pc: usize) {
+            self.0.rip = pc as u64;
+        }
+
+        pub fn stack_pointer(&self) -> usize {
+            self.0.rsp as usize
+        }
+
+        pub fn frame_pointer(&self) -> usize {
+            self.0.rbp as usize
+        }
+
+        /// The integer arguments of the function whose first instruction the pc is at.
+        pub fn arguments(&self) -> [u64; 6] {
+            [self.0.rdi, self.0.rsi, self.0.rdx, self.0.rcx, self.0.r8, self.0.r9]
+        }
+
+        /// Where the function whose first instruction the pc is at puts a structure it returns
+        /// in memory: the hidden first argument.
+        pub fn indirect_result(&self) -> usize {
+            self.0.rdi as usize
+        }
+
+        /// The registers a value of up to 16 bytes is returned in.
+        pub fn return_values(&self) -> [u64; 2] {
+            [self.0.rax, self.0.rdx]
+        }
+
+        pub fn syscall_number(&self) -> u64 {
+            self.0.orig_rax
+        }
+
+        pub fn syscall_arguments(&self) -> [u64; 6] {
+            [self.0.rdi, self.0.rsi, self.0.rdx, self.0.r10, self.0.r8, self.0.r9]
+        }
+
+        pub fn syscall_return(&self) -> u64 {
+            self.0.rax
+        }
+
+        /// Returns these registers set up for the `SYSCALL` instruction to call fork().
+        pub fn fork_call(&self) -> Registers {
+            let mut call = self.0;
+            call.rax = libc::SYS_fork as u64;
+            // not a system call to restart
+            call.orig_rax = u64::MAX;
+            Registers(call)
+        }
+
+        /// The registers the terminal UI shows, by name.
+        pub fn named(&self) -> Vec<(&'static str, u64)> {
+            let regs = &self.0;
+            vec![
+                ("rax", regs.rax), ("rbx", regs.rbx), ("rcx", regs.rcx), ("rdx", regs.rdx),
+                ("rsi", regs.rsi), ("rdi", regs.rdi), ("rbp", regs.rbp), ("rsp", regs.rsp),
+                ("r8", regs.r8), ("r9", regs.r9), ("r10", regs.r10), ("r11", regs.r11),
+                ("r12", regs.r12), ("r13", regs.r13), ("r14", regs.r14), ("r15", regs.r15),
+                ("rip", regs.rip), ("eflags", regs.eflags), ("fs_base", regs.fs_base), ("gs_base", regs.gs_base),
+            ]
+        }
+
+        /// Returns register `num` of `GDB_REGISTERS`.
+        pub fn gdb_register_mut(&mut self, num: usize) -> Option<&mut u64> {
+            let regs = &mut self.0;
+            Some(match num {
+                0 => &mut regs.rax,
+                1 => &mut regs.rbx,
+                2 => &mut regs.rcx,
+                3 => &mut regs.rdx,
+                4 => &mut regs.rsi,
+                5 => &mut regs.rdi,
+                6 => &mut regs.rbp,
+                7 => &mut regs.rsp,
+                8 => &mut regs.r8,
+                9 => &mut regs.r9,
+                10 => &mut regs.r10,
+                11 => &mut regs.r11,
+                12 => &mut regs.r12,
+                13 => &mut regs.r13,
+                14 => &mut regs.r14,
+                15 => &mut regs.r15,
+                16 => &mut regs.rip,
+                17 => &mut regs.eflags,
+                18 => &mut regs.cs,
+                19 => &mut regs.ss,
+                20 => &mut regs.ds,
+                21 => &mut regs.es,
+                22 => &mut regs.fs,
+                23 => &mut regs.gs,
+                _ => return None,
+            })
+        }
+    }
+
+    /// With the pc at a function's first instruction, returns the address the function returns
+    /// to, which the call pushed, and the stack pointer once it has returned.
+    pub fn return_address_at_entry(pid: Pid, regs: &Registers) -> Result<(usize, usize), nix::Error> {
+        let stack_ptr = regs.stack_pointer();
+        let return_addr = ptrace::read(pid, stack_ptr as ptrace::AddressType)? as usize;
+        Ok((return_addr, stack_ptr + 8))
+    }
+
+    /// Returns the canonical frame address (the caller's stack pointer before the call) of a
+    /// frame with `base_ptr` and `stack_ptr` that has no call frame information. Once the
+    /// prologue has run, the saved frame pointer and the return address sit just below it.
+    pub fn fallback_cfa(base_ptr: usize, _stack_ptr: usize) -> usize {
+        base_ptr + 16
+    }
+
+    /// Checks whether the instruction just stepped, which ran with registers `before` and left
+    /// them as `after`, was a call. If so, returns the address it will return to and the stack
+    /// pointer once it has.
+    pub fn called_from(pid: Pid, before: &Registers, after: &Registers) -> Result<Option<(usize, usize)>, nix::Error> {
+        if after.stack_pointer() + 8 != before.stack_pointer() {
+            return Ok(None);
+        }
+        let (return_addr, stack_ptr) = return_address_at_entry(pid, after)?;
+        // a call pushes the address of the following instruction
+        if return_addr > before.pc() && return_addr <= before.pc() + 16 {
+            Ok(Some((return_addr, stack_ptr)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads the low 16 bytes of %xmm0, where floating point values are returned.
+    pub fn float_return_value(pid: Pid) -> Result<[u8; 16], nix::Error> {
+        let fpregs: user_fpregs_struct = get_regset(pid, libc::NT_PRFPREG)?;
+        let mut bytes = [0; 16];
+        for (chunk, word) in bytes.chunks_mut(4).zip(&fpregs.xmm_space[..4]) {
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+/// Experimental: only checked to build and unit tested, not run against aarch64 programs.
+// also built for the tests on other hosts, which check what doesn't need an aarch64 process
+#[cfg(any(target_arch = "aarch64", test))]
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+mod aarch64 {
+    use super::{get_regset, set_regset};
+    use nix::libc;
+    use nix::unistd::Pid;
+
+    /// `brk #0`
+    pub const TRAP: [u8; 4] = 0xd420_0000u32.to_le_bytes();
+
+    /// How far past a breakpoint's address the pc is once it has trapped: `brk` leaves it at
+    /// the breakpoint.
+    pub const TRAP_PC_OFFSET: usize = 0;
+
+    /// `svc #0`
+    pub const SYSCALL: [u8; 4] = 0xd400_0001u32.to_le_bytes();
+
+    pub const DWARF_FRAME_POINTER: gimli::Register = gimli::AArch64::X29;
+    pub const DWARF_STACK_POINTER: gimli::Register = gimli::AArch64::SP;
+
+    /// Where Linux loads position-independent executables when address randomization is
+    /// disabled, as `Inferior::new` does, for the 64 KiB segment alignment aarch64 linkers use.
+    /// It is only assumed until the program runs, when where it was actually loaded is read.
+    pub const PIE_LOAD_ADDRESS: usize = 0xaaaa_aaaa_0000;
+
+    /// The registers of the gdb remote protocol's `g` packet, in the order gdb expects when the
+    /// stub does not describe its own, with their sizes in bytes.
+    pub const GDB_REGISTERS: &[(&str, usize)] = &[
+        ("x0", 8), ("x1", 8), ("x2", 8), ("x3", 8), ("x4", 8), ("x5", 8), ("x6", 8), ("x7", 8),
+        ("x8", 8), ("x9", 8), ("x10", 8), ("x11", 8), ("x12", 8), ("x13", 8), ("x14", 8), ("x15", 8),
+        ("x16", 8), ("x17", 8), ("x18", 8), ("x19", 8), ("x20", 8), ("x21", 8), ("x22", 8), ("x23", 8),
+        ("x24", 8), ("x25", 8), ("x26", 8), ("x27", 8), ("x28", 8), ("x29", 8), ("x30", 8),
+        ("sp", 8), ("pc", 8), ("cpsr", 4),
+    ];
+
+    /// clone() is how fork() is called: aarch64 has no fork system call.
+    const SYS_CLONE: u64 = 220;
+    const SIGCHLD: u64 = 17;
+
+    /// The general purpose registers as NT_PRSTATUS has them (struct user_pt_regs). x29 is the
+    /// frame pointer and x30 the link register.
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct UserPtRegs {
+        regs: [u64; 31],
+        sp: u64,
+        pc: u64,
+        pstate: u64,
+    }
+
+    /// The floating point registers as NT_PRFPREG has them (struct user_fpsimd_state).
+    #[repr(C)]
+    struct UserFpsimdState {
+        vregs: [u128; 32],
+        fpsr: u32,
+        fpcr: u32,
+        reserved: [u32; 2],
+    }
+
+    #[derive(Clone, Copy, Default)]
+    pub struct Registers(UserPtRegs);
+
+    impl Registers {
+        pub fn read(pid: Pid) -> Result<Registers, nix::Error> {
+            get_regset(pid, libc::NT_PRSTATUS).map(Registers)
+        }
+
+        pub fn write(&self, pid: Pid) -> Result<(), nix::Error> {
+            set_regset(pid, libc::NT_PRSTATUS, &self.0)
+        }
+
+        pub fn pc(&self) -> usize {
+            self.0.pc as usize
+        }
+
+        pub fn set_pc(&mut self, pc: usize) {
+            self.0.pc = pc as u64;
+        }
+
+        pub fn stack_pointer(&self) -> usize {
+            self.0.sp as usize
+        }
+
+        pub fn frame_pointer(&self) -> usize {
+            self.0.regs[29] as usize
+        }
+
+        /// The integer arguments of the function whose first instruction the pc is at.
+        pub fn arguments(&self) -> [u64; 6] {
+            self.0.regs[..6].try_into().unwrap()
+        }
+
+        /// Where the function whose first instruction the pc is at puts a structure it returns
+        /// in memory: x8.
+        pub fn indirect_result(&self) -> usize {
+            self.0.regs[8] as usize
+        }
+
+        /// The registers a value of up to 16 bytes is returned in.
+        pub fn return_values(&self) -> [u64; 2] {
+            [self.0.regs[0], self.0.regs[1]]
+        }
+
+        pub fn syscall_number(&self) -> u64 {
+            self.0.regs[8]
+        }
+
+        /// The arguments of a system call. x0 is overwritten by the result, so this is only
+        /// right at the entry to the call.
+        pub fn syscall_arguments(&self) -> [u64; 6] {
+            self.0.regs[..6].try_into().unwrap()
+        }
+
+        pub fn syscall_return(&self) -> u64 {
+            self.0.regs[0]
+        }
+
+        /// Returns these registers set up for the `SYSCALL` instruction to call fork().
+        pub fn fork_call(&self) -> Registers {
+            let mut call = self.0;
+            call.regs[8] = SYS_CLONE;
+            // flags (just the signal sent to the parent on exit), stack, parent tid, tls and
+            // child tid
+            call.regs[..5].copy_from_slice(&[SIGCHLD, 0, 0, 0, 0]);
+            Registers(call)
+        }
+
+        /// The registers the terminal UI shows, by name.
+        pub fn named(&self) -> Vec<(&'static str, u64)> {
+            let mut named: Vec<(&'static str, u64)> = GDB_REGISTERS[..31].iter().map(|(name, _)| *name).zip(self.0.regs).collect();
+            named.extend([("sp", self.0.sp), ("pc", self.0.pc), ("cpsr", self.0.pstate)]);
+            named
+        }
+
+        /// Returns register `num` of `GDB_REGISTERS`.
+        pub fn gdb_register_mut(&mut self, num: usize) -> Option<&mut u64> {
+            let regs = &mut self.0;
+            Some(match num {
+                0..=30 => &mut regs.regs[num],
+                31 => &mut regs.sp,
+                32 => &mut regs.pc,
+                33 => &mut regs.pstate,
+                _ => return None,
+            })
+        }
+    }
+
+    /// With the pc at a function's first instruction, returns the address the function returns
+    /// to, which the call left in the link register, and the stack pointer once it has
+    /// returned.
+    pub fn return_address_at_entry(_pid: Pid, regs: &Registers) -> Result<(usize, usize), nix::Error> {
+        Ok((regs.0.regs[30] as usize, regs.stack_pointer()))
+    }
+
+    /// Returns a lower bound for the canonical frame address (the caller's stack pointer before
+    /// the call) of a frame with `base_ptr` and `stack_ptr` that has no call frame information.
+    /// The frame record (the saved frame pointer and link register) sits at the low end of the
+    /// frame, with the locals above it, so how far up the frame reaches isn't known; it reaches
+    /// at least past the record, and past the stack pointer.
+    pub fn fallback_cfa(base_ptr: usize, stack_ptr: usize) -> usize {
+        (base_ptr + 16).max(stack_ptr)
+    }
+
+    /// Checks whether the instruction just stepped, which ran with registers `before` and left
+    /// them as `after`, was a call. If so, returns the address it will return to and the stack
+    /// pointer once it has.
+    pub fn called_from(pid: Pid, before: &Registers, after: &Registers) -> Result<Option<(usize, usize)>, nix::Error> {
+        // `bl` and `blr` set the link register to the following instruction, and jump
+        let next = before.pc() + 4;
+        if after.0.regs[30] as usize != next || after.pc() == next || after.stack_pointer() != before.stack_pointer() {
+            return Ok(None);
+        }
+        return_address_at_entry(pid, after).map(Some)
+    }
+
+    /// Reads the low 16 bytes of v0, where floating point values are returned.
+    pub fn float_return_value(pid: Pid) -> Result<[u8; 16], nix::Error> {
+        let fpregs: UserFpsimdState = get_regset(pid, libc::NT_PRFPREG)?;
+        Ok(fpregs.vregs[0].to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_aarch64() {
+        use super::aarch64::{self, Registers};
+        assert_eq!(aarch64::TRAP, [0x00, 0x00, 0x20, 0xd4]);
+        let pid = Pid::from_raw(0);
+        let mut before = Registers::default();
+        before.set_pc(0x1000);
+        *before.gdb_register_mut(31).unwrap() = 0x7ffff000;
+        // bl 0x2000
+        let mut after = before;
+        after.set_pc(0x2000);
+        *after.gdb_register_mut(30).unwrap() = 0x1004;
+        assert_eq!(aarch64::called_from(pid, &before, &after), Ok(Some((0x1004, 0x7ffff000))));
+        // an instruction that isn't a call, run with the link register still set by one
+        let mut after = before;
+        after.set_pc(0x1004);
+        *after.gdb_register_mut(30).unwrap() = 0x1004;
+        assert_eq!(aarch64::called_from(pid, &before, &after), Ok(None));
+        assert_eq!(after.named()[30], ("x30", 0x1004));
+        assert_eq!(after.named()[32], ("pc", 0x1004));
+        assert!(after.gdb_register_mut(34).is_none());
+        let fork = before.fork_call();
+        assert_eq!((fork.syscall_number(), fork.syscall_arguments()[0]), (220, 17));
+        // the frame record is at the low end of the frame, at or above the stack pointer
+        assert_eq!(aarch64::fallback_cfa(0x7fff0000, 0x7ffefff0), 0x7fff0010);
+    }
+
+    #[test]
+    fn test_gdb_registers() {
+        let mut regs = Registers::default();
+        for num in 0..GDB_REGISTERS.len() {
+            *regs.gdb_register_mut(num).unwrap() = num as u64;
+        }
+        assert!(regs.gdb_register_mut(GDB_REGISTERS.len()).is_none());
+        let pc = GDB_REGISTERS.iter().position(|(name, _)| matches!(*name, "rip" | "pc")).unwrap();
+        assert_eq!(regs.pc(), pc);
+    }
+}
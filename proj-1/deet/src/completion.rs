@@ -0,0 +1,127 @@
+//! Tab completion at the prompt. The first word completes to a command name; the words after it
+//! complete to whatever the command takes: subcommands, locations (function and file names),
+//! variables in scope, system call names or file names.
+
+use crate::debugger_command::{expand_abbreviations, COMMANDS, SUBCOMMANDS};
+use crate::syscalls;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+/// What an argument completes to.
+#[derive(Debug, PartialEq)]
+enum Argument {
+    Subcommand(&'static [&'static str]),
+    Location,
+    Variable,
+    Syscall,
+    File,
+    Nothing,
+}
+
+#[derive(Default)]
+pub struct DeetHelper {
+    /// The functions of the program and of the shared libraries it has loaded.
+    pub functions: Vec<String>,
+    /// The number of shared libraries whose functions are in `functions`.
+    pub libraries: usize,
+    /// The names of the program's source files, which locations may start with.
+    pub files: Vec<String>,
+    /// The variables in scope where the inferior is stopped.
+    pub variables: Vec<String>,
+    /// Commands created with `define`.
+    pub user_commands: Vec<String>,
+    filenames: FilenameCompleter,
+}
+
+impl DeetHelper {
+    /// Returns what the argument after `words` (the command and any arguments before it)
+    /// completes to.
+    fn argument(words: &[&str]) -> Argument {
+        let words = match expand_abbreviations(words) {
+            Ok(words) => words,
+            Err(_) => return Argument::Nothing,
+        };
+        let command = words[0];
+        match (command, words.get(1).copied(), words.len()) {
+            ("break" | "trace-calls", _, 1) => Argument::Location,
+            ("info", Some("scope" | "line" | "address"), 2) => Argument::Location,
+            ("catch", Some("syscall"), _) => Argument::Syscall,
+            ("set", Some("cwd"), 2) | ("save", Some("breakpoints"), 2) => Argument::File,
+            ("print", _, _) => Argument::Variable,
+            ("run" | "source" | "tty", _, _) => Argument::File,
+            (_, _, 1) => match SUBCOMMANDS.iter().find(|(name, _)| *name == command) {
+                Some((_, subcommands)) => Argument::Subcommand(subcommands),
+                None => Argument::Nothing,
+            },
+            _ => Argument::Nothing,
+        }
+    }
+}
+
+impl Completer for DeetHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let word = &line[start..pos];
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let mut names: Vec<String> = Vec::new();
+        if words.is_empty() {
+            names.extend(COMMANDS.iter().map(|command| command.to_string()));
+            names.extend(self.user_commands.iter().cloned());
+        } else {
+            match DeetHelper::argument(&words) {
+                Argument::Subcommand(subcommands) => names.extend(subcommands.iter().map(|name| name.to_string())),
+                Argument::Location => {
+                    names.extend(self.functions.iter().cloned());
+                    names.extend(self.files.iter().map(|file| format!("{}:", file)));
+                }
+                Argument::Variable => names.extend(self.variables.iter().cloned()),
+                Argument::Syscall => names.extend(syscalls::names().map(|name| name.to_string())),
+                Argument::File => return self.filenames.complete(line, pos, ctx),
+                Argument::Nothing => {}
+            }
+        }
+        names.retain(|name| name.starts_with(word));
+        names.sort();
+        names.dedup();
+        let candidates = names
+            .into_iter()
+            .map(|name| Pair {
+                // a file name in a location is followed by the line number or function
+                replacement: if name.ends_with(':') { name.clone() } else { format!("{} ", name) },
+                display: name,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for DeetHelper {
+    type Hint = String;
+}
+
+impl Highlighter for DeetHelper {}
+
+impl Validator for DeetHelper {}
+
+impl Helper for DeetHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_argument() {
+        assert_eq!(DeetHelper::argument(&["b"]), Argument::Location);
+        assert_eq!(DeetHelper::argument(&["i", "scope"]), Argument::Location);
+        assert_eq!(DeetHelper::argument(&["info"]), Argument::Subcommand(SUBCOMMANDS[0].1));
+        assert_eq!(DeetHelper::argument(&["p"]), Argument::Variable);
+        assert_eq!(DeetHelper::argument(&["catch", "sys", "open"]), Argument::Syscall);
+        assert_eq!(DeetHelper::argument(&["sou"]), Argument::File);
+        assert_eq!(DeetHelper::argument(&["continue"]), Argument::Nothing);
+    }
+}
@@ -0,0 +1,98 @@
+//! Line coverage for `deet coverage`. A one-shot breakpoint on every statement address of the
+//! line table marks its line as executed the first time it is hit; the result is written in
+//! the lcov tracefile format that genhtml and most coverage tools read.
+
+use crate::dwarf_data::File;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct Coverage {
+    /// Whether each line of each source file (by path) has been executed.
+    lines: BTreeMap<String, BTreeMap<usize, bool>>,
+    /// The source file and line of each address with a breakpoint.
+    addrs: HashMap<usize, (String, usize)>,
+}
+
+impl Coverage {
+    /// Collects the statement addresses of the units' own source files.
+    pub fn new<'a>(files: impl IntoIterator<Item = &'a File>) -> Coverage {
+        let mut coverage = Coverage::default();
+        for file in files {
+            let path = Path::new(&file.comp_dir).join(&file.name).to_string_lossy().to_string();
+            for line in file.lines.iter().filter(|line| line.number != 0 && file.statements.contains(&line.address)) {
+                coverage.lines.entry(path.clone()).or_default().insert(line.number, false);
+                coverage.addrs.insert(line.address, (path.clone(), line.number));
+            }
+        }
+        coverage
+    }
+
+    /// The addresses to set breakpoints on.
+    pub fn addrs(&self) -> Vec<usize> {
+        self.addrs.keys().copied().collect()
+    }
+
+    /// Marks the line at `addr` as executed. Returns false if `addr` has no breakpoint.
+    pub fn hit(&mut self, addr: usize) -> bool {
+        let (path, number) = match self.addrs.remove(&addr) {
+            Some(line) => line,
+            None => return false,
+        };
+        if let Some(hit) = self.lines.get_mut(&path).and_then(|lines| lines.get_mut(&number)) {
+            *hit = true;
+        }
+        true
+    }
+
+    /// Returns the number of lines executed and the number of lines with code.
+    pub fn summary(&self) -> (usize, usize) {
+        let lines = self.lines.values().flat_map(|lines| lines.values());
+        lines.fold((0, 0), |(hit, total), executed| (hit + *executed as usize, total + 1))
+    }
+
+    /// Formats the coverage as an lcov tracefile.
+    pub fn lcov(&self) -> String {
+        let mut out = String::from("TN:\n");
+        for (path, lines) in &self.lines {
+            let _ = writeln!(out, "SF:{}", path);
+            for (number, hit) in lines {
+                let _ = writeln!(out, "DA:{},{}", number, *hit as usize);
+            }
+            let hit = lines.values().filter(|hit| **hit).count();
+            let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), hit);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::Line;
+
+    #[test]
+    fn test_lcov() {
+        let line = |number, address| Line {
+            file: "a.c".to_string(),
+            number,
+            address,
+        };
+        let file = File {
+            name: "a.c".to_string(),
+            comp_dir: "/src".to_string(),
+            lines: vec![line(3, 0x10), line(4, 0x14), line(4, 0x18), line(6, 0x20), line(7, 0x24)],
+            // the row at 0x24 isn't a statement
+            statements: [0x10, 0x14, 0x18, 0x20].into_iter().collect(),
+            ..File::default()
+        };
+        let mut coverage = Coverage::new([&file]);
+        assert_eq!(coverage.addrs().len(), 4);
+        assert!(coverage.hit(0x18));
+        assert!(coverage.hit(0x10));
+        assert!(!coverage.hit(0x10));
+        assert_eq!(coverage.summary(), (2, 3));
+        assert_eq!(coverage.lcov(), "TN:\nSF:/src/a.c\nDA:3,1\nDA:4,1\nDA:6,0\nLF:3\nLH:2\nend_of_record\n");
+    }
+}
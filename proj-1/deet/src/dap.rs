@@ -0,0 +1,386 @@
+//! A Debug Adapter Protocol server, so that editors such as VS Code can use deet as their
+//! debugger. Messages are JSON bodies preceded by a `Content-Length` header, exchanged over
+//! stdin/stdout (`--dap`) or a TCP connection (`--dap=[host]:port`). Only a single thread is
+//! reported, with id 1.
+
+use crate::debugger::Debugger;
+use crate::debugger_command::{DebuggerCommand, RunArgs};
+use crate::interpreter::{Event, Frame, StopReason};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+const THREAD_ID: u64 = 1;
+
+/// The sending half of the connection. It is shared with the threads forwarding the inferior's
+/// output, so writes are serialized and numbered here.
+struct Connection {
+    writer: Mutex<Box<dyn Write + Send>>,
+    seq: AtomicU64,
+}
+
+impl Connection {
+    fn send(&self, mut message: Value) {
+        message["seq"] = json!(self.seq.fetch_add(1, Ordering::SeqCst) + 1);
+        let body = message.to_string();
+        let mut writer = self.writer.lock().unwrap();
+        // the client going away is noticed when reading its next request
+        let _ = write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = writer.flush();
+    }
+
+    fn send_event(&self, event: &str, body: Value) {
+        self.send(json!({"type": "event", "event": event, "body": body}));
+    }
+
+    fn send_output(&self, category: &str, text: &str) {
+        self.send_event("output", json!({"category": category, "output": text}));
+    }
+}
+
+/// Reads one message. Returns None once the client closes the connection.
+fn read_message(reader: &mut dyn BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let content_length = content_length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+struct Session<'a> {
+    debugger: &'a mut Debugger,
+    connection: Arc<Connection>,
+    /// Arguments for the inferior, from the `launch` request.
+    args: Vec<String>,
+    /// Breakpoints set through `setBreakpoints`, by source path.
+    source_breakpoints: HashMap<String, Vec<usize>>,
+    function_breakpoints: Vec<usize>,
+    /// The frames returned by the last `stackTrace`, which frame ids and variable references
+    /// refer to. Cleared whenever the inferior resumes.
+    frames: Vec<Frame>,
+    running: bool,
+}
+
+/// Serves DAP requests from stdin/stdout, or from the first client to connect to `listen_addr`.
+pub fn serve(debugger: &mut Debugger, listen_addr: Option<&str>) -> io::Result<()> {
+    let (mut reader, writer): (Box<dyn BufRead>, Box<dyn Write + Send>) = match listen_addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            eprintln!("Listening for a DAP client on {}", listener.local_addr()?);
+            let (stream, _) = listener.accept()?;
+            (Box::new(BufReader::new(stream.try_clone()?)), Box::new(stream))
+        }
+        None => {
+            debugger.set_stdin_reserved();
+            (Box::new(BufReader::new(io::stdin())), Box::new(io::stdout()))
+        }
+    };
+    let connection = Arc::new(Connection {
+        writer: Mutex::new(writer),
+        seq: AtomicU64::new(0),
+    });
+    let output_connection = connection.clone();
+    debugger.set_target_output_handler(Arc::new(move |stream: &str, text: &str| {
+        output_connection.send_output(stream, text)
+    }));
+
+    let mut session = Session {
+        debugger,
+        connection,
+        args: Vec::new(),
+        source_breakpoints: HashMap::new(),
+        function_breakpoints: Vec::new(),
+        frames: Vec::new(),
+        running: true,
+    };
+    while session.running {
+        let request = match read_message(&mut reader)? {
+            Some(request) => request,
+            None => break,
+        };
+        session.handle(&request);
+    }
+    session.debugger.execute(DebuggerCommand::Quit);
+    Ok(())
+}
+
+impl Session<'_> {
+    fn handle(&mut self, request: &Value) {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => {
+                self.args = arguments["args"]
+                    .as_array()
+                    .map(|args| args.iter().filter_map(|arg| arg.as_str().map(String::from)).collect())
+                    .unwrap_or_default();
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(arguments)),
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(arguments),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" | "disconnect"
+            | "terminate" => Ok(Value::Null),
+            _ => Err(format!("Unsupported request \"{}\"", command)),
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.connection.send(response);
+
+        // requests that resume the inferior are acknowledged first, then report how it stopped
+        let resume = match command {
+            "initialize" => {
+                self.connection.send_event("initialized", json!({}));
+                // report what the startup command files printed
+                self.flush_events(false);
+                None
+            }
+            "configurationDone" => Some(DebuggerCommand::Run(RunArgs {
+                args: self.args.clone(),
+                ..RunArgs::default()
+            })),
+            "continue" => Some(DebuggerCommand::Continue),
+            "next" => Some(DebuggerCommand::Next),
+            "stepIn" => Some(DebuggerCommand::Step),
+            "stepOut" => Some(DebuggerCommand::Finish),
+            "disconnect" | "terminate" => {
+                self.running = false;
+                None
+            }
+            _ => None,
+        };
+        if let Some(resume) = resume {
+            self.frames.clear();
+            self.debugger.execute(resume);
+            self.flush_events(false);
+        }
+    }
+
+    /// Sends the events the debugger emitted as DAP events. Stops and exits become `stopped`,
+    /// `exited` and `terminated` events; everything else is console output, or is returned
+    /// instead if `collect` is set.
+    fn flush_events(&mut self, collect: bool) -> String {
+        let mut collected = Vec::new();
+        for event in self.debugger.take_events() {
+            match event {
+                Event::Stopped { signal, reason, .. } => {
+                    self.frames.clear();
+                    let mut body = json!({"threadId": THREAD_ID, "allThreadsStopped": true});
+                    match reason {
+                        StopReason::Breakpoint(num) => {
+                            body["reason"] = json!("breakpoint");
+                            body["hitBreakpointIds"] = json!([num]);
+                        }
+                        StopReason::Step => body["reason"] = json!("step"),
+                        StopReason::Catchpoint(num) => {
+                            body["reason"] = json!("breakpoint");
+                            body["hitBreakpointIds"] = json!([num]);
+                        }
+                        StopReason::Signal => {
+                            body["reason"] = json!("exception");
+                            body["description"] = json!(format!("Signal {}", signal));
+                        }
+                    }
+                    self.connection.send_event("stopped", body);
+                }
+                Event::Exited(code) => self.send_exit(code),
+                Event::Signaled(signal) => self.send_exit(128 + signal as i32),
+                event if collect => collected.push(event.to_string()),
+                event => self.connection.send_output("console", &format!("{}\n", event)),
+            }
+        }
+        collected.join("\n")
+    }
+
+    fn send_exit(&mut self, code: i32) {
+        self.frames.clear();
+        self.connection.send_event("exited", json!({"exitCode": code}));
+        self.connection.send_event("terminated", json!({}));
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"].as_str().unwrap_or_default().to_string();
+        for num in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            self.debugger.delete_breakpoint(num);
+        }
+        let mut nums = Vec::new();
+        let mut breakpoints = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or_default();
+            match self.debugger.set_breakpoint(&format!("{}:{}", path, line)) {
+                Some((num, Some(addr))) => {
+                    nums.push(num);
+                    // the breakpoint may have moved to the next line that has code
+                    let actual_line = self
+                        .debugger
+                        .debug_data()
+                        .get_line_from_addr(addr)
+                        .map_or(line as usize, |line| line.number);
+                    breakpoints.push(json!({"id": num, "verified": true, "line": actual_line}));
+                }
+                _ => breakpoints.push(json!({"verified": false, "line": line})),
+            }
+        }
+        self.debugger.take_events();
+        self.source_breakpoints.insert(path, nums);
+        json!({"breakpoints": breakpoints})
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Value {
+        for num in self.function_breakpoints.drain(..) {
+            self.debugger.delete_breakpoint(num);
+        }
+        let mut breakpoints = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let name = requested["name"].as_str().unwrap_or_default();
+            match self.debugger.set_breakpoint(name) {
+                // a breakpoint pending on a shared library is verified once it is loaded
+                Some((num, addr)) => {
+                    self.function_breakpoints.push(num);
+                    breakpoints.push(json!({"id": num, "verified": addr.is_some()}));
+                }
+                None => breakpoints.push(json!({"verified": false})),
+            }
+        }
+        self.debugger.take_events();
+        json!({"breakpoints": breakpoints})
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        self.frames = self.debugger.backtrace().map_err(|err| err.to_string())?;
+        let frames: Vec<Value> = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut name = frame.function.clone().unwrap_or_else(|| "??".to_string());
+                if frame.inlined {
+                    name.push_str(" [inlined]");
+                }
+                let mut dap_frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": frame.line.as_ref().map_or(0, |line| line.number),
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#x}", frame.addr),
+                });
+                if let Some(line) = &frame.line {
+                    let name = line.file.rsplit('/').next().unwrap_or(&line.file);
+                    dap_frame["source"] = json!({"name": name, "path": line.file});
+                }
+                dap_frame
+            })
+            .collect();
+        Ok(json!({"stackFrames": frames, "totalFrames": frames.len()}))
+    }
+
+    fn frame(&self, id: &Value) -> Result<&Frame, String> {
+        id.as_u64()
+            .and_then(|id| self.frames.get(id as usize))
+            .ok_or_else(|| format!("Unknown frame {}", id))
+    }
+
+    fn scopes(&self, arguments: &Value) -> Result<Value, String> {
+        let id = arguments["frameId"].as_u64().unwrap_or_default();
+        self.frame(&arguments["frameId"])?;
+        // variable references must be positive, so frame n's locals are reference n + 1
+        Ok(json!({"scopes": [{
+            "name": "Locals",
+            "presentationHint": "locals",
+            "variablesReference": id + 1,
+            "expensive": false,
+        }]}))
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+        let frame = self.frame(&json!(reference.saturating_sub(1)))?;
+        let variables: Vec<Value> = self
+            .debugger
+            .frame_variables(frame)
+            .into_iter()
+            .map(|(name, value)| json!({"name": name, "value": value, "variablesReference": 0}))
+            .collect();
+        Ok(json!({"variables": variables}))
+    }
+
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().unwrap_or_default();
+        if arguments["context"] == "repl" {
+            // the debug console accepts any deet command
+            let commands = expression.lines().map(String::from).collect();
+            if !self.debugger.execute_commands(commands) {
+                self.running = false;
+            }
+            let result = self.flush_events(true);
+            return Ok(json!({"result": result, "variablesReference": 0}));
+        }
+        if self.frames.is_empty() {
+            self.frames = self.debugger.backtrace().map_err(|err| err.to_string())?;
+        }
+        let frame = match arguments.get("frameId") {
+            Some(id) => self.frame(id)?,
+            None => self.frame(&json!(0))?,
+        };
+        match self.debugger.evaluate(expression, frame) {
+            Some(Ok(value)) => Ok(json!({"result": value, "variablesReference": 0})),
+            Some(Err(err)) => Err(err.to_string()),
+            None => Err(format!("No symbol \"{}\" in current context.", expression)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_message() {
+        let input = b"Content-Length: 13\r\n\r\n{\"seq\": 1}   content-length: 2\r\n\r\n{}";
+        let mut reader: &[u8] = input;
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({"seq": 1})));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({})));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut reader: &[u8] = b"\r\n{}";
+        assert!(read_message(&mut reader).is_err());
+    }
+}
@@ -1,33 +1,229 @@
-use crate::debugger_command::DebuggerCommand;
-use crate::inferior::{Inferior, Status};
+use crate::arch::{self, Registers};
+use crate::completion::DeetHelper;
+use crate::coverage::Coverage;
+use crate::debugger_command::{self, DebuggerCommand, RunArgs};
+use crate::inferior::{Inferior, LaunchOptions, Status};
+use crate::interpreter::{self, Event, Frame, Interpreter, OutputHandler, StopReason};
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::{CompletionType, Config, Editor};
 use rustyline::history::FileHistory;
 use nix::sys::ptrace;
+use nix::sys::signal::{self, Signal};
 // debugging symbols
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use std::collections::HashMap;
+use crate::dwarf_data::{DwarfData, Encoding, Error as DwarfError, FrameBase, Function, Location, Type, TypeKind, Variable};
+use crate::symbols::{Symbol, SymbolTable};
+use crate::syscalls;
+use crate::heap::{self, HeapTracker, InvalidFree, Site};
+use crate::profile::Profile;
+use crate::session;
+use crate::tui::{self, Tui};
+use crate::value_format::ValueFormatter;
+use std::cell::RefCell;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Clone)]
 pub struct Breakpoint {
     pub addr: usize,
-    pub orig_byte: u8,
+    /// The bytes of the instruction the trap replaced.
+    pub orig_bytes: [u8; arch::TRAP.len()],
 }
 
+impl Breakpoint {
+    /// Puts the replaced bytes back into `bytes`, a copy of the inferior's memory starting at
+    /// `start`.
+    pub fn restore_in(&self, start: usize, bytes: &mut [u8]) {
+        for (i, orig_byte) in self.orig_bytes.iter().enumerate() {
+            if let Some(byte) = (self.addr + i).checked_sub(start).and_then(|offset| bytes.get_mut(offset)) {
+                *byte = *orig_byte;
+            }
+        }
+    }
+}
+
+/// A breakpoint set with `break`. Its traps are installed through `Debugger::breakpoints`, and
+/// may be shared by several user breakpoints at the same address.
+pub struct UserBreakpoint {
+    pub num: usize,
+    /// What the breakpoint was set on, as given to `break`.
+    pub location: String,
+    /// A breakpoint on a function also stops in every copy of it inlined into other functions.
+    /// It is empty while the breakpoint is pending on a shared library that isn't loaded yet.
+    pub addrs: Vec<usize>,
+    /// Commands run automatically each time the breakpoint is hit.
+    pub commands: Vec<String>,
+    /// Whether the breakpoint is kept in the session. Breakpoints set by command files aren't,
+    /// since the files set them again on the next run.
+    pub saved: bool,
+}
+
+/// Where a command line came from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Typed by the user or sent by a front end, or run on behalf of such a command (e.g. a
+    /// user-defined command's body).
+    User,
+    /// Read from a `-x` file, `~/.deetinit` or `source`.
+    Script,
+    /// Restored from the session file.
+    Session,
+}
+
+/// A catchpoint set with `catch syscall`, which stops the inferior on entry to and on return
+/// from the listed system calls.
+pub struct Catchpoint {
+    pub num: usize,
+    /// The system call numbers caught. Empty catches every system call.
+    pub syscalls: Vec<u64>,
+}
+
+/// A paused copy of the inferior made with `checkpoint`, which `restart` goes back to.
+struct Checkpoint {
+    num: usize,
+    process: Inferior,
+    /// The breakpoints planted in the copy's memory when it was made.
+    breakpoints: HashMap<usize, Option<Breakpoint>>,
+    /// Where the copy is stopped.
+    frame: Frame,
+}
+
+/// The state of `trace-calls`, which logs calls to the functions matching a pattern as a tree,
+/// without stopping. Breakpoints on the functions' entry points note each call, and ones on the
+/// return addresses of the calls in progress note their return.
+struct CallTrace {
+    /// The glob the functions were chosen with.
+    pattern: String,
+    functions: Vec<Function>,
+    /// The address each function's body starts at, where its parameters can be read.
+    bodies: Vec<usize>,
+    /// The return addresses breakpoints have been set on, which stay set until tracing stops.
+    return_addrs: HashSet<usize>,
+    /// The calls that haven't returned yet, innermost last.
+    calls: Vec<TracedCall>,
+}
+
+struct TracedCall {
+    /// The index of the function in `CallTrace::functions`.
+    function: usize,
+    return_addr: usize,
+    /// The stack pointer once the call has returned, which tells recursive calls apart.
+    stack_ptr: usize,
+    /// Where the call returns a large structure, if that is known.
+    indirect_result: Option<usize>,
+    start: Instant,
+    /// Set once the call has been logged, which waits until its parameters can be read.
+    logged: bool,
+}
+
+impl CallTrace {
+    fn is_trace_point(&self, addr: usize) -> bool {
+        self.return_addrs.contains(&addr)
+            || self.bodies.contains(&addr)
+            || self.functions.iter().any(|func| func.address == addr)
+    }
+}
+
+/// The state of `leak-check`: breakpoints on the allocation functions, and on the return
+/// addresses of the calls to them, feed a HeapTracker.
+#[derive(Default)]
+struct HeapCheck {
+    tracker: HeapTracker,
+    /// The allocation functions by address, once the C library is loaded.
+    entries: HashMap<usize, &'static str>,
+    /// The return addresses breakpoints have been set on, which stay set until tracking stops.
+    return_addrs: HashSet<usize>,
+    /// The allocation call in progress. Calls the allocation functions make themselves aren't
+    /// tracked.
+    pending: Option<PendingAllocation>,
+    /// Set if tracking started with the program, so that every allocation has been seen.
+    complete: bool,
+}
+
+struct PendingAllocation {
+    function: &'static str,
+    size: usize,
+    /// The pointer passed to `realloc`.
+    old_ptr: usize,
+    return_addr: usize,
+    /// The stack pointer once the call has returned.
+    stack_ptr: usize,
+    site: Site,
+}
+
+impl HeapCheck {
+    fn is_trap(&self, addr: usize) -> bool {
+        self.entries.contains_key(&addr) || self.return_addrs.contains(&addr)
+    }
+}
+
+/// A function in an inline chain, with the file and line number it is at.
+type InlineFrame = (Option<String>, Option<(String, usize)>);
 
 pub struct Debugger {
     target: String,
     history_path: String,
-    readline: Editor<(), FileHistory>,
+    readline: Editor<DeetHelper, FileHistory>,
+    /// The last line the user typed if it was `step` or `next`, which an empty line repeats.
+    repeat_line: Option<String>,
     inferior: Option<Inferior>,
     debug_data: DwarfData,
+    /// When the target was last modified as of reading `debug_data`, which is read again if the
+    /// target is rebuilt.
+    target_modified: Option<SystemTime>,
+    /// The symbols of the shared libraries the inferior has loaded.
+    library_symbols: Vec<SymbolTable>,
     breakpoints: HashMap<usize, Option<Breakpoint>>,
+    user_breakpoints: Vec<UserBreakpoint>,
+    next_breakpoint_num: usize,
+    /// Catchpoints are numbered along with the breakpoints.
+    catchpoints: Vec<Catchpoint>,
+    /// Set by `trace syscalls`, which logs every system call like strace without stopping.
+    trace_syscalls: bool,
+    /// Commands created with `define`, mapped to the lines of their body.
+    user_commands: HashMap<String, Vec<String>>,
+    /// Command lines read from `-x` files, `~/.deetinit` or `source`, which are run before
+    /// prompting the user for more input, with where they came from.
+    pending_commands: VecDeque<(String, Origin)>,
+    /// Where the command being run came from.
+    origin: Origin,
+    /// In batch mode deet exits once the pending commands run out instead of prompting.
+    batch: bool,
+    exit_status: i32,
+    interpreter: Interpreter,
+    /// The id of the JSON request currently being executed, echoed in every record it produces.
+    request_id: Option<serde_json::Value>,
+    /// Events emitted in DAP mode, waiting to be picked up by the DAP server.
+    events: RefCell<Vec<Event>>,
+    /// Receives the inferior's stdout and stderr. If unset, the inferior writes straight to
+    /// deet's own stdout and stderr.
+    target_output: Option<OutputHandler>,
+    /// Threads forwarding the inferior's output to `target_output`.
+    output_forwarders: Vec<JoinHandle<()>>,
+    /// The terminal, environment and directory set for the inferior, which apply to every run.
+    launch: LaunchOptions,
+    /// Copies of the inferior to go back to, which last until the program is run again.
+    checkpoints: Vec<Checkpoint>,
+    next_checkpoint_num: usize,
+    /// Set while the terminal UI is on, which is redrawn before each prompt.
+    tui: Option<Tui>,
+    /// Set by `trace-calls`.
+    call_trace: Option<CallTrace>,
+    /// Set by `leak-check`.
+    heap_check: Option<HeapCheck>,
+    /// The target's key in the session file, once its session has been loaded. The session is
+    /// saved under it when deet exits.
+    session: Option<String>,
 }
 
 impl Debugger {
     /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
-        // TODO (milestone 3): initialize the DwarfData
+    pub fn new(target: &str, interpreter: Interpreter) -> Debugger {
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
             Err(DwarfError::ErrorOpeningFile) => {
@@ -39,198 +235,2446 @@ impl Debugger {
                 std::process::exit(1);
             }
         };
-        debug_data.print();
 
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<(), FileHistory>::new().expect("Create Editor fail");
+        // like gdb, tab completes as far as the candidates agree and lists them when pressed again
+        let config = Config::builder().completion_type(CompletionType::List).build();
+        let mut readline = Editor::<DeetHelper, FileHistory>::with_config(config).expect("Create Editor fail");
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
+        let mut helper = DeetHelper::default();
+        helper.files = file_names(&debug_data);
+        helper.functions = function_names(debug_data.symbols());
+        readline.set_helper(Some(helper));
+
+        let target_output: Option<OutputHandler> = match interpreter {
+            Interpreter::Json => Some(Arc::new(interpreter::print_target_output_record)),
+            _ => None,
+        };
 
         Debugger {
             target: target.to_string(),
             history_path,
             readline,
+            repeat_line: None,
             inferior: None,
             debug_data,
+            target_modified: fs::metadata(target).and_then(|metadata| metadata.modified()).ok(),
+            library_symbols: Vec::new(),
             breakpoints: HashMap::new(),
+            user_breakpoints: Vec::new(),
+            next_breakpoint_num: 0,
+            catchpoints: Vec::new(),
+            trace_syscalls: false,
+            user_commands: HashMap::new(),
+            pending_commands: VecDeque::new(),
+            origin: Origin::User,
+            batch: false,
+            exit_status: 0,
+            interpreter,
+            request_id: None,
+            events: RefCell::new(Vec::new()),
+            target_output,
+            output_forwarders: Vec::new(),
+            // JSON requests come in on stdin
+            launch: LaunchOptions { null_stdin: interpreter == Interpreter::Json, ..LaunchOptions::default() },
+            checkpoints: Vec::new(),
+            next_checkpoint_num: 1,
+            tui: None,
+            call_trace: None,
+            heap_check: None,
+            session: None,
+        }
+    }
+
+    /// Reports an event through the selected interpreter.
+    fn emit(&self, event: Event) {
+        match self.interpreter {
+            Interpreter::Console => println!("{}", event),
+            Interpreter::Json => {
+                let mut record = event.to_json();
+                if let Some(id) = &self.request_id {
+                    record["id"] = id.clone();
+                }
+                println!("{}", record);
+            }
+            Interpreter::Dap => self.events.borrow_mut().push(event),
+        }
+    }
+
+    /// Returns the events emitted since the last call (DAP mode only).
+    pub fn take_events(&self) -> Vec<Event> {
+        self.events.replace(Vec::new())
+    }
+
+    /// Sends the output of inferiors started from now on to `handler` instead of deet's stdout.
+    pub fn set_target_output_handler(&mut self, handler: OutputHandler) {
+        self.target_output = Some(handler);
+    }
+
+    /// Keeps inferiors started from now on off deet's stdin, which carries a client's requests.
+    pub fn set_stdin_reserved(&mut self) {
+        self.launch.null_stdin = true;
+    }
+
+    pub fn debug_data(&self) -> &DwarfData {
+        &self.debug_data
+    }
+
+    /// Queues the commands in `path` to run after any commands that are already queued.
+    pub fn source(&mut self, path: &str) -> io::Result<()> {
+        let commands = read_command_file(path)?;
+        self.pending_commands.extend(commands.into_iter().map(|command| (command, Origin::Script)));
+        Ok(())
+    }
+
+    /// Queues the commands in ~/.deetinit, if that file exists.
+    pub fn source_init_file(&mut self) {
+        if let Ok(home) = std::env::var("HOME") {
+            let init_path = format!("{}/.deetinit", home);
+            if fs::metadata(&init_path).is_ok() {
+                if let Err(err) = self.source(&init_path) {
+                    self.emit(Event::Error(format!("Warning: failed to read {}: {}", init_path, err)));
+                }
+            }
+        }
+    }
+
+    /// Queues the commands saved in the project's session file for the target, and saves the
+    /// session there when deet exits.
+    pub fn load_session(&mut self) {
+        let key = session::target_key(&self.target);
+        match session::load(&key) {
+            Ok(commands) => self.pending_commands.extend(commands.into_iter().map(|command| (command, Origin::Session))),
+            Err(err) => self.emit(Event::Error(format!("Warning: failed to read {}: {}", session::SESSION_PATH, err))),
+        }
+        self.session = Some(key);
+    }
+
+    /// Saves the breakpoints and settings to the session file, if the session was loaded.
+    fn save_session(&mut self) {
+        let key = match &self.session {
+            Some(key) => key.clone(),
+            None => return,
+        };
+        let mut commands = self.settings_commands();
+        commands.extend(self.breakpoint_commands(true));
+        if let Err(err) = session::save(&key, &commands) {
+            self.emit(Event::Error(format!("Warning: failed to save {}: {}", session::SESSION_PATH, err)));
+        }
+    }
+
+    /// Returns the commands that recreate the breakpoints and catchpoints, with their command
+    /// lists. For the session, only the breakpoints it keeps are included, each location once.
+    fn breakpoint_commands(&self, session: bool) -> Vec<String> {
+        let mut commands = Vec::new();
+        let mut locations = HashSet::new();
+        for user_breakpoint in &self.user_breakpoints {
+            let location = self.symbolic_location(&user_breakpoint.location);
+            if session && (!user_breakpoint.saved || !locations.insert(location.clone())) {
+                continue;
+            }
+            commands.push(format!("break {}", location));
+            if !user_breakpoint.commands.is_empty() {
+                commands.push("commands".to_string());
+                commands.extend(user_breakpoint.commands.iter().cloned());
+                commands.push("end".to_string());
+            }
+        }
+        for catchpoint in &self.catchpoints {
+            let names = catchpoint.syscalls.iter().map(|number| syscalls::name(*number).map_or_else(|| number.to_string(), str::to_string));
+            commands.push(format!("catch syscall {}", names.collect::<Vec<String>>().join(" ")).trim_end().to_string());
+        }
+        commands
+    }
+
+    /// Returns `location` as the function or `file:line` whose breakpoint goes at the same
+    /// address, if `location` is an address, since an address doesn't survive rebuilding the
+    /// program.
+    fn symbolic_location(&self, location: &str) -> String {
+        let addr = match location.strip_prefix('*').and_then(|addr| usize::from_str_radix(addr.trim_start_matches("0x"), 16).ok()) {
+            Some(addr) => addr,
+            None => return location.to_string(),
+        };
+        if let Some(func) = self.debug_data.get_function_containing(addr) {
+            if self.debug_data.get_addr_after_prologue(None, &func.name) == Some(addr) {
+                return func.name.clone();
+            }
+        }
+        if let Some(line) = self.debug_data.get_line_starting_at(addr) {
+            let file = line.file.rsplit('/').next().unwrap_or(&line.file);
+            if self.debug_data.get_addr_for_line(Some(file), line.number) == Some(addr) {
+                return format!("{}:{}", file, line.number);
+            }
+        }
+        location.to_string()
+    }
+
+    /// Returns the commands that recreate the settings: the inferior's environment, directory
+    /// and terminal, and the tracing that is on.
+    fn settings_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        if let Some(environment) = &self.launch.environment {
+            let original: BTreeMap<String, String> = std::env::vars().collect();
+            if environment.is_empty() {
+                commands.push("unset environment".to_string());
+            } else {
+                commands.extend(original.keys().filter(|name| !environment.contains_key(*name)).map(|name| format!("unset environment {}", name)));
+            }
+            for (name, value) in environment {
+                if original.get(name) != Some(value) {
+                    commands.push(format!("set environment {}={}", name, value));
+                }
+            }
+        }
+        if let Some(cwd) = &self.launch.cwd {
+            commands.push(format!("set cwd {}", cwd));
+        }
+        if let Some(tty) = &self.launch.tty {
+            commands.push(format!("tty {}", tty));
+        }
+        if self.trace_syscalls {
+            commands.push("trace syscalls on".to_string());
+        }
+        if let Some(trace) = &self.call_trace {
+            commands.push(format!("trace-calls {}", trace.pattern));
         }
+        if self.heap_check.is_some() {
+            commands.push("leak-check on".to_string());
+        }
+        commands
+    }
+
+    /// Writes the commands that recreate the breakpoints to `path`, for `source` to read.
+    fn save_breakpoints(&mut self, path: &str) {
+        let commands = self.breakpoint_commands(false);
+        let contents: String = commands.iter().map(|command| format!("{}\n", command)).collect();
+        match fs::write(path, contents) {
+            Ok(()) => {
+                let count = self.user_breakpoints.len() + self.catchpoints.len();
+                let noun = if count == 1 { "breakpoint" } else { "breakpoints" };
+                self.emit(Event::Message(format!("Saved {} {} to {}.", count, noun, path)));
+            }
+            Err(err) => self.emit(Event::Error(format!("{}: {}", path, err))),
+        }
+    }
+
+    pub fn set_batch(&mut self, batch: bool) {
+        self.batch = batch;
+    }
+
+    /// Returns the status deet should exit with in batch mode: the exit code of the last
+    /// inferior, or 128 + the signal number if it was killed (or stopped) by a signal.
+    pub fn exit_status(&self) -> i32 {
+        self.exit_status
     }
 
     fn inferior_release_try(&mut self) {
         if let Some(old_inferior) = self.inferior.as_mut() {
+            let pid = old_inferior.pid();
             old_inferior.kill();
+            self.emit(Event::Message(format!("Killing running inferior (pid {})", pid)));
+        }
+        self.inferior = None;
+        for mut checkpoint in self.checkpoints.drain(..) {
+            checkpoint.process.kill();
         }
     }
 
-    fn inferior_continue_exec(&mut self, breakpoints: &HashMap<usize, Option<Breakpoint>>) {
-        if let Some(inferior) = self.inferior.as_mut() {
-            match inferior.continue_exec(breakpoints) {
-                Ok(status) => match status {
-                    Status::Stopped(sig, ptr) => {
-                        println!("Child stopped (signal {}, address {:#x})", sig, ptr);
-                        let regs = ptrace::getregs(inferior.pid()).unwrap();
-                        let line_t = DwarfData::get_line_from_addr(&self.debug_data, regs.rip as usize).unwrap();
-                        let func_name = DwarfData::get_function_from_addr(&self.debug_data, regs.rip as usize).unwrap();
-                        println!("Stopped at {} ({})", func_name, line_t);
-                    },
-                    Status::Signaled(sig) => println!("Child exited (signal {})", sig),
-                    Status::Exited(ret) => println!("Child exited (status {})", ret),
-                },
-                Err(err) => print!("Child error ({})", err),
+    /// Queues `commands` to run before any other pending input.
+    fn queue_commands_front(&mut self, commands: Vec<String>, origin: Origin) {
+        for command in commands.into_iter().rev() {
+            self.pending_commands.push_front((command, origin));
+        }
+    }
+
+    /// Waits until all output of an inferior that has exited has been forwarded.
+    fn finish_output_forwarding(&mut self) {
+        // checkpoints keep the output pipes open, to be forwarded on for a restarted copy
+        if !self.checkpoints.is_empty() {
+            return;
+        }
+        for forwarder in self.output_forwarders.drain(..) {
+            let _ = forwarder.join();
+        }
+    }
+
+    /// Reports how the inferior stopped. `stepping` is set when the stop ends a step, so that a
+    /// plain SIGTRAP is reported as the end of the step rather than as a signal.
+    fn report_status(&mut self, status: Status, stepping: bool) {
+        match status {
+            Status::Stopped(sig, ptr) => {
+                // reaching a breakpoint's address runs that breakpoint's commands
+                let mut commands: Vec<String> = Vec::new();
+                let mut hit = None;
+                if sig == Signal::SIGTRAP {
+                    for user_breakpoint in &self.user_breakpoints {
+                        if user_breakpoint.addrs.contains(&ptr) {
+                            hit.get_or_insert(user_breakpoint.num);
+                            commands.extend(user_breakpoint.commands.iter().cloned());
+                        }
+                    }
+                }
+                let reason = match hit {
+                    Some(num) => StopReason::Breakpoint(num),
+                    None if stepping && sig == Signal::SIGTRAP => StopReason::Step,
+                    None => StopReason::Signal,
+                };
+                // like gdb, a command list starting with "silent" hides the stop message
+                let silent = commands.first().is_some_and(|cmd| cmd == "silent");
+                if silent {
+                    commands.remove(0);
+                } else {
+                    self.emit(Event::Stopped {
+                        signal: sig,
+                        addr: ptr,
+                        function: self.function_name_at(ptr),
+                        line: DwarfData::get_line_from_addr(&self.debug_data, ptr),
+                        reason,
+                    });
+                }
+                if sig != Signal::SIGTRAP {
+                    self.exit_status = 128 + sig as i32;
+                }
+                self.queue_commands_front(commands, self.origin);
+            },
+            Status::Syscall(exit, ptr) => {
+                let (call, catchpoint) = match self.syscall_stop(exit) {
+                    Ok(stop) => stop,
+                    Err(err) => {
+                        self.emit(Event::Error(format!("Child error ({})", err)));
+                        self.draw_tui();
+                        return;
+                    }
+                };
+                let num = catchpoint.unwrap_or_default();
+                let name = call.split('(').next().unwrap_or_default().to_string();
+                let what = if exit { "returned from" } else { "call to" };
+                self.emit(Event::Message(format!("Catchpoint {} ({} syscall {}), {}", num, what, name, call)));
+                self.emit(Event::Stopped {
+                    signal: Signal::SIGTRAP,
+                    addr: ptr,
+                    function: self.function_name_at(ptr),
+                    line: DwarfData::get_line_from_addr(&self.debug_data, ptr),
+                    reason: StopReason::Catchpoint(num),
+                });
+            },
+            Status::Signaled(sig) => {
+                self.finish_output_forwarding();
+                self.emit(Event::Signaled(sig));
+                self.exit_status = 128 + sig as i32;
+                self.inferior = None;
+                self.report_leaks();
+            },
+            Status::Exited(ret) => {
+                self.finish_output_forwarding();
+                self.emit(Event::Exited(ret));
+                self.exit_status = ret;
+                self.inferior = None;
+                self.report_leaks();
+            },
+        }
+        // here rather than before the prompt, so that stops in scripts and breakpoint command
+        // lists are shown too
+        self.draw_tui();
+    }
+
+    fn report_error(&mut self, context: &str, result: Result<(), nix::Error>) {
+        if let Err(err) = result {
+            self.emit(Event::Error(format!("{} error ({})", context, err)));
+        }
+    }
+
+    fn start_inferior(&mut self, run: &RunArgs) {
+        match self.spawn_inferior(run) {
+            Ok(None) => self.inferior_continue_exec(),
+            Ok(Some(status)) => self.report_status(status, false),
+            Err(err) => {
+                self.emit(Event::Error(err));
+                self.draw_tui();
             }
-        } else {
-            println!("No inferior found");
         }
     }
 
-    fn print_backtrace(&mut self) -> Result<(), nix::Error> {
-        // Starter code: Ok(println!("Hello world"))
-        if let Some(inferior) = self.inferior.as_mut() {
-            let regs = ptrace::getregs(inferior.pid()).unwrap();
-            let mut instruction_ptr = regs.rip;
-            let mut base_ptr = regs.rbp;
-            loop {
-                let line_t = DwarfData::get_line_from_addr(&self.debug_data, instruction_ptr as usize).unwrap();
-                let func_name = DwarfData::get_function_from_addr(&self.debug_data, instruction_ptr as usize).unwrap();
-                println!("{} ({})", func_name, line_t);
-                if func_name == "main".to_string() {
-                    break;
-                }
-                instruction_ptr = ptrace::read(inferior.pid(), (base_ptr + 8) as ptrace::AddressType)? as u64;
-                base_ptr = ptrace::read(inferior.pid(), base_ptr as ptrace::AddressType)? as u64;
-            }
-            Ok(())
-        } else {
-            Err(nix::Error::ECHILD)
+    /// Starts a new inferior and runs it to the program's entry point (see
+    /// `load_shared_libraries`). Returns the status to report if it stopped before getting
+    /// there, or an error message if it couldn't be started.
+    fn spawn_inferior(&mut self, run: &RunArgs) -> Result<Option<Status>, String> {
+        // kill the previous inferior if it exists
+        self.inferior_release_try();
+        // a status left from an earlier inferior isn't this one's
+        self.exit_status = 0;
+        self.reload_if_rebuilt();
+        self.reset_call_trace();
+        self.reset_heap_check();
+
+        // Create new inferior
+        let launch = LaunchOptions {
+            stdin: run.stdin.clone(),
+            stdout: run.stdout.clone(),
+            stderr: run.stderr.clone(),
+            capture_output: self.target_output.is_some(),
+            ..self.launch.clone()
+        };
+        let mut inferior = Inferior::new(&self.target, &run.args, &launch)?;
+        if inferior.address_randomized().unwrap_or(false) {
+            self.emit(Event::Error("Warning: Error disabling address space randomization; the program's addresses change from run to run.".to_string()));
+        }
+        match inferior.entry_point() {
+            Ok(entry_point) => self.relocate(entry_point),
+            Err(err) => self.emit(Event::Error(format!("Warning: cannot tell where the program was loaded ({}).", err))),
+        }
+        inferior.insert_breakpoints(&mut self.breakpoints);
+        let mut unset: Vec<usize> = self.breakpoints.iter().filter(|(_, bp)| bp.is_none()).map(|(addr, _)| *addr).collect();
+        unset.sort_unstable();
+        for addr in unset {
+            self.emit(Event::Error(format!("Cannot insert breakpoint at {:#x}", addr)));
         }
+        if let Some(handler) = &self.target_output {
+            if let Some(stdout) = inferior.take_stdout() {
+                self.output_forwarders.push(interpreter::forward_target_output("stdout", stdout, handler.clone()));
+            }
+            if let Some(stderr) = inferior.take_stderr() {
+                self.output_forwarders.push(interpreter::forward_target_output("stderr", stderr, handler.clone()));
+            }
+        }
+        // Create the inferior
+        self.inferior = Some(inferior);
+        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
+        // to the Inferior object
+        // system calls the dynamic linker makes before the program starts aren't caught
+        let loaded = self.load_shared_libraries();
+        self.update_syscall_tracing();
+        loaded.map_err(|err| format!("Child error ({})", err))
     }
 
-    pub fn parse_address(&mut self, addr: &str) -> Option<usize> {
-        let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
-            &addr[2..]
-        } else {
-            &addr
+    /// Reads the debugging information again if the target has been rebuilt since it was read,
+    /// and resolves the breakpoints again against it.
+    fn reload_if_rebuilt(&mut self) {
+        let modified = fs::metadata(&self.target).and_then(|metadata| metadata.modified()).ok();
+        if modified == self.target_modified {
+            return;
+        }
+        self.target_modified = modified;
+        let debug_data = match DwarfData::from_file(&self.target) {
+            Ok(debug_data) => debug_data,
+            Err(_) => {
+                self.emit(Event::Error(format!("Warning: `{}' has changed but can't be read; keeping its old symbols.", self.target)));
+                return;
+            }
         };
-        usize::from_str_radix(addr_without_0x, 16).ok()
+        // a file that was only touched or copied keeps its build ID
+        if debug_data.build_id().is_some() && debug_data.build_id() == self.debug_data.build_id() {
+            return;
+        }
+        self.emit(Event::Message(format!("`{}' has changed; re-reading symbols.", self.target)));
+        self.debug_data = debug_data;
+        self.library_symbols.clear();
+        if let Some(helper) = self.readline.helper_mut() {
+            helper.files = file_names(&self.debug_data);
+            helper.functions = function_names(self.debug_data.symbols());
+            helper.libraries = 0;
+        }
+        self.resolve_breakpoints_again();
     }
 
-    pub fn run(&mut self) {
-        loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    // kill the previous inferior if it exists
-                    self.inferior_release_try();
+    /// Reads the debugging information again if the program wasn't loaded where it was assumed
+    /// to be (its entry point is at `entry_point` instead), e.g. because address randomization
+    /// is on, and resolves the breakpoints again against it.
+    fn relocate(&mut self, entry_point: usize) {
+        let link_entry_point = self.debug_data.entry_point() - self.debug_data.load_bias();
+        let load_bias = entry_point.wrapping_sub(link_entry_point);
+        if load_bias == self.debug_data.load_bias() {
+            return;
+        }
+        match DwarfData::from_file_loaded_at(&self.target, Some(load_bias)) {
+            Ok(debug_data) => self.debug_data = debug_data,
+            Err(_) => {
+                self.emit(Event::Error(format!("Warning: `{}' can't be read again where it was loaded; its addresses are wrong.", self.target)));
+                return;
+            }
+        }
+        self.resolve_breakpoints_again();
+    }
 
-                    // Create new inferior
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &mut self.breakpoints) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                        self.inferior_continue_exec(&self.breakpoints.clone());
-                    } else {
-                        println!("Error starting subprocess");
-                    }
+    /// Resolves the breakpoints' locations against new debugging information, warning about
+    /// the ones that no longer resolve. Breakpoints on addresses keep them. There is no inferior,
+    /// so the traps are planted from scratch when the next one starts.
+    fn resolve_breakpoints_again(&mut self) {
+        self.breakpoints.clear();
+        for index in 0..self.user_breakpoints.len() {
+            let location = self.user_breakpoints[index].location.clone();
+            if !location.starts_with('*') {
+                let addrs = self.resolve_location(&location);
+                if addrs.is_empty() {
+                    let num = self.user_breakpoints[index].num;
+                    self.emit(Event::Error(format!("Warning: breakpoint {} (\"{}\") no longer resolves; it is pending.", num, location)));
                 }
-                DebuggerCommand::Quit => {
-                    self.inferior_release_try();
-                    return;
-                },
-                DebuggerCommand::Continue => {
-                    if let Some(_) = self.inferior.as_mut() {
-                        self.inferior_continue_exec(&self.breakpoints.clone());
-                    } else {
-                        // press 'c' before 'r'
-                        println!("No process running error")
+                self.user_breakpoints[index].addrs = addrs;
+            }
+            for &addr in &self.user_breakpoints[index].addrs {
+                self.breakpoints.insert(addr, None);
+            }
+        }
+        // the traced functions may have moved, or be gone
+        if let Some(trace) = self.call_trace.take() {
+            self.trace_calls(Some(trace.pattern));
+        }
+    }
+
+    /// Runs the program with `args` under the sampling profiler, which stops it `hz` times a
+    /// second to unwind its stack. Once it exits, prints the flat profile and writes the stacks
+    /// to `folded_path` for flamegraph tools.
+    pub fn profile(&mut self, args: &[String], hz: u32, folded_path: &str) {
+        let run = RunArgs {
+            args: args.to_vec(),
+            ..RunArgs::default()
+        };
+        match self.spawn_inferior(&run) {
+            Ok(None) => {}
+            Ok(Some(status)) => return self.report_status(status, false),
+            Err(err) => return self.emit(Event::Error(err)),
+        }
+        let pid = match &self.inferior {
+            Some(inferior) => inferior.pid(),
+            None => return,
+        };
+        // a thread stops the inferior with SIGSTOP at each tick, until it has exited
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = {
+            let done = done.clone();
+            let interval = Duration::from_secs(1) / hz.max(1);
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    thread::sleep(interval);
+                    if !done.load(Ordering::SeqCst) {
+                        let _ = signal::kill(pid, Signal::SIGSTOP);
                     }
-                },
-                DebuggerCommand::Backtrace => {
-                    self.print_backtrace().expect("Nothing");
-                },
-                DebuggerCommand::Breakpoint(target_wrapper) => {
-                    if let Some(tg) = target_wrapper {
-                        let addr: Option<usize>;
-                        if tg.starts_with('*') {
-                            addr = self.parse_address(&tg[1..]); 
-                        } else {
-                            // parse as the line number
-                            if tg.chars().into_iter().all(|c| c.is_numeric()) {
-                                let line_number = tg.parse::<usize>().unwrap();
-                                addr = DwarfData::get_addr_for_line(&self.debug_data, None, line_number);
-                            } else {
-                                // treat the target as the function name
-                                let func_name = &tg;
-                                addr = DwarfData::get_addr_for_function(&self.debug_data, None, func_name);
-                            }
-                        }
-                        
-                        if let Some(parsed_addr) = addr {
-                            // parse the addr string which has no prefix symbol '*'
-                            // set breakpoints if inferior exists
-                            if let Some(inferior) = self.inferior.as_mut() {
-                                match inferior.write_byte(parsed_addr, 0xcc as u8) {
-                                    Ok(orig_byte) => {
-                                        self.breakpoints.insert(
-                                            parsed_addr, 
-                                            Some(Breakpoint{
-                                                addr: parsed_addr,
-                                                orig_byte: orig_byte
-                                            }));
-                                    },
-                                    Err(err) => println!("Inferior::write_byte for breakpoint error {}", err),
-                                }
-                            } else {
-                                self.breakpoints.insert(parsed_addr, None);
-                            }
-                            println!("Set breakpoint {} at {:#x}", self.breakpoints.len() - 1, parsed_addr);
-                        } else {
-                            println!("fail to parse a usize from a hexadecimal string");
-                            return;
-                        }
+                }
+            })
+        };
+        let mut profile = Profile::default();
+        let mut signal = None;
+        let status = loop {
+            let inferior = match self.inferior.as_mut() {
+                Some(inferior) => inferior,
+                None => break Err(nix::Error::ECHILD),
+            };
+            let status = match inferior.resume(&self.breakpoints, false, signal.take()) {
+                Ok(status) => status,
+                Err(err) => break Err(err),
+            };
+            match status {
+                Status::Stopped(Signal::SIGSTOP, _) => {
+                    if let Ok(frames) = self.backtrace() {
+                        profile.add_sample(&frames);
                     }
                 }
+                // other signals are the program's own, and passed on to it
+                Status::Stopped(sig, _) => signal = Some(sig),
+                Status::Syscall(..) => {}
+                Status::Exited(_) | Status::Signaled(_) => break Ok(status),
             }
+        };
+        done.store(true, Ordering::SeqCst);
+        let _ = ticker.join();
+        match status {
+            Ok(status) => self.report_status(status, false),
+            Err(err) => self.emit(Event::Error(format!("Child error ({})", err))),
+        }
+        println!("\n{} samples at {} Hz", profile.samples(), hz);
+        print!("{}", profile.report());
+        match fs::write(folded_path, profile.folded()) {
+            Ok(()) => println!("\nFolded stacks written to {}", folded_path),
+            Err(err) => println!("\n{}: {}", folded_path, err),
         }
     }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
-    fn get_next_command(&mut self) -> DebuggerCommand {
-        loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
-                Err(ReadlineError::Interrupted) => {
-                    // User pressed ctrl+c. We're going to ignore it
-                    println!("Type \"quit\" to exit");
-                }
-                Err(ReadlineError::Eof) => {
-                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
-                    return DebuggerCommand::Quit;
+    /// Runs the program to completion with a one-shot breakpoint on every statement in its line
+    /// tables, and writes the lines that were and weren't executed to `lcov_path`.
+    pub fn coverage(&mut self, args: &[String], lcov_path: &str) {
+        let mut coverage = Coverage::new(self.debug_data.all_units().into_iter().map(|(file, _)| file));
+        let run = RunArgs {
+            args: args.to_vec(),
+            ..RunArgs::default()
+        };
+        match self.spawn_inferior(&run) {
+            Ok(None) => {}
+            Ok(Some(status)) => return self.report_status(status, false),
+            Err(err) => return self.emit(Event::Error(err)),
+        }
+        if !self.install_breakpoints(&coverage.addrs()) {
+            return;
+        }
+        let mut signal = None;
+        let status = loop {
+            let inferior = match self.inferior.as_mut() {
+                Some(inferior) => inferior,
+                None => break Err(nix::Error::ECHILD),
+            };
+            let status = match inferior.resume(&self.breakpoints, false, signal.take()) {
+                Ok(status) => status,
+                Err(err) => break Err(err),
+            };
+            match status {
+                Status::Stopped(Signal::SIGTRAP, pc) if coverage.hit(pc) => self.remove_trap(pc),
+                // other signals are the program's own, and passed on to it
+                Status::Stopped(sig, _) => signal = Some(sig),
+                Status::Syscall(..) => {}
+                Status::Exited(_) | Status::Signaled(_) => break Ok(status),
+            }
+        };
+        match status {
+            Ok(status) => self.report_status(status, false),
+            Err(err) => self.emit(Event::Error(format!("Child error ({})", err))),
+        }
+        let (hit, total) = coverage.summary();
+        println!("\nLines executed: {} of {} ({:.2}%)", hit, total, 100.0 * hit as f64 / total.max(1) as f64);
+        match fs::write(lcov_path, coverage.lcov()) {
+            Ok(()) => println!("Coverage written to {}", lcov_path),
+            Err(err) => println!("{}: {}", lcov_path, err),
+        }
+    }
+
+    /// Runs a new inferior to the program's entry point, by which time the dynamic linker has
+    /// loaded its shared libraries, reads their symbols and sets the breakpoints that were
+    /// pending on them. Returns the status to report if the inferior stopped before getting
+    /// there, or at a user breakpoint on the entry point.
+    fn load_shared_libraries(&mut self) -> Result<Option<Status>, nix::Error> {
+        let entry_point = self.debug_data.entry_point();
+        let status = self.run_to(entry_point, 0)?;
+        if !matches!(status, Status::Stopped(Signal::SIGTRAP, pc) if pc == entry_point) {
+            return Ok(Some(status));
+        }
+        let pid = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?.pid();
+        let maps = fs::read_to_string(format!("/proc/{}/maps", pid)).map_err(|_| nix::Error::EIO)?;
+        let target = fs::canonicalize(&self.target).ok();
+        self.library_symbols.clear();
+        for line in maps.lines() {
+            // start-end perms offset dev inode path
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (range, offset, path) = match fields[..] {
+                [range, _, offset, _, _, path] => (range, offset, path),
+                _ => continue,
+            };
+            let loaded = self.library_symbols.iter().any(|table| table.library.as_deref() == Some(path));
+            if offset.trim_start_matches('0').is_empty()
+                && path.starts_with('/')
+                && target.as_deref() != Some(std::path::Path::new(path))
+                && !loaded
+            {
+                let start = range.split('-').next().and_then(|start| usize::from_str_radix(start, 16).ok());
+                if let Some(table) = start.and_then(|start| SymbolTable::from_library(path, start)) {
+                    self.library_symbols.push(table);
                 }
-                Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+            }
+        }
+        if self.heap_check.is_some() {
+            self.install_heap_breakpoints(true);
+        }
+        let pending: Vec<(usize, String)> = self
+            .user_breakpoints
+            .iter()
+            .filter(|bp| bp.addrs.is_empty())
+            .map(|bp| (bp.num, bp.location.clone()))
+            .collect();
+        for (num, location) in pending {
+            let addrs = self.resolve_location(&location);
+            if !addrs.is_empty() && self.install_breakpoints(&addrs) {
+                if let Some(user_breakpoint) = self.user_breakpoints.iter_mut().find(|bp| bp.num == num) {
+                    user_breakpoint.addrs = addrs.clone();
                 }
-                Ok(line) => {
-                    if line.trim().len() == 0 {
+                self.emit(Event::BreakpointSet { num, addrs });
+            }
+        }
+        match self.is_user_breakpoint(entry_point) {
+            true => Ok(Some(status)),
+            false => Ok(None),
+        }
+    }
+
+    /// Looks up an ELF symbol by name, in the program and then in its shared libraries.
+    fn lookup_symbol(&self, name: &str) -> Option<&Symbol> {
+        std::iter::once(self.debug_data.symbols())
+            .chain(&self.library_symbols)
+            .find_map(|table| table.lookup(name))
+    }
+
+    /// Finds the ELF symbol `addr` is in, the offset of `addr` in it and the shared library
+    /// it is in, if it isn't in the program.
+    fn symbol_containing(&self, addr: usize) -> Option<(&Symbol, usize, Option<&str>)> {
+        std::iter::once(self.debug_data.symbols())
+            .chain(&self.library_symbols)
+            .find_map(|table| {
+                let (symbol, offset) = table.containing(addr)?;
+                Some((symbol, offset, table.library.as_deref()))
+            })
+    }
+
+    /// Names the function at `addr`, from the debugging information or else the symbols.
+    fn function_name_at(&self, addr: usize) -> Option<String> {
+        self.debug_data
+            .get_function_from_addr(addr)
+            .or_else(|| Some(self.symbol_containing(addr)?.0.name.clone()))
+    }
+
+    fn inferior_continue_exec(&mut self) {
+        if self.inferior.is_some() {
+            match self.continue_inferior(None) {
+                Ok(status) => self.report_status(status, false),
+                Err(err) => self.emit(Event::Error(format!("Child error ({})", err))),
+            }
+        } else {
+            self.emit(Event::Error("No inferior found".to_string()));
+        }
+    }
+
+    /// Continues the inferior until it stops for something other than a system call nobody
+    /// asked to stop at or a `trace-calls` or `leak-check` breakpoint. System calls and traced
+    /// calls are logged on the way, and heap allocations recorded. It always stops at `until`,
+    /// even if that is where a traced call returns to.
+    fn continue_inferior(&mut self, until: Option<usize>) -> Result<Status, nix::Error> {
+        loop {
+            let inferior = self.inferior.as_mut().ok_or(nix::Error::ECHILD)?;
+            let pid = inferior.pid();
+            let status = inferior.continue_exec(&self.breakpoints)?;
+            let exit = match status {
+                Status::Syscall(exit, _) => exit,
+                Status::Stopped(Signal::SIGTRAP, pc) if self.call_trace.is_some() || self.heap_check.is_some() => {
+                    let traced = self.trace_call_stop(pc)?;
+                    let heap = self.heap_stop(pc)?;
+                    if (traced || heap) && !self.is_user_breakpoint(pc) && until != Some(pc) {
                         continue;
                     }
-                    self.readline.add_history_entry(line.as_str()).expect("Can not add history entry");
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+                    return Ok(status);
+                }
+                _ => return Ok(status),
+            };
+            let (call, catchpoint) = self.syscall_stop(exit)?;
+            if self.trace_syscalls {
+                let number = Registers::read(pid)?.syscall_number();
+                if exit {
+                    self.emit(Event::Message(call));
+                } else if syscalls::never_returns(number) {
+                    self.emit(Event::Message(format!("{} = ?", call)));
+                }
+            }
+            if catchpoint.is_some() {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Decodes the system call the inferior is stopped at the entry to or (with `exit` set)
+    /// the return from, like `write(1, "hi\n", 3) = 3`. Also returns the number of the first
+    /// catchpoint that catches it.
+    fn syscall_stop(&self, exit: bool) -> Result<(String, Option<usize>), nix::Error> {
+        let inferior = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?;
+        let regs = Registers::read(inferior.pid())?;
+        let number = regs.syscall_number();
+        let read_memory = |addr, len| inferior.read_memory(addr, len);
+        let mut call = syscalls::format_call(number, inferior.syscall_arguments(), &read_memory);
+        if exit {
+            call.push_str(&format!(" = {}", syscalls::format_return(number, regs.syscall_return())));
+        }
+        let catchpoint = self
+            .catchpoints
+            .iter()
+            .find(|catchpoint| catchpoint.syscalls.is_empty() || catchpoint.syscalls.contains(&number))
+            .map(|catchpoint| catchpoint.num);
+        Ok((call, catchpoint))
+    }
+
+    /// Stops the inferior at system calls only while something needs to see them.
+    fn update_syscall_tracing(&mut self) {
+        let trace = self.trace_syscalls || !self.catchpoints.is_empty();
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.trace_syscalls = trace;
+        }
+    }
+
+    /// Sets a catchpoint on the named or numbered system calls, or on all of them.
+    fn catch_syscall(&mut self, names: &[String]) {
+        let mut syscalls = Vec::new();
+        let mut shown = Vec::new();
+        for name in names {
+            let number = match name.parse::<u64>() {
+                Ok(number) => number,
+                Err(_) => match syscalls::number(name) {
+                    Some(number) => number,
+                    None => {
+                        self.emit(Event::Error(format!("Unknown syscall name '{}'.", name)));
+                        return;
+                    }
+                },
+            };
+            syscalls.push(number);
+            shown.push(format!("'{}' [{}]", syscalls::name(number).unwrap_or(name), number));
+        }
+        let num = self.next_breakpoint_num;
+        self.next_breakpoint_num += 1;
+        let message = match shown.len() {
+            0 => format!("Catchpoint {} (any syscall)", num),
+            1 => format!("Catchpoint {} (syscall {})", num, shown[0]),
+            _ => format!("Catchpoint {} (syscalls {})", num, shown.join(" ")),
+        };
+        self.catchpoints.push(Catchpoint { num, syscalls });
+        self.update_syscall_tracing();
+        self.emit(Event::Message(message));
+    }
+
+    /// Continues the inferior until it stops at `addr` with its stack pointer at or above
+    /// `min_stack_ptr` (so that a recursive call reaching `addr` in a deeper frame doesn't count).
+    /// Returns the status the inferior stopped with, which is a stop somewhere else if it hit a
+    /// breakpoint, got a signal or exited first.
+    fn run_to(&mut self, addr: usize, min_stack_ptr: usize) -> Result<Status, nix::Error> {
+        let inferior = self.inferior.as_mut().ok_or(nix::Error::ECHILD)?;
+        // a temporary breakpoint, unless there is one at `addr` already
+        let temporary = !matches!(self.breakpoints.get(&addr), Some(Some(_)));
+        if temporary {
+            let breakpoint = inferior.insert_trap(addr)?;
+            self.breakpoints.insert(addr, Some(breakpoint));
+        }
+        let status = loop {
+            let status = self.continue_inferior(Some(addr))?;
+            let inferior = self.inferior.as_mut().ok_or(nix::Error::ECHILD)?;
+            match status {
+                Status::Stopped(Signal::SIGTRAP, pc) if pc == addr => {
+                    if Registers::read(inferior.pid())?.stack_pointer() >= min_stack_ptr {
+                        break status;
+                    }
+                }
+                _ => break status,
+            }
+        };
+        // calls traced meanwhile may have made it a trace-calls or leak-check breakpoint, which
+        // stays
+        if temporary && !self.is_internal_trap(addr) {
+            if let Some(Some(breakpoint)) = self.breakpoints.remove(&addr) {
+                if let (Some(inferior), Status::Stopped(..) | Status::Syscall(..)) = (self.inferior.as_mut(), status) {
+                    inferior.remove_trap(&breakpoint)?;
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// Steps to the next source line. With `over` set, calls are stepped over; otherwise the
+    /// step stops at the first line of a called function that has debugging information.
+    fn step_line(&mut self, over: bool) -> Result<(), nix::Error> {
+        let inferior = self.inferior.as_mut().ok_or(nix::Error::ECHILD)?;
+        let pid = inferior.pid();
+        let start_pc = Registers::read(pid)?.pc();
+        let start_line = self.debug_data.get_line_from_addr(start_pc);
+        let start_func = self.debug_data.get_function_containing(start_pc).map(|func| func.address);
+        let start_depth = self.debug_data.get_inline_depth(start_pc);
+        loop {
+            let before = Registers::read(pid)?;
+            let status = self.inferior.as_mut().unwrap().step_instruction(&self.breakpoints)?;
+            let pc = match status {
+                Status::Stopped(Signal::SIGTRAP, pc) => pc,
+                _ => {
+                    self.report_status(status, true);
+                    return Ok(());
+                }
+            };
+            let regs = Registers::read(pid)?;
+            if let Some((return_addr, caller_stack_ptr)) = arch::called_from(pid, &before, &regs)? {
+                let callee = self.debug_data.get_function_containing(pc).cloned();
+                match callee {
+                    Some(func) if !over && pc == func.address => {
+                        // skip the callee's prologue so that its variables can be read
+                        let body = self.debug_data.get_prologue_end(&func);
+                        let status = self.run_to(body, 0)?;
+                        self.report_status(status, true);
+                        return Ok(());
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    _ => {
+                        let status = self.run_to(return_addr, caller_stack_ptr)?;
+                        match status {
+                            Status::Stopped(Signal::SIGTRAP, pc) if pc == return_addr => {}
+                            _ => {
+                                self.report_status(status, true);
+                                return Ok(());
+                            }
+                        }
                     }
                 }
             }
+            let pc = Registers::read(pid)?.pc();
+            let func = match self.debug_data.get_function_containing(pc) {
+                Some(func) => func.address,
+                None => {
+                    // we returned into code without debugging information (e.g. out of main),
+                    // so there's no line to stop at
+                    self.inferior_continue_exec();
+                    return Ok(());
+                }
+            };
+            if self.is_user_breakpoint(pc) {
+                self.report_status(Status::Stopped(Signal::SIGTRAP, pc), true);
+                return Ok(());
+            }
+            // `next` steps over inlined calls just like real ones
+            if over && Some(func) == start_func && self.debug_data.get_inline_depth(pc) > start_depth {
+                continue;
+            }
+            if let Some(line) = self.debug_data.get_line_starting_at(pc) {
+                let same_line = start_line.as_ref().is_some_and(|start| {
+                    start.file == line.file && start.number == line.number
+                });
+                if !same_line {
+                    self.report_status(Status::Stopped(Signal::SIGTRAP, pc), true);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn is_user_breakpoint(&self, addr: usize) -> bool {
+        self.user_breakpoints.iter().any(|bp| bp.addrs.contains(&addr))
+    }
+
+    /// Returns the functions executing at `addr`: the inlined calls and the function they were
+    /// inlined into, outermost last. Lines are kept as file and number only, so that chains at
+    /// different addresses can be compared.
+    fn inline_chain(&self, addr: usize) -> Vec<InlineFrame> {
+        self.debug_data
+            .get_frames_from_addr(addr)
+            .into_iter()
+            .map(|(function, line)| (function, line.map(|line| (line.file, line.number))))
+            .collect()
+    }
+
+    /// Runs until the inlined function the inferior is stopped in is left, stepping over calls.
+    fn finish_inline(&mut self) -> Result<(), nix::Error> {
+        let pid = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?.pid();
+        let regs = Registers::read(pid)?;
+        let start_pc = regs.pc();
+        let frame = self.frame_at(start_pc, regs.frame_pointer(), regs.stack_pointer());
+        self.emit(Event::Message(format!("Run till exit from {}", frame)));
+        let start_func = self.debug_data.get_function_containing(start_pc).map(|func| func.address);
+        // the inlined function is left once its callers are no longer the ones it started with
+        let callers = self.inline_chain(start_pc).split_off(1);
+        loop {
+            let before = Registers::read(pid)?;
+            let status = self.inferior.as_mut().unwrap().step_instruction(&self.breakpoints)?;
+            if !matches!(status, Status::Stopped(Signal::SIGTRAP, _)) {
+                self.report_status(status, true);
+                return Ok(());
+            }
+            let regs = Registers::read(pid)?;
+            if let Some((return_addr, caller_stack_ptr)) = arch::called_from(pid, &before, &regs)? {
+                let status = self.run_to(return_addr, caller_stack_ptr)?;
+                if !matches!(status, Status::Stopped(Signal::SIGTRAP, pc) if pc == return_addr) {
+                    self.report_status(status, true);
+                    return Ok(());
+                }
+            }
+            let pc = Registers::read(pid)?.pc();
+            let func = self.debug_data.get_function_containing(pc).map(|func| func.address);
+            let chain = self.inline_chain(pc);
+            let inside = func == start_func
+                && chain.len() > callers.len()
+                && chain[chain.len() - callers.len()..] == callers[..];
+            if !inside || self.is_user_breakpoint(pc) {
+                self.report_status(Status::Stopped(Signal::SIGTRAP, pc), true);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs until the current function returns to its caller.
+    fn finish(&mut self) -> Result<(), nix::Error> {
+        let inferior = self.inferior.as_mut().ok_or(nix::Error::ECHILD)?;
+        let pid = inferior.pid();
+        let regs = Registers::read(pid)?;
+        let pc = regs.pc();
+        if self.debug_data.get_inline_depth(pc) > 0 {
+            return self.finish_inline();
+        }
+        let after_prologue = match self.debug_data.get_function_containing(pc) {
+            Some(func) => pc >= self.debug_data.get_prologue_end(func),
+            None => false,
+        };
+        // once the prologue has run, the return address sits above the saved frame pointer;
+        // before that it is where the call left it
+        let (base_ptr, stack_ptr) = (regs.frame_pointer(), regs.stack_pointer());
+        let (return_addr, caller_stack_ptr) = if after_prologue {
+            let return_addr = ptrace::read(pid, (base_ptr + 8) as ptrace::AddressType)? as usize;
+            (return_addr, self.canonical_frame_address(pc, base_ptr, stack_ptr))
+        } else {
+            arch::return_address_at_entry(pid, &regs)?
+        };
+        let frame = self.frame_at(pc, base_ptr, stack_ptr);
+        self.emit(Event::Message(format!("Run till exit from {}", frame)));
+        let status = self.run_to(return_addr, caller_stack_ptr)?;
+        self.report_status(status, true);
+        Ok(())
+    }
+
+    fn frame_at(&self, addr: usize, base_ptr: usize, stack_ptr: usize) -> Frame {
+        Frame {
+            function: self.function_name_at(addr),
+            line: DwarfData::get_line_from_addr(&self.debug_data, addr),
+            addr,
+            base_ptr,
+            stack_ptr,
+            inlined: self.debug_data.get_inline_depth(addr) > 0,
+        }
+    }
+
+    /// Returns the canonical frame address of the function executing at `addr` in the frame
+    /// with `base_ptr` and `stack_ptr`: the caller's stack pointer before the call. Without call
+    /// frame information for `addr`, it is guessed from the frame layout of the processor, which
+    /// on some only bounds it from below.
+    fn canonical_frame_address(&self, addr: usize, base_ptr: usize, stack_ptr: usize) -> usize {
+        match self.debug_data.get_cfa_rule(addr) {
+            Some((arch::DWARF_FRAME_POINTER, offset)) => base_ptr.wrapping_add_signed(offset as isize),
+            Some((arch::DWARF_STACK_POINTER, offset)) => stack_ptr.wrapping_add_signed(offset as isize),
+            _ => arch::fallback_cfa(base_ptr, stack_ptr),
+        }
+    }
+
+    /// Returns the frames executing at `addr` in the stack frame at `base_ptr`: a virtual frame
+    /// for each function inlined there, followed by the function they were inlined into.
+    fn frames_at(&self, addr: usize, base_ptr: usize, stack_ptr: usize) -> Vec<Frame> {
+        let chain = self.debug_data.get_frames_from_addr(addr);
+        if chain.is_empty() {
+            return vec![self.frame_at(addr, base_ptr, stack_ptr)];
+        }
+        let outermost = chain.len() - 1;
+        chain
+            .into_iter()
+            .enumerate()
+            .map(|(index, (function, line))| Frame {
+                function,
+                line,
+                addr,
+                base_ptr,
+                stack_ptr,
+                inlined: index < outermost,
+            })
+            .collect()
+    }
+
+    /// Walks the stack of the stopped inferior, innermost frame first.
+    pub fn backtrace(&self) -> Result<Vec<Frame>, nix::Error> {
+        let inferior = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?;
+        let regs = Registers::read(inferior.pid())?;
+        let mut instruction_ptr = regs.pc();
+        let mut base_ptr = regs.frame_pointer();
+        let mut stack_ptr = regs.stack_pointer();
+        let mut frames = Vec::new();
+        // past the innermost frame, the pc is a return address, which may be the start of the
+        // next function after a call that doesn't return
+        let mut innermost = true;
+        // at the first instruction of a function without debugging information (e.g. at a
+        // breakpoint on malloc), the frame pointer still is the caller's, and the return
+        // address is where the call left it
+        let at_symbol = self.symbol_containing(instruction_ptr).is_some_and(|(_, offset, _)| offset == 0);
+        if at_symbol && self.debug_data.get_function_containing(instruction_ptr).is_none() {
+            frames.push(self.frame_at(instruction_ptr, base_ptr, stack_ptr));
+            (instruction_ptr, stack_ptr) = arch::return_address_at_entry(inferior.pid(), &regs)?;
+            innermost = false;
+        }
+        loop {
+            // a caller's function and line are those of its call, which the return address may
+            // be past, e.g. at the next line after a call whose result isn't used
+            let lookup_addr = if innermost { instruction_ptr } else { instruction_ptr - 1 };
+            let mut inline_frames = self.frames_at(lookup_addr, base_ptr, stack_ptr);
+            for frame in &mut inline_frames {
+                frame.addr = instruction_ptr;
+            }
+            // Rust programs' main is e.g. "hello::main", called from the standard library
+            let is_main = inline_frames.last().unwrap()
+                .function
+                .as_deref()
+                .is_some_and(|name| name == "main" || name.ends_with("::main"));
+            frames.extend(inline_frames);
+            if is_main || base_ptr == 0 {
+                break;
+            }
+            // the caller's stack pointer is the frame's canonical frame address
+            stack_ptr = self.canonical_frame_address(lookup_addr, base_ptr, stack_ptr);
+            // code built without frame pointers (like most of the C library) leaves something
+            // else in the frame pointer, which ends the walk once it doesn't point into the stack
+            let read = |addr: usize| ptrace::read(inferior.pid(), addr as ptrace::AddressType);
+            match (read(base_ptr + 8), read(base_ptr)) {
+                (Ok(return_addr), Ok(saved_base_ptr)) => {
+                    instruction_ptr = return_addr as usize;
+                    base_ptr = saved_base_ptr as usize;
+                    innermost = false;
+                }
+
+                _ => break,
+            }
+        }
+        Ok(frames)
+    }
+
+    fn print_backtrace(&mut self) -> Result<(), nix::Error> {
+        // Starter code: Ok(println!("Hello world"))
+        let frames = self.backtrace()?;
+        self.emit(Event::Backtrace(frames));
+        Ok(())
+    }
+
+    /// Reads the value of `var` as seen from `frame`.
+    pub fn read_variable(&self, var: &Variable, frame: &Frame) -> Result<String, nix::Error> {
+        let inferior = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?;
+        let addr = match var.location {
+            Location::Address(addr) => addr,
+            Location::FramePointerOffset(offset) => {
+                let frame_base = match self.debug_data.get_function_containing(frame.addr) {
+                    Some(func) if func.frame_base == FrameBase::FramePointer => frame.base_ptr,
+                    Some(func) if func.frame_base == FrameBase::StackPointer => frame.stack_ptr,
+                    _ => self.canonical_frame_address(frame.addr, frame.base_ptr, frame.stack_ptr),
+                };
+                (frame_base as isize + offset) as usize
+            }
+        };
+        let read_memory = |addr, len| inferior.read_memory(addr, len);
+        let get_type = |offset| self.debug_data.get_type(offset);
+        ValueFormatter::new(&get_type, &read_memory).format(addr, &var.entity_type)
+    }
+
+    /// Returns the local variables and parameters of the function `frame` is in, with their
+    /// values.
+    pub fn frame_variables(&self, frame: &Frame) -> Vec<(String, String)> {
+        let func = match self.debug_data.get_function_containing(frame.addr) {
+            Some(func) => func,
+            None => return Vec::new(),
+        };
+        func.variables
+            .iter()
+            .map(|var| {
+                let value = self
+                    .read_variable(var, frame)
+                    .unwrap_or_else(|err| format!("<error: {}>", err));
+                (var.name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Evaluates the variable `name` in `frame`. Returns None if no such variable is visible.
+    pub fn evaluate(&self, name: &str, frame: &Frame) -> Option<Result<String, nix::Error>> {
+        let var = self.debug_data.get_variable(frame.addr, name)?;
+        Some(self.read_variable(var, frame))
+    }
+
+    fn print_variable(&self, name: &str) -> Result<(), nix::Error> {
+        let inferior = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?;
+        let regs = Registers::read(inferior.pid())?;
+        let frame = self.frame_at(regs.pc(), regs.frame_pointer(), regs.stack_pointer());
+        match self.evaluate(name, &frame) {
+            Some(value) => self.emit(Event::Value {
+                name: name.to_string(),
+                value: value?,
+            }),
+            None => self.emit(Event::Error(format!("No symbol \"{}\" in current context.", name))),
+        }
+        Ok(())
+    }
+
+    /// Resolves a breakpoint location: `*address`, `[file:]line` or `[file:]function`. A
+    /// function may have several addresses: its own body and each copy of it that was inlined.
+    /// Functions without debugging information are found through the ELF symbols.
+    fn resolve_location(&mut self, location: &str) -> Vec<usize> {
+        if let Some(raw_addr) = location.strip_prefix('*') {
+            return self.parse_address(raw_addr).into_iter().collect();
+        }
+        let (file, target) = split_location(location);
+        if target.chars().all(|c| c.is_numeric()) {
+            // parse as the line number
+            let line_number = match target.parse::<usize>() {
+                Ok(line_number) => line_number,
+                Err(_) => return Vec::new(),
+            };
+            DwarfData::get_addr_for_line(&self.debug_data, file, line_number).into_iter().collect()
+        } else {
+            // treat the target as the function name
+            let mut addrs: Vec<usize> =
+                DwarfData::get_addr_after_prologue(&self.debug_data, file, target).into_iter().collect();
+            addrs.extend(self.debug_data.get_inlined_addrs(file, target));
+            if addrs.is_empty() && file.is_none() {
+                addrs.extend(self.lookup_symbol(target).map(|symbol| symbol.address));
+            }
+            addrs
+        }
+    }
+
+    /// Sets a breakpoint at `location` and returns its number and (first) address. Before the
+    /// program runs, a function that can't be found may be in a shared library, so the
+    /// breakpoint is left pending (without an address) until the libraries are loaded.
+    pub fn set_breakpoint(&mut self, location: &str) -> Option<(usize, Option<usize>)> {
+        let addrs = self.resolve_location(location);
+        let is_function = !location.starts_with('*')
+            && split_location(location).0.is_none()
+            && !location.chars().all(|c| c.is_numeric());
+        if addrs.is_empty() && !(is_function && self.inferior.is_none()) {
+            self.emit(Event::Error(format!("Could not find a location for breakpoint {}", location)));
+            return None;
+        }
+        if !self.install_breakpoints(&addrs) {
+            return None;
+        }
+        let num = self.next_breakpoint_num;
+        self.next_breakpoint_num += 1;
+        self.user_breakpoints.push(UserBreakpoint {
+            num,
+            location: location.to_string(),
+            addrs: addrs.clone(),
+            commands: Vec::new(),
+            saved: self.origin != Origin::Script,
+        });
+        let addr = addrs.first().copied();
+        if addrs.is_empty() {
+            self.emit(Event::Message(format!("Breakpoint {} ({}) pending.", num, location)));
+        } else {
+            self.emit(Event::BreakpointSet { num, addrs });
+        }
+        Some((num, addr))
+    }
+
+    /// Writes the traps for a breakpoint at `addrs`, if the inferior is running, or otherwise
+    /// records them to be written when it starts. Returns false if one can't be written.
+    fn install_breakpoints(&mut self, addrs: &[usize]) -> bool {
+        for &parsed_addr in addrs {
+            // set breakpoints if inferior exists
+            if let Some(inferior) = self.inferior.as_mut() {
+                if !matches!(self.breakpoints.get(&parsed_addr), Some(Some(_))) {
+                    match inferior.insert_trap(parsed_addr) {
+                        Ok(breakpoint) => {
+                            self.breakpoints.insert(parsed_addr, Some(breakpoint));
+                        },
+                        Err(err) => {
+                            self.emit(Event::Error(format!("Inferior::insert_trap for breakpoint error {}", err)));
+                            return false;
+                        }
+                    }
+                }
+            } else {
+                self.breakpoints.insert(parsed_addr, None);
+            }
+        }
+        true
+    }
+
+    /// Deletes user breakpoint `num`, removing its trap unless another breakpoint shares it.
+    pub fn delete_breakpoint(&mut self, num: usize) -> bool {
+        if let Some(index) = self.catchpoints.iter().position(|catchpoint| catchpoint.num == num) {
+            self.catchpoints.remove(index);
+            self.update_syscall_tracing();
+            return true;
+        }
+        let index = match self.user_breakpoints.iter().position(|bp| bp.num == num) {
+            Some(index) => index,
+            None => return false,
+        };
+        for addr in self.user_breakpoints.remove(index).addrs {
+            self.remove_trap(addr);
+        }
+        true
+    }
+
+    /// Returns whether `trace-calls` or `leak-check` has a breakpoint at `addr`.
+    fn is_internal_trap(&self, addr: usize) -> bool {
+        self.call_trace.as_ref().is_some_and(|trace| trace.is_trace_point(addr))
+            || self.heap_check.as_ref().is_some_and(|check| check.is_trap(addr))
+    }
+
+    /// Removes the trap at `addr`, unless a user breakpoint, `trace-calls` or `leak-check` still
+    /// needs it.
+    fn remove_trap(&mut self, addr: usize) {
+        if self.is_user_breakpoint(addr) || self.is_internal_trap(addr) {
+            return;
+        }
+        if let Some(Some(breakpoint)) = self.breakpoints.remove(&addr) {
+            if let Some(inferior) = self.inferior.as_mut() {
+                let _ = inferior.remove_trap(&breakpoint);
+            }
+        }
+    }
+
+    /// Starts tracing the calls to the functions whose names match the glob `pattern`, or stops
+    /// tracing if it is None.
+    fn trace_calls(&mut self, pattern: Option<String>) {
+        if let Some(trace) = self.call_trace.take() {
+            let addrs = trace.functions.iter().map(|func| func.address).chain(trace.bodies).chain(trace.return_addrs);
+            for addr in addrs {
+                self.remove_trap(addr);
+            }
+        }
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => return,
+        };
+        let regex = glob_to_regex(&pattern);
+        let mut functions: Vec<Function> = Vec::new();
+        for (file, _) in self.debug_data.all_units() {
+            let matching = file.functions.iter().filter(|func| regex.is_match(&func.name) || regex.is_match(&func.qualified_name));
+            functions.extend(matching.cloned());
+        }
+        if functions.is_empty() {
+            self.emit(Event::Error(format!("No function matches \"{}\".", pattern)));
+            return;
+        }
+        let bodies: Vec<usize> = functions.iter().map(|func| self.debug_data.get_prologue_end(func)).collect();
+        let addrs: Vec<usize> = functions.iter().map(|func| func.address).chain(bodies.iter().copied()).collect();
+        let count = functions.len();
+        self.call_trace = Some(CallTrace {
+            pattern: pattern.clone(),
+            functions,
+            bodies,
+            return_addrs: HashSet::new(),
+            calls: Vec::new(),
+        });
+        if self.install_breakpoints(&addrs) {
+            let noun = if count == 1 { "function" } else { "functions" };
+            self.emit(Event::Message(format!("Tracing calls to {} {} matching \"{}\".", count, noun, pattern)));
+        }
+    }
+
+    /// Starts or stops tracking the inferior's heap allocations. Started while the program runs,
+    /// it only sees the allocations made from then on.
+    fn leak_check(&mut self, on: bool) {
+        if !on {
+            if let Some(check) = self.heap_check.take() {
+                for addr in check.entries.into_keys().chain(check.return_addrs) {
+                    self.remove_trap(addr);
+                }
+            }
+            return;
+        }
+        if self.heap_check.is_some() {
+            return;
+        }
+        self.heap_check = Some(HeapCheck::default());
+        if self.inferior.is_some() {
+            self.install_heap_breakpoints(false);
+        }
+        self.emit(Event::Message("Tracking heap allocations.".to_string()));
+    }
+
+    /// Sets the breakpoints on the allocation functions. `complete` is set when this happens as
+    /// the program starts.
+    fn install_heap_breakpoints(&mut self, complete: bool) {
+        let entries: HashMap<usize, &'static str> = heap::FUNCTIONS
+            .iter()
+            .filter_map(|name| Some((self.lookup_symbol(name)?.address, *name)))
+            .collect();
+        let addrs: Vec<usize> = entries.keys().copied().collect();
+        if let Some(check) = self.heap_check.as_mut() {
+            check.entries = entries;
+            check.complete = complete;
+        }
+        if addrs.is_empty() {
+            self.emit(Event::Error("No allocation functions found to track.".to_string()));
+        }
+        self.install_breakpoints(&addrs);
+    }
+
+    /// Forgets the allocations of the previous run, and the breakpoints on the allocation
+    /// functions and return addresses, which may not be valid in a new process.
+    fn reset_heap_check(&mut self) {
+        let check = match self.heap_check.as_mut() {
+            Some(check) => std::mem::take(check),
+            None => return,
+        };
+        for addr in check.entries.into_keys().chain(check.return_addrs) {
+            self.remove_trap(addr);
+        }
+    }
+
+    /// Records the allocation or free that the `leak-check` breakpoint at `pc` marks, reporting
+    /// invalid frees. Returns false if there is no such breakpoint at `pc`.
+    fn heap_stop(&mut self, pc: usize) -> Result<bool, nix::Error> {
+        let mut check = match self.heap_check.take() {
+            Some(check) if check.is_trap(pc) => check,
+            other => {
+                self.heap_check = other;
+                return Ok(false);
+            }
+        };
+        let result = self.heap_stop_with(&mut check, pc);
+        self.heap_check = Some(check);
+        result.map(|_| true)
+    }
+
+    fn heap_stop_with(&mut self, check: &mut HeapCheck, pc: usize) -> Result<(), nix::Error> {
+        let pid = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?.pid();
+        let regs = Registers::read(pid)?;
+        let returned = check
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.return_addr == pc && pending.stack_ptr == regs.stack_pointer());
+        if returned {
+            let pending = check.pending.take().unwrap();
+            let ptr = regs.return_values()[0] as usize;
+            if pending.function == "realloc" && pending.old_ptr != 0 {
+                // a realloc that fails leaves the old block allocated, unless it was asked for
+                // zero bytes
+                if ptr == 0 && pending.size != 0 {
+                    return Ok(());
+                }
+                let _ = check.tracker.freed(pending.old_ptr, pending.site.clone());
+            }
+            check.tracker.allocated(ptr, pending.size, pending.site);
+        }
+        let function = match check.entries.get(&pc) {
+            Some(function) if check.pending.is_none() => *function,
+            _ => return Ok(()),
+        };
+        // the caller's frames, without the allocation function's own
+        let site: Site = self.backtrace()?.iter().skip(1).map(|frame| frame.to_string()).collect();
+        let [arg0, arg1, ..] = regs.arguments().map(|arg| arg as usize);
+        let invalid = match function {
+            "free" => check.tracker.freed(arg0, site.clone()),
+            "realloc" => check.tracker.check(arg0),
+            _ => Ok(()),
+        };
+        match invalid {
+            Err(InvalidFree::DoubleFree(first)) => self.emit(Event::Error(format!(
+                "Invalid {}() of {:#x}: double free\n{}\n  first freed at\n{}",
+                function,
+                arg0,
+                heap::format_site(&site),
+                heap::format_site(&first)
+            ))),
+            // a block allocated before tracking started isn't known
+            Err(InvalidFree::Unknown) if check.complete => self.emit(Event::Error(format!(
+                "Invalid {}() of {:#x}: not an allocated block\n{}",
+                function,
+                arg0,
+                heap::format_site(&site)
+            ))),
+            _ => {}
+        }
+        let (size, old_ptr) = match function {
+            "malloc" => (arg0, 0),
+            "calloc" => (arg0.saturating_mul(arg1), 0),
+            "realloc" => (arg1, arg0),
+            _ => return Ok(()),
+        };
+        let (return_addr, stack_ptr) = arch::return_address_at_entry(pid, &regs)?;
+        check.pending = Some(PendingAllocation {
+            function,
+            size,
+            old_ptr,
+            return_addr,
+            stack_ptr,
+            site,
+        });
+        if check.return_addrs.insert(return_addr) {
+            self.install_breakpoints(&[return_addr]);
+        }
+        Ok(())
+    }
+
+    /// Lists the live heap allocations.
+    fn info_heap(&mut self) {
+        let check = match &self.heap_check {
+            Some(check) => check,
+            None => return self.emit(Event::Error("Heap allocations aren't tracked; see leak-check.".to_string())),
+        };
+        let live = check.tracker.live();
+        let mut lines: Vec<String> = live
+            .iter()
+            .map(|(ptr, allocation)| {
+                let site = allocation.site.first().map(String::as_str).unwrap_or("??");
+                format!("{:#x}: {} bytes from {}", ptr, allocation.size, site)
+            })
+            .collect();
+        let total: usize = live.iter().map(|(_, allocation)| allocation.size).sum();
+        lines.push(format!("{} bytes in {}", total, heap::allocations(live.len())));
+        self.emit(Event::Message(lines.join("\n")));
+    }
+
+    /// Lists what is still allocated once the inferior has gone, if heap allocations are
+    /// tracked.
+    fn report_leaks(&mut self) {
+        let report = match &self.heap_check {
+            Some(check) => check.tracker.leak_report().unwrap_or_else(|| "All heap blocks were freed.".to_string()),
+            None => return,
+        };
+        self.emit(Event::Message(report));
+    }
+
+    /// Forgets the calls in progress, whose returns won't be seen, and the breakpoints on their
+    /// return addresses, which may not be valid in a new process.
+    fn reset_call_trace(&mut self) {
+        let return_addrs = match self.call_trace.as_mut() {
+            Some(trace) => {
+                trace.calls.clear();
+                std::mem::take(&mut trace.return_addrs)
+            }
+            None => return,
+        };
+        for addr in return_addrs {
+            self.remove_trap(addr);
+        }
+    }
+
+    /// Logs the call or return that the `trace-calls` breakpoint at `pc` marks. Returns false if
+    /// there is no such breakpoint at `pc`.
+    fn trace_call_stop(&mut self, pc: usize) -> Result<bool, nix::Error> {
+        let mut trace = match self.call_trace.take() {
+            Some(trace) if trace.is_trace_point(pc) => trace,
+            other => {
+                self.call_trace = other;
+                return Ok(false);
+            }
+        };
+        let result = self.trace_call_stop_with(&mut trace, pc);
+        self.call_trace = Some(trace);
+        result.map(|_| true)
+    }
+
+    fn trace_call_stop_with(&mut self, trace: &mut CallTrace, pc: usize) -> Result<(), nix::Error> {
+        let inferior = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?;
+        let pid = inferior.pid();
+        let regs = Registers::read(pid)?;
+        if trace.return_addrs.contains(&pc) {
+            let returned = trace
+                .calls
+                .iter()
+                .rposition(|call| call.return_addr == pc && call.stack_ptr == regs.stack_pointer());
+            if let Some(index) = returned {
+                // calls above it were left without returning, e.g. by longjmp
+                let call = trace.calls.drain(index..).next().unwrap();
+                if call.logged {
+                    let func = &trace.functions[call.function];
+                    let elapsed = call.start.elapsed().as_secs_f64() * 1000.0;
+                    let value = match &func.return_type {
+                        Some(return_type) => {
+                            let value = self
+                                .format_return_value(return_type, &regs, call.indirect_result)
+                                .unwrap_or_else(|err| format!("<error: {}>", err));
+                            format!(" = {}", value)
+                        }
+                        None => String::new(),
+                    };
+                    let indent = "  ".repeat(index);
+                    self.emit(Event::Message(format!("{}}} {}{} <{:.3} ms>", indent, func.qualified_name, value, elapsed)));
+                }
+            }
+        }
+        let (base_ptr, stack_ptr) = (regs.frame_pointer(), regs.stack_pointer());
+        if let Some(function) = trace.functions.iter().position(|func| func.address == pc) {
+            let (return_addr, stack_ptr) = arch::return_address_at_entry(pid, &regs)?;
+            trace.calls.push(TracedCall {
+                function,
+                return_addr,
+                stack_ptr,
+                indirect_result: Some(regs.indirect_result()),
+                start: Instant::now(),
+                logged: false,
+            });
+            if trace.return_addrs.insert(return_addr) {
+                self.install_breakpoints(&[return_addr]);
+            }
+        }
+        if let Some(function) = trace.bodies.iter().position(|body| *body == pc) {
+            let entered = trace.calls.last().is_some_and(|call| call.function == function && !call.logged);
+            if !entered {
+                // the entry breakpoint was stepped over, e.g. by `step`, so the return address
+                // is found through the frame pointer set up by now
+                let return_addr = ptrace::read(pid, (base_ptr + 8) as ptrace::AddressType)? as usize;
+                trace.calls.push(TracedCall {
+                    function,
+                    return_addr,
+                    stack_ptr: self.canonical_frame_address(pc, base_ptr, stack_ptr),
+                    indirect_result: None,
+                    start: Instant::now(),
+                    logged: false,
+                });
+                if trace.return_addrs.insert(return_addr) {
+                    self.install_breakpoints(&[return_addr]);
+                }
+            }
+            let func = &trace.functions[function];
+            let frame = self.frame_at(pc, base_ptr, stack_ptr);
+            let args: Vec<String> = func
+                .variables
+                .iter()
+                .filter(|var| var.parameter)
+                .map(|var| {
+                    let value = self.read_variable(var, &frame).unwrap_or_else(|err| format!("<error: {}>", err));
+                    format!("{}={}", var.name, value)
+                })
+                .collect();
+            let indent = "  ".repeat(trace.calls.len() - 1);
+            self.emit(Event::Message(format!("{}{}({}) {{", indent, func.qualified_name, args.join(", "))));
+            if let Some(call) = trace.calls.last_mut() {
+                call.logged = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats the value of type `return_type` that a function has just returned. It is in
+    /// two integer registers, or a vector register for floating point numbers; a large
+    /// structure is returned in memory at `indirect_result`, where the caller asked for it.
+    fn format_return_value(&self, return_type: &Type, regs: &Registers, indirect_result: Option<usize>) -> Result<String, nix::Error> {
+        let inferior = self.inferior.as_ref().ok_or(nix::Error::ECHILD)?;
+        let get_type = |offset| self.debug_data.get_type(offset);
+        let mut resolved = return_type;
+        while let TypeKind::Alias(Some(offset)) = resolved.kind {
+            match get_type(offset) {
+                Some(target) => resolved = target,
+                None => break,
+            }
+        }
+        let value: Vec<u8> = match resolved.kind {
+            TypeKind::Base(Encoding::Float) if resolved.size <= 8 => arch::float_return_value(inferior.pid())?.to_vec(),
+            _ if resolved.size > 16 => {
+                let read_memory = |addr, len| inferior.read_memory(addr, len);
+                let addr = indirect_result.ok_or(nix::Error::ENODATA)?;
+                return ValueFormatter::new(&get_type, &read_memory).format(addr, return_type);
+            }
+            _ => regs.return_values().iter().flat_map(|value| value.to_ne_bytes()).collect(),
+        };
+        // the registers are formatted as if they were memory at address 0, where nothing a
+        // value might point to can be
+        let read_memory = |addr: usize, len: usize| match value.get(addr..addr + len) {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => inferior.read_memory(addr, len),
+        };
+        ValueFormatter::new(&get_type, &read_memory).format(0, return_type)
+    }
+
+    /// Switches the terminal UI on or off.
+    pub fn set_tui(&mut self, on: bool) {
+        if !on {
+            self.tui = None;
+        } else if self.tui.is_none() {
+            if self.interpreter != Interpreter::Console {
+                self.emit(Event::Error("The TUI is only available with the console interpreter.".to_string()));
+                return;
+            }
+            self.tui = Tui::enable();
+            if self.tui.is_none() {
+                self.emit(Event::Error("Cannot enable the TUI: the terminal is missing or too small.".to_string()));
+            }
+            self.draw_tui();
+        }
+    }
+
+    /// Redraws the terminal UI's panes, if it is on, for the current state of the inferior and
+    /// the breakpoints.
+    fn draw_tui(&mut self) {
+        if self.tui.is_some() {
+            let view = self.tui_view();
+            if let Some(tui) = self.tui.as_mut() {
+                tui.draw(&view);
+            }
+        }
+    }
+
+    /// Gathers what the terminal UI's panes show.
+    fn tui_view(&self) -> tui::View {
+        let breakpoint_addrs: HashSet<usize> = self.user_breakpoints.iter().flat_map(|bp| bp.addrs.iter().copied()).collect();
+        let mut view = tui::View {
+            breakpoint_lines: breakpoint_addrs
+                .iter()
+                .filter_map(|&addr| self.debug_data.get_line_from_addr(addr))
+                .map(|line| (line.file, line.number))
+                .collect(),
+            breakpoint_addrs,
+            ..tui::View::default()
+        };
+        let regs = match self.inferior.as_ref().map(|inferior| Registers::read(inferior.pid())) {
+            Some(Ok(regs)) => regs,
+            _ => return view,
+        };
+        let pc = regs.pc();
+        view.pc = Some(pc);
+        view.line = self.debug_data.get_line_from_addr(pc);
+        view.code = self.code_around(pc);
+        view.frames = self.backtrace().unwrap_or_default();
+        view.registers = regs.named();
+        view
+    }
+
+    /// Reads the machine code of the function containing `pc` up to a little past it, with the
+    /// original bytes in place of breakpoints. Returns the address the code starts at.
+    fn code_around(&self, pc: usize) -> (usize, Vec<u8>) {
+        const MAX_FUNCTION_OFFSET: usize = 0x1000;
+        const LOOKAHEAD: usize = 0x100;
+        let start = match self.debug_data.get_function_containing(pc) {
+            Some(func) => func.address,
+            None => self.symbol_containing(pc).map_or(pc, |(_, offset, _)| pc - offset),
+        };
+        // decoding a huge function from its start would be slow, so start at the pc instead
+        let start = if pc - start > MAX_FUNCTION_OFFSET { pc } else { start };
+        let mut code = Vec::new();
+        if let Some(inferior) = self.inferior.as_ref() {
+            // a word at a time, so that the end of a mapping just cuts the code short
+            while code.len() < pc - start + LOOKAHEAD {
+                match inferior.read_memory(start + code.len(), 8) {
+                    Ok(word) => code.extend(word),
+                    Err(_) => break,
+                }
+            }
+        }
+        for breakpoint in self.breakpoints.values().flatten() {
+            breakpoint.restore_in(start, &mut code);
+        }
+        (start, code)
+    }
+
+    /// Makes a checkpoint: a paused copy of the inferior at its current point.
+    fn checkpoint(&mut self) -> Result<(), nix::Error> {
+        let inferior = self.inferior.as_mut().ok_or(nix::Error::ECHILD)?;
+        if inferior.in_syscall() {
+            self.emit(Event::Error("Can't make a checkpoint inside a system call.".to_string()));
+            return Ok(());
+        }
+        let process = inferior.fork()?;
+        let regs = Registers::read(process.pid())?;
+        let num = self.next_checkpoint_num;
+        self.next_checkpoint_num += 1;
+        self.emit(Event::Message(format!("checkpoint {}: fork returned pid {}.", num, process.pid())));
+        self.checkpoints.push(Checkpoint {
+            num,
+            process,
+            breakpoints: self.breakpoints.clone(),
+            frame: self.frame_at(regs.pc(), regs.frame_pointer(), regs.stack_pointer()),
+        });
+        Ok(())
+    }
+
+    fn info_checkpoints(&mut self) {
+        if self.checkpoints.is_empty() {
+            self.emit(Event::Message("No checkpoints.".to_string()));
+            return;
+        }
+        let lines: Vec<String> = self
+            .checkpoints
+            .iter()
+            .map(|checkpoint| format!("  {} process {} at {}", checkpoint.num, checkpoint.process.pid(), checkpoint.frame))
+            .collect();
+        self.emit(Event::Message(lines.join("\n")));
+    }
+
+    /// Goes back to checkpoint `num`, replacing the inferior with a copy of it. The checkpoint
+    /// itself stays as it is, so it can be restarted again.
+    fn restart(&mut self, num: usize) -> Result<(), nix::Error> {
+        let index = match self.checkpoints.iter().position(|checkpoint| checkpoint.num == num) {
+            Some(index) => index,
+            None => {
+                self.emit(Event::Error(format!("No checkpoint number {}.", num)));
+                return Ok(());
+            }
+        };
+        let checkpoint = &mut self.checkpoints[index];
+        let mut process = checkpoint.process.fork()?;
+        // the breakpoints may have changed since the checkpoint was made
+        for (addr, planted) in &checkpoint.breakpoints {
+            if let Some(planted) = planted {
+                if !matches!(self.breakpoints.get(addr), Some(Some(_))) {
+                    process.remove_trap(planted)?;
+                }
+            }
+        }
+        for (addr, breakpoint) in self.breakpoints.iter_mut() {
+            if !matches!(checkpoint.breakpoints.get(addr), Some(Some(_))) {
+                *breakpoint = Some(process.insert_trap(*addr)?);
+            }
+        }
+        if let Some(mut inferior) = self.inferior.take() {
+            inferior.kill();
+        }
+        if let Some(trace) = self.call_trace.as_mut() {
+            trace.calls.clear();
+        }
+        if let Some(check) = self.heap_check.as_mut() {
+            check.pending = None;
+        }
+        process.make_interruptible();
+        let pid = process.pid();
+        self.inferior = Some(process);
+        self.update_syscall_tracing();
+        self.emit(Event::Message(format!("Switching to process {}", pid)));
+        let pc = Registers::read(pid)?.pc();
+        self.emit(Event::Stopped {
+            signal: Signal::SIGTRAP,
+            addr: pc,
+            function: self.function_name_at(pc),
+            line: DwarfData::get_line_from_addr(&self.debug_data, pc),
+            reason: StopReason::Step,
+        });
+        self.draw_tui();
+        Ok(())
+    }
+
+    /// Kills checkpoint `num`.
+    fn delete_checkpoint(&mut self, num: usize) {
+        match self.checkpoints.iter().position(|checkpoint| checkpoint.num == num) {
+            Some(index) => self.checkpoints.remove(index).process.kill(),
+            None => self.emit(Event::Error(format!("No checkpoint number {}.", num))),
+        }
+    }
+
+    /// Describes the symbol `addr` is in, like `func + 4 in section .text`.
+    fn info_symbol(&mut self, addr: &str) {
+        let message = match self.parse_address(addr).and_then(|addr| self.symbol_containing(addr)) {
+            Some((symbol, offset, library)) => {
+                let mut message = symbol.name.clone();
+                if offset > 0 {
+                    message.push_str(&format!(" + {}", offset));
+                }
+                message.push_str(&format!(" in section {}", symbol.section));
+                if let Some(library) = library {
+                    message.push_str(&format!(" of {}", library));
+                }
+                message
+            }
+            None => format!("No symbol matches {}.", addr),
+        };
+        self.emit(Event::Message(message));
+    }
+
+    /// Tells where the function or variable `name` is stored: at an address, or for a local
+    /// variable of the current function, relative to its frame base.
+    fn info_address(&mut self, name: &str) {
+        let pc = match &self.inferior {
+            Some(inferior) => Registers::read(inferior.pid()).map_or(0, |regs| regs.pc()),
+            None => 0,
+        };
+        let message = if let Some(var) = self.debug_data.get_variable(pc, name) {
+            match var.location {
+                Location::Address(addr) => {
+                    format!("Symbol \"{}\" is static storage at address {:#x}.", name, addr)
+                }
+                Location::FramePointerOffset(offset) => {
+                    format!("Symbol \"{}\" is a variable at frame base offset {}.", name, offset)
+                }
+            }
+        } else if let Some(addr) = self.debug_data.get_addr_for_function(None, name) {
+            format!("Symbol \"{}\" is a function at address {:#x}.", name, addr)
+        } else if let Some(symbol) = self.lookup_symbol(name) {
+            format!(
+                "Symbol \"{}\" is at {:#x} in a file compiled without debugging.",
+                name, symbol.address
+            )
+        } else {
+            format!("No symbol \"{}\" in current context.", name)
+        };
+        self.emit(Event::Message(message));
+    }
+
+    /// Labels an address with the function it is in, like `<func2+11>`.
+    fn addr_label(&self, addr: usize) -> String {
+        let (name, offset) = match self.debug_data.get_function_containing(addr) {
+            Some(func) => (func.qualified_name.clone(), addr - func.address),
+            None => match self.symbol_containing(addr) {
+                Some((symbol, offset, _)) => (symbol.name.clone(), offset),
+                None => return String::new(),
+            },
+        };
+        match offset {
+            0 => format!("<{}>", name),
+            offset => format!("<{}+{}>", name, offset),
+        }
+    }
+
+    /// Lists the functions (or, with `functions` unset, the variables) whose names match
+    /// `regex`, by file, followed by the matching symbols of code without debugging information.
+    fn info_functions_or_variables(&mut self, regex: Option<String>, functions: bool) {
+        let pattern = match Regex::new(regex.as_deref().unwrap_or("")) {
+            Ok(pattern) => pattern,
+            Err(err) => return self.emit(Event::Error(format!("Invalid regexp: {}", err))),
+        };
+        let mut files: BTreeMap<&str, Vec<(&str, String)>> = BTreeMap::new();
+        let mut described = HashSet::new();
+        for (file, _) in self.debug_data.all_units() {
+            let entries = files.entry(file.name.as_str()).or_default();
+            if functions {
+                for func in &file.functions {
+                    if pattern.is_match(&func.qualified_name) {
+                        entries.push((&func.qualified_name, format!("{}:\t{};", func.line_number, func.qualified_name)));
+                    }
+                }
+            } else {
+                for var in &file.global_variables {
+                    if pattern.is_match(&var.name) {
+                        let declaration = format!("{}:\t{} {};", var.line_number, var.entity_type.name, var.name);
+                        entries.push((&var.name, declaration));
+                        described.insert(var.name.as_str());
+                    }
+                }
+            }
+        }
+        let what = if functions { "functions" } else { "variables" };
+        let mut message = match &regex {
+            Some(regex) => format!("All {} matching regular expression \"{}\":", what, regex),
+            None => format!("All defined {}:", what),
+        };
+        for (file, mut entries) in files {
+            if entries.is_empty() {
+                continue;
+            }
+            entries.sort();
+            entries.dedup();
+            message.push_str(&format!("\n\nFile {}:", file));
+            for (_, entry) in entries {
+                message.push_str(&format!("\n{}", entry));
+            }
+        }
+        let symbols: Vec<String> = std::iter::once(self.debug_data.symbols())
+            .chain(&self.library_symbols)
+            .flat_map(|table| table.symbols())
+            .filter(|symbol| {
+                let has_debug_info = match functions {
+                    true => self.debug_data.get_function_containing(symbol.address).is_some(),
+                    false => described.contains(symbol.name.as_str()),
+                };
+                symbol.function == functions && !has_debug_info && pattern.is_match(&symbol.name)
+            })
+            .map(|symbol| format!("{:#018x}  {}", symbol.address, symbol.name))
+            .collect();
+        if !symbols.is_empty() {
+            message.push_str("\n\nNon-debugging symbols:\n");
+            message.push_str(&symbols.join("\n"));
+        }
+        self.emit(Event::Message(message));
+    }
+
+    /// Lists the named types matching `regex`, by file.
+    fn info_types(&mut self, regex: Option<String>) {
+        let pattern = match Regex::new(regex.as_deref().unwrap_or("")) {
+            Ok(pattern) => pattern,
+            Err(err) => return self.emit(Event::Error(format!("Invalid regexp: {}", err))),
+        };
+        let mut files: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (file, types) in self.debug_data.all_units() {
+            let names = files.entry(file.name.as_str()).or_default();
+            for entity_type in types.values() {
+                // pointers and qualified types are named after the type they refer to, if at all
+                let pointer = matches!(entity_type.kind, TypeKind::Pointer(_));
+                if !pointer && !entity_type.name.is_empty() && pattern.is_match(&entity_type.name) {
+                    names.push(&entity_type.name);
+                }
+            }
+        }
+        let mut message = match &regex {
+            Some(regex) => format!("All types matching regular expression \"{}\":", regex),
+            None => "All defined types:".to_string(),
+        };
+        for (file, mut names) in files {
+            if names.is_empty() {
+                continue;
+            }
+            names.sort_unstable();
+            names.dedup();
+            message.push_str(&format!("\n\nFile {}:", file));
+            for name in names {
+                message.push_str(&format!("\n\t{}", name));
+            }
+        }
+        self.emit(Event::Message(message));
+    }
+
+    /// Lists the variables of the function at `location` and where they are stored.
+    fn info_scope(&mut self, location: &str) {
+        let (file, target) = split_location(location);
+        let addr = match target.chars().all(|c| c.is_numeric()) || location.starts_with('*') {
+            true => self.resolve_location(location).first().copied(),
+            false => self.debug_data.get_addr_for_function(file, target),
+        };
+        let func = match addr.and_then(|addr| self.debug_data.get_function_containing(addr)) {
+            Some(func) => func,
+            None => {
+                let message = format!("No function \"{}\" in this program.", location);
+                return self.emit(Event::Error(message));
+            }
+        };
+        let mut message = format!("Scope for {}:", location);
+        if func.variables.is_empty() {
+            message.push_str("\nSymbol table contains no locals or arguments.");
+        }
+        for var in &func.variables {
+            let storage = match var.location {
+                Location::Address(addr) => format!("static storage at address {:#x}", addr),
+                Location::FramePointerOffset(offset) => format!("a variable at frame base offset {}", offset),
+            };
+            message.push_str(&format!(
+                "\nSymbol {} is {}, length {}.",
+                var.name, storage, var.entity_type.size
+            ));
+        }
+        self.emit(Event::Message(message));
+    }
+
+    /// Tells which addresses the code of the line at `location` (or of the current line)
+    /// occupies.
+    fn info_line(&mut self, location: Option<String>) {
+        let addr = match &location {
+            Some(location) => {
+                let (file, target) = split_location(location);
+                match target.chars().all(|c| c.is_numeric()) || location.starts_with('*') {
+                    true => self.resolve_location(location).first().copied(),
+                    false => self.debug_data.get_addr_for_function(file, target),
+                }
+            }
+            None => self
+                .inferior
+                .as_ref()
+                .and_then(|inferior| Registers::read(inferior.pid()).ok())
+                .map(|regs| regs.pc()),
+        };
+        let addr = match addr {
+            Some(addr) => addr,
+            None => {
+                let location = location.unwrap_or_default();
+                return self.emit(Event::Error(format!("No line number information available for {}", location)));
+            }
+        };
+        let message = match self.debug_data.get_line_range(addr) {
+            Some((line, end)) => format!(
+                "Line {} of \"{}\" starts at address {:#x} {} and ends at {:#x} {}.",
+                line.number,
+                line.file,
+                line.address,
+                self.addr_label(line.address),
+                end,
+                self.addr_label(end)
+            ),
+            None => format!("No line number information available for address {:#x} {}", addr, self.addr_label(addr)),
+        };
+        self.emit(Event::Message(message));
+    }
+
+    /// Describes the current source file: that of the current line, or before the program runs,
+    /// that of `main`.
+    fn info_source(&mut self) {
+        let addr = match &self.inferior {
+            Some(inferior) => Registers::read(inferior.pid()).ok().map(|regs| regs.pc()),
+            None => self.debug_data.get_addr_for_function(None, "main"),
+        };
+        let file = match addr.and_then(|addr| self.debug_data.get_file_containing(addr)) {
+            Some(file) => file,
+            None => return self.emit(Event::Message("No current source file.".to_string())),
+        };
+        let path = std::path::Path::new(&file.comp_dir).join(&file.name);
+        let mut message = format!("Current source file is {}", file.name);
+        message.push_str(&format!("\nCompilation directory is {}", file.comp_dir));
+        message.push_str(&format!("\nLocated in {}", path.display()));
+        if let Ok(contents) = fs::read_to_string(&path) {
+            message.push_str(&format!("\nContains {} lines.", contents.lines().count()));
+        }
+        if let Some(language) = &file.language {
+            message.push_str(&format!("\nSource language is {}.", language));
+        }
+        if let Some(producer) = &file.producer {
+            message.push_str(&format!("\nProducer is {}.", producer));
+        }
+        message.push_str(&format!("\nCompiled with DWARF {} debugging format.", file.dwarf_version));
+        self.emit(Event::Message(message));
+    }
+
+    /// Reads the body of a `define` or `commands` block, up to its matching `end`.
+    fn read_block(&mut self) -> Vec<String> {
+        if self.pending_commands.is_empty() && !self.batch && self.interpreter == Interpreter::Console {
+            println!("Type commands one per line, ending with a line saying just \"end\".");
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        while let Some(line) = self.next_line(">") {
+            let line = line.trim().to_string();
+            match line.split_whitespace().next() {
+                Some("end") if depth == 0 => break,
+                Some("end") => depth -= 1,
+                Some("define") | Some("commands") => depth += 1,
+                _ => {}
+            }
+            body.push(line);
+        }
+        body
+    }
+
+    pub fn parse_address(&mut self, addr: &str) -> Option<usize> {
+        let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
+            &addr[2..]
+        } else {
+            addr
+        };
+        usize::from_str_radix(addr_without_0x, 16).ok()
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            let line = match self.next_line("(deet) ") {
+                Some(line) => line,
+                None => {
+                    self.inferior_release_try();
+                    self.save_session();
+                    return;
+                }
+            };
+            if !self.execute_line(&line) {
+                self.save_session();
+                return;
+            }
+        }
+    }
+
+    /// Runs `commands` followed by anything they queue, such as breakpoint command lists.
+    /// Returns false if one of them was `quit`.
+    pub fn execute_commands(&mut self, commands: Vec<String>) -> bool {
+        self.queue_commands_front(commands, Origin::User);
+        while let Some((line, origin)) = self.pending_commands.pop_front() {
+            self.origin = origin;
+            if !self.execute_line(&line) {
+                return false;
+            }
+        }
+        // what is run next without being queued comes from the front end
+        self.origin = Origin::User;
+        true
+    }
+
+    /// Parses and runs one line of input: a builtin command or a user-defined one. Blank lines
+    /// and `#` comments are skipped. Returns false if the command was `quit`.
+    pub fn execute_line(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            return true;
+        }
+        // run's arguments are parsed from the line itself, as quoted ones may contain whitespace
+        if matches!(tokens[0], "r" | "run") {
+            match RunArgs::parse(&line.trim_start()[tokens[0].len()..]) {
+                Ok(run) => return self.execute(DebuggerCommand::Run(run)),
+                Err(err) => {
+                    self.emit(Event::Error(err));
+                    return true;
+                }
+            }
+        }
+        if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+            return self.execute(cmd);
+        } else if let Some(body) = self.user_commands.get(tokens[0]) {
+            match expand_user_command(body, &tokens[1..]) {
+                Ok(commands) => self.queue_commands_front(commands, self.origin),
+                Err(missing) => self.emit(Event::Error(format!("Missing argument {} in user function.", missing))),
+            }
+            return true;
+        }
+        match debugger_command::expand_abbreviations(&tokens) {
+            Ok(expanded) if expanded[0] == "run" => return self.execute_line(&format!("run{}", &line.trim_start()[tokens[0].len()..])),
+            Ok(expanded) => {
+                if let Some(cmd) = DebuggerCommand::from_tokens(&expanded) {
+                    return self.execute(cmd);
+                }
+                self.emit(Event::Error("Unrecognized command.".to_string()));
+            }
+            Err(commands) if commands.len() > 1 => {
+                self.emit(Event::Error(format!("Ambiguous command \"{}\": {}.", tokens[0], commands.join(", "))));
+            }
+            Err(_) => self.emit(Event::Error("Unrecognized command.".to_string())),
+        }
+        true
+    }
+
+    /// Parses a line into a builtin command, spelling out abbreviations, without running it.
+    fn parse_line(&self, line: &str) -> Option<DebuggerCommand> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let expanded = debugger_command::expand_abbreviations(&tokens).ok()?;
+        DebuggerCommand::from_tokens(&expanded)
+    }
+
+    /// Brings what tab completion offers up to date: the functions of newly loaded shared
+    /// libraries, the variables in scope where the inferior is stopped and the user's commands.
+    fn update_completion(&mut self) {
+        let pc = self.inferior.as_ref().and_then(|inferior| Registers::read(inferior.pid()).ok()).map(|regs| regs.pc());
+        let mut variables: Vec<String> = Vec::new();
+        if let Some(pc) = pc {
+            if let Some(function) = self.debug_data.get_function_containing(pc) {
+                variables.extend(function.variables.iter().map(|var| var.name.clone()));
+            }
+            if let Some(file) = self.debug_data.get_file_containing(pc) {
+                variables.extend(file.global_variables.iter().map(|var| var.name.clone()));
+            }
+        }
+        let helper = match self.readline.helper_mut() {
+            Some(helper) => helper,
+            None => return,
+        };
+        if helper.libraries != self.library_symbols.len() {
+            helper.functions = function_names(self.debug_data.symbols());
+            for table in &self.library_symbols {
+                helper.functions.extend(function_names(table));
+            }
+            helper.libraries = self.library_symbols.len();
+        }
+        helper.variables = variables;
+        helper.user_commands = self.user_commands.keys().cloned().collect();
+    }
+
+    /// Runs a parsed command. Returns false if the command was `quit`.
+    pub fn execute(&mut self, command: DebuggerCommand) -> bool {
+        match command {
+            DebuggerCommand::Run(args) => self.start_inferior(&args),
+            DebuggerCommand::Quit => {
+                self.inferior_release_try();
+                return false;
+            },
+            DebuggerCommand::Continue => {
+                if self.inferior.is_some() {
+                    self.inferior_continue_exec();
+                } else {
+                    // press 'c' before 'r'
+                    self.emit(Event::Error("No process running error".to_string()));
+                }
+            },
+            DebuggerCommand::Step => {
+                let result = self.step_line(false);
+                self.report_error("Step", result);
+            }
+            DebuggerCommand::Next => {
+                let result = self.step_line(true);
+                self.report_error("Next", result);
+            }
+            DebuggerCommand::Finish => {
+                let result = self.finish();
+                self.report_error("Finish", result);
+            }
+            DebuggerCommand::Backtrace => {
+                let result = self.print_backtrace();
+                self.report_error("Backtrace", result);
+            },
+            DebuggerCommand::Breakpoint(target_wrapper) => {
+                if let Some(tg) = target_wrapper {
+                    self.set_breakpoint(&tg);
+                    self.draw_tui();
+                }
+            }
+            DebuggerCommand::Delete(num) => {
+                if !self.delete_breakpoint(num) {
+                    self.emit(Event::Error(format!("No breakpoint number {}.", num)));
+                }
+                self.draw_tui();
+            }
+            DebuggerCommand::SetEnvironment(name, value) => {
+                self.launch
+                    .environment
+                    .get_or_insert_with(|| std::env::vars().collect())
+                    .insert(name, value);
+            }
+            DebuggerCommand::UnsetEnvironment(name) => {
+                let environment = self.launch.environment.get_or_insert_with(|| std::env::vars().collect());
+                match name {
+                    Some(name) => {
+                        environment.remove(&name);
+                    }
+                    None => environment.clear(),
+                }
+            }
+            DebuggerCommand::SetCwd(cwd) => self.launch.cwd = cwd,
+            DebuggerCommand::Tty(tty) => self.launch.tty = tty,
+            DebuggerCommand::Tui(on) => self.set_tui(on),
+            DebuggerCommand::Checkpoint => {
+                let result = self.checkpoint();
+                self.report_error("Checkpoint", result);
+            }
+            DebuggerCommand::InfoCheckpoints => self.info_checkpoints(),
+            DebuggerCommand::Restart(num) => {
+                let result = self.restart(num);
+                self.report_error("Restart", result);
+            }
+            DebuggerCommand::DeleteCheckpoint(num) => self.delete_checkpoint(num),
+            DebuggerCommand::CatchSyscall(names) => self.catch_syscall(&names),
+            DebuggerCommand::TraceCalls(pattern) => self.trace_calls(pattern),
+            DebuggerCommand::LeakCheck(on) => self.leak_check(on),
+            DebuggerCommand::InfoHeap => self.info_heap(),
+            DebuggerCommand::SaveBreakpoints(path) => self.save_breakpoints(&path),
+            DebuggerCommand::TraceSyscalls(on) => {
+                self.trace_syscalls = on;
+                self.update_syscall_tracing();
+            }
+            DebuggerCommand::Source(path) => match read_command_file(&path) {
+                // commands in the sourced file run before the rest of the current script
+                Ok(commands) => self.queue_commands_front(commands, Origin::Script),
+                Err(err) => self.emit(Event::Error(format!("{}: {}", path, err))),
+            },
+            DebuggerCommand::Print(name) => {
+                let result = self.print_variable(&name);
+                self.report_error("Print", result);
+            }
+            DebuggerCommand::Define(name) => {
+                let body = self.read_block();
+                self.user_commands.insert(name, body);
+            }
+            DebuggerCommand::Commands(num) => {
+                let body = self.read_block();
+                let user_breakpoint = match num {
+                    Some(num) => self.user_breakpoints.iter_mut().find(|bp| bp.num == num),
+                    None => self.user_breakpoints.last_mut(),
+                };
+                match user_breakpoint {
+                    Some(user_breakpoint) => user_breakpoint.commands = body,
+                    None => self.emit(Event::Error(format!("No breakpoint number {}.", num.unwrap_or(0)))),
+                }
+            }
+            DebuggerCommand::InfoSymbol(addr) => self.info_symbol(&addr),
+            DebuggerCommand::InfoAddress(name) => self.info_address(&name),
+            DebuggerCommand::InfoFunctions(regex) => self.info_functions_or_variables(regex, true),
+            DebuggerCommand::InfoVariables(regex) => self.info_functions_or_variables(regex, false),
+            DebuggerCommand::InfoTypes(regex) => self.info_types(regex),
+            DebuggerCommand::InfoScope(location) => self.info_scope(&location),
+            DebuggerCommand::InfoLine(location) => self.info_line(location),
+            DebuggerCommand::InfoSource => self.info_source(),
+        }
+        true
+    }
+
+    /// This function returns the next line of input. Lines queued from command files are used
+    /// first; once they run out, the user is prompted (or, in batch mode, None is returned so
+    /// that deet exits). None is also returned when the user presses ctrl+d.
+    fn next_line(&mut self, prompt: &str) -> Option<String> {
+        if let Some((line, origin)) = self.pending_commands.pop_front() {
+            self.origin = origin;
+            return Some(line);
+        }
+        self.origin = Origin::User;
+        match self.interpreter {
+            _ if self.batch => return None,
+            Interpreter::Json => return self.next_json_request(),
+            // the DAP server hands over all input through execute_commands
+            Interpreter::Dap => return None,
+            Interpreter::Console => {}
+        }
+        self.update_completion();
+        loop {
+            // Print prompt and get next line of user input
+            match self.readline.readline(prompt) {
+                Err(ReadlineError::Interrupted) => {
+                    // User pressed ctrl+c. We're going to ignore it
+                    println!("Type \"quit\" to exit");
+                }
+                Err(ReadlineError::Eof) => {
+                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
+                    return None;
+                }
+                Err(err) => {
+                    panic!("Unexpected I/O error: {:?}", err);
+                }
+                Ok(line) => {
+                    if line.trim().is_empty() {
+                        return Some(self.repeat_line.clone().unwrap_or(line));
+                    }
+                    self.readline.add_history_entry(line.as_str()).expect("Can not add history entry");
+                    if let Err(err) = self.readline.save_history(&self.history_path) {
+                        println!(
+                            "Warning: failed to save history file at {}: {}",
+                            self.history_path, err
+                        );
+                    }
+                    self.repeat_line = self.parse_line(&line).filter(DebuggerCommand::repeats).map(|_| line.clone());
+                    return Some(line);
+                }
+            }
+        }
+    }
+
+    /// Reads JSON requests from stdin until a well-formed one arrives, queues its command lines
+    /// and returns the first. Before reading, the previous request is reported as done.
+    fn next_json_request(&mut self) -> Option<String> {
+        loop {
+            if let Some(id) = self.request_id.take() {
+                println!("{}", serde_json::json!({"type": "done", "id": id}));
+            }
+            let mut line = String::new();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => panic!("Unexpected I/O error: {:?}", err),
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match interpreter::parse_request(&line) {
+                Ok((id, commands)) => {
+                    self.request_id = id;
+                    self.pending_commands.extend(commands.into_iter().map(|command| (command, Origin::User)));
+                    if let Some((line, _)) = self.pending_commands.pop_front() {
+                        return Some(line);
+                    }
+                }
+                Err(err) => self.emit(Event::Error(format!("Invalid request: {}", err))),
+            }
+        }
+    }
+}
+
+
+/// Splits `[file:]target` into the file and the line number or function name. Colons in
+/// qualified names such as `hello::shapes::area` don't separate a file.
+fn split_location(location: &str) -> (Option<&str>, &str) {
+    let bytes = location.as_bytes();
+    let separator = (0..bytes.len()).rev().find(|&i| {
+        bytes[i] == b':' && bytes.get(i + 1) != Some(&b':') && (i == 0 || bytes[i - 1] != b':')
+    });
+    match separator {
+        Some(i) => (Some(&location[..i]), &location[i + 1..]),
+        None => (None, location),
+    }
+}
+
+/// Reads a file of debugger commands, one per line.
+fn read_command_file(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?.lines().map(|line| line.to_string()).collect())
+}
+
+/// Substitutes `$argc` and `$arg0`..`$argN` in the body of a user-defined command. Returns the
+/// index of the first referenced argument that was not supplied as an error.
+fn expand_user_command(body: &[String], args: &[&str]) -> Result<Vec<String>, usize> {
+    let mut commands = Vec::with_capacity(body.len());
+    for line in body {
+        let line = line.replace("$argc", &args.len().to_string());
+        let mut expanded = String::new();
+        let mut rest = line.as_str();
+        while let Some(pos) = rest.find("$arg") {
+            expanded.push_str(&rest[..pos]);
+            let after = &rest[pos + 4..];
+            let digits = after.chars().take_while(|c| c.is_ascii_digit()).count();
+            if digits == 0 {
+                expanded.push_str("$arg");
+            } else {
+                let index: usize = after[..digits].parse().unwrap();
+                expanded.push_str(args.get(index).ok_or(index)?);
+            }
+            rest = &after[digits..];
+        }
+        expanded.push_str(rest);
+        commands.push(expanded);
+    }
+    Ok(commands)
+}
+
+/// Returns the names of the program's source files, without their directories.
+fn file_names(debug_data: &DwarfData) -> Vec<String> {
+    debug_data.unit_names().iter().map(|name| name.rsplit('/').next().unwrap_or(name).to_string()).collect()
+}
+
+/// Returns the names of the functions in a symbol table.
+fn function_names(table: &SymbolTable) -> Vec<String> {
+    table.symbols().iter().filter(|symbol| symbol.function).map(|symbol| symbol.name.clone()).collect()
+}
+
+/// Converts a glob, in which `*` matches any text and `?` any one character, into a regular
+/// expression matching whole names.
+fn glob_to_regex(glob: &str) -> Regex {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
         }
     }
+    pattern.push('$');
+    Regex::new(&pattern).expect("escaped glob is a valid regex")
 }
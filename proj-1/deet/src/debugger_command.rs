@@ -1,22 +1,224 @@
+/// The arguments of `run`: the program's arguments, and the files its standard streams are
+/// redirected to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunArgs {
+    pub args: Vec<String>,
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+}
+
+impl RunArgs {
+    /// Parses `run`'s arguments the way a shell would: words are split on whitespace except
+    /// inside single or double quotes or after a backslash, and unquoted `<`, `>` and `2>`
+    /// redirect stdin, stdout and stderr to the word after them.
+    pub fn parse(text: &str) -> Result<RunArgs, String> {
+        let mut run = RunArgs::default();
+        let mut chars = text.chars().peekable();
+        // the stream the next word redirects, if it follows a redirection operator
+        let mut redirect: Option<u8> = None;
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let c = match chars.peek() {
+                Some(&c) => c,
+                None => break,
+            };
+            let operator = match c {
+                '<' => Some(0),
+                '>' => Some(1),
+                '2' if chars.clone().nth(1) == Some('>') => Some(2),
+                _ => None,
+            };
+            if let Some(fd) = operator {
+                if redirect.is_some() {
+                    return Err("Missing file name after redirection.".to_string());
+                }
+                chars.next();
+                if fd == 2 {
+                    chars.next();
+                }
+                redirect = Some(fd);
+                continue;
+            }
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '<' && *c != '>') {
+                match c {
+                    '\\' => word.extend(chars.next()),
+                    '\'' => loop {
+                        match chars.next() {
+                            Some('\'') => break,
+                            Some(c) => word.push(c),
+                            None => return Err("Unterminated quoted string.".to_string()),
+                        }
+                    },
+                    '"' => loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') if matches!(chars.peek(), Some('"' | '\\')) => word.extend(chars.next()),
+                            Some(c) => word.push(c),
+                            None => return Err("Unterminated quoted string.".to_string()),
+                        }
+                    },
+                    c => word.push(c),
+                }
+            }
+            match redirect.take() {
+                Some(0) => run.stdin = Some(word),
+                Some(1) => run.stdout = Some(word),
+                Some(_) => run.stderr = Some(word),
+                None => run.args.push(word),
+            }
+        }
+        if redirect.is_some() {
+            return Err("Missing file name after redirection.".to_string());
+        }
+        Ok(run)
+    }
+}
+
 pub enum DebuggerCommand {
     Quit,
-    Run(Vec<String>),
+    Run(RunArgs),
     Continue,
+    Step,
+    Next,
+    Finish,
     Backtrace,
     Breakpoint(Option<String>),
+    Delete(usize),
+    Source(String),
+    Print(String),
+    Define(String),
+    Commands(Option<usize>),
+    InfoSymbol(String),
+    InfoAddress(String),
+    /// `info functions`, `info variables` and `info types`, with an optional regular expression
+    /// the names have to match.
+    InfoFunctions(Option<String>),
+    InfoVariables(Option<String>),
+    InfoTypes(Option<String>),
+    InfoScope(String),
+    InfoLine(Option<String>),
+    InfoSource,
+    /// `catch syscall`, with the names or numbers of the system calls to catch (all if empty).
+    CatchSyscall(Vec<String>),
+    /// `trace syscalls on|off`
+    TraceSyscalls(bool),
+    /// `trace-calls GLOB`, or `trace-calls off` (None).
+    TraceCalls(Option<String>),
+    /// `set environment VAR=value`
+    SetEnvironment(String, String),
+    /// `unset environment VAR`, or without a name to clear the whole environment.
+    UnsetEnvironment(Option<String>),
+    /// `set cwd DIR`, or without a directory to run in deet's own.
+    SetCwd(Option<String>),
+    /// `tty /dev/pts/N`, or without a terminal to use deet's own.
+    Tty(Option<String>),
+    /// `layout` or `tui enable` switches the terminal UI on, and `tui disable` off.
+    Tui(bool),
+    Checkpoint,
+    InfoCheckpoints,
+    /// `leak-check on|off`, which tracks the inferior's heap allocations.
+    LeakCheck(bool),
+    InfoHeap,
+    /// `save breakpoints FILE`, which writes the breakpoints as commands that `source` reads.
+    SaveBreakpoints(String),
+    Restart(usize),
+    DeleteCheckpoint(usize),
+}
+
+/// The names of the commands, which may be abbreviated to any prefix that is unique among them.
+pub const COMMANDS: &[&str] = &[
+    "backtrace", "break", "catch", "checkpoint", "commands", "continue", "define", "delete", "finish",
+    "info", "layout", "leak-check", "next", "print", "quit", "restart", "run", "save", "set", "source", "step",
+    "trace", "trace-calls", "tty", "tui", "unset",
+];
+
+/// Short names of commands, which take precedence over abbreviations.
+pub const ALIASES: &[(&str, &str)] = &[
+    ("b", "break"),
+    ("back", "backtrace"),
+    ("breakpoint", "break"),
+    ("bt", "backtrace"),
+    ("c", "continue"),
+    ("cont", "continue"),
+    ("d", "delete"),
+    ("i", "info"),
+    ("n", "next"),
+    ("p", "print"),
+    ("q", "quit"),
+    ("r", "run"),
+    ("s", "step"),
+];
+
+/// The subcommands of the commands that have them, which may be abbreviated in the same way.
+pub const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    (
+        "info",
+        &[
+            "address", "checkpoints", "functions", "heap", "line", "scope", "source", "symbol", "types", "variables",
+        ],
+    ),
+    ("catch", &["syscall"]),
+    ("save", &["breakpoints"]),
+    ("delete", &["checkpoint"]),
+    ("set", &["cwd", "environment"]),
+    ("trace", &["syscalls"]),
+    ("tui", &["disable", "enable"]),
+    ("unset", &["environment"]),
+];
+
+/// Finds the name `word` abbreviates among `names`. An exact match wins even if it is also the
+/// prefix of other names; otherwise all the names it is a prefix of are returned as the error.
+pub fn expand_abbreviation(word: &str, names: &[&'static str]) -> Result<&'static str, Vec<&'static str>> {
+    if let Some(name) = names.iter().find(|name| **name == word) {
+        return Ok(name);
+    }
+    let matches: Vec<&'static str> = names.iter().copied().filter(|name| name.starts_with(word)).collect();
+    match matches[..] {
+        [name] => Ok(name),
+        _ => Err(matches),
+    }
+}
+
+/// Finds the command `word` is an alias or abbreviation of.
+pub fn command_name(word: &str) -> Result<&'static str, Vec<&'static str>> {
+    match ALIASES.iter().find(|(alias, _)| *alias == word) {
+        Some((_, command)) => Ok(command),
+        None => expand_abbreviation(word, COMMANDS),
+    }
+}
+
+/// Spells out an abbreviated command name, like gdb, and its subcommand if it has them (a
+/// subcommand that abbreviates nothing is left as it is, as it may be an argument). Returns the
+/// commands an ambiguous abbreviation could mean as the error.
+pub fn expand_abbreviations<'a>(tokens: &[&'a str]) -> Result<Vec<&'a str>, Vec<&'static str>> {
+    let mut expanded = tokens.to_vec();
+    let command = command_name(tokens[0])?;
+    expanded[0] = command;
+    let subcommands = SUBCOMMANDS.iter().find(|(name, _)| *name == command);
+    if let (Some((_, subcommands)), Some(word)) = (subcommands, tokens.get(1)) {
+        if let Ok(subcommand) = expand_abbreviation(word, subcommands) {
+            expanded[1] = subcommand;
+        }
+    }
+    Ok(expanded)
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
+    /// Returns whether an empty line repeats the command, as it does for stepping.
+    pub fn repeats(&self) -> bool {
+        matches!(self, DebuggerCommand::Step | DebuggerCommand::Next)
+    }
+
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
-            "r" | "run" => {
-                let args = tokens[1..].to_vec();
-                Some(DebuggerCommand::Run(
-                    args.iter().map(|s| s.to_string()).collect(),
-                ))
-            },
+            "r" | "run" => Some(DebuggerCommand::Run(RunArgs::parse(&tokens[1..].join(" ")).ok()?)),
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "finish" => Some(DebuggerCommand::Finish),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
             "b" | "break" | "breakpoint" => {
                 if tokens.len() >= 2 {
@@ -26,8 +228,130 @@ impl DebuggerCommand {
                 // command length not satisfy the requirement
                 Some(DebuggerCommand::Breakpoint(None))
             }
+            "d" | "delete" => {
+                if tokens.get(1) == Some(&"checkpoint") {
+                    return Some(DebuggerCommand::DeleteCheckpoint(tokens.get(2)?.parse().ok()?));
+                }
+                if tokens.len() >= 2 {
+                    return Some(DebuggerCommand::Delete(tokens[1].parse().ok()?));
+                }
+                None
+            }
+            "source" => {
+                if tokens.len() >= 2 {
+                    return Some(DebuggerCommand::Source(tokens[1..].join(" ")));
+                }
+                None
+            }
+            "p" | "print" => {
+                if tokens.len() >= 2 {
+                    return Some(DebuggerCommand::Print(tokens[1..].join(" ")));
+                }
+                None
+            }
+            "define" => {
+                if tokens.len() >= 2 {
+                    return Some(DebuggerCommand::Define(tokens[1].to_string()));
+                }
+                None
+            }
+            "commands" => match tokens.get(1) {
+                Some(num) => Some(DebuggerCommand::Commands(Some(num.parse().ok()?))),
+                None => Some(DebuggerCommand::Commands(None)),
+            },
+            "i" | "info" => {
+                let argument = (tokens.len() > 2).then(|| tokens[2..].join(" "));
+                match (tokens.get(1).copied(), argument) {
+                    (Some("symbol"), Some(addr)) => Some(DebuggerCommand::InfoSymbol(addr)),
+                    (Some("address"), Some(name)) => Some(DebuggerCommand::InfoAddress(name)),
+                    (Some("functions"), regex) => Some(DebuggerCommand::InfoFunctions(regex)),
+                    (Some("variables"), regex) => Some(DebuggerCommand::InfoVariables(regex)),
+                    (Some("types"), regex) => Some(DebuggerCommand::InfoTypes(regex)),
+                    (Some("scope"), Some(location)) => Some(DebuggerCommand::InfoScope(location)),
+                    (Some("line"), location) => Some(DebuggerCommand::InfoLine(location)),
+                    (Some("source"), None) => Some(DebuggerCommand::InfoSource),
+                    (Some("checkpoints"), None) => Some(DebuggerCommand::InfoCheckpoints),
+                    (Some("heap"), None) => Some(DebuggerCommand::InfoHeap),
+                    _ => None,
+                }
+            }
+            "catch" if tokens.get(1) == Some(&"syscall") => Some(DebuggerCommand::CatchSyscall(
+                tokens[2..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "trace" if tokens.get(1) == Some(&"syscalls") => match tokens.get(2) {
+                Some(&"on") | None => Some(DebuggerCommand::TraceSyscalls(true)),
+                Some(&"off") => Some(DebuggerCommand::TraceSyscalls(false)),
+                _ => None,
+            },
+            "leak-check" => match tokens.get(1) {
+                Some(&"on") | None => Some(DebuggerCommand::LeakCheck(true)),
+                Some(&"off") => Some(DebuggerCommand::LeakCheck(false)),
+                _ => None,
+            },
+            "trace-calls" => match tokens.get(1) {
+                Some(&"off") => Some(DebuggerCommand::TraceCalls(None)),
+                Some(pattern) => Some(DebuggerCommand::TraceCalls(Some(pattern.to_string()))),
+                None => None,
+            },
+            "set" if tokens.get(1) == Some(&"environment") && tokens.len() > 2 => {
+                // both VAR=value and VAR value are accepted, like in gdb
+                let setting = tokens[2..].join(" ");
+                let (name, value) = match setting.split_once('=') {
+                    Some((name, value)) => (name.trim(), value.trim()),
+                    None => setting.split_once(' ').unwrap_or((&setting, "")),
+                };
+                Some(DebuggerCommand::SetEnvironment(name.to_string(), value.to_string()))
+            }
+            "unset" if tokens.get(1) == Some(&"environment") => {
+                Some(DebuggerCommand::UnsetEnvironment(tokens.get(2).map(|name| name.to_string())))
+            }
+            "set" if tokens.get(1) == Some(&"cwd") => {
+                Some(DebuggerCommand::SetCwd((tokens.len() > 2).then(|| tokens[2..].join(" "))))
+            }
+            "save" if tokens.get(1) == Some(&"breakpoints") && tokens.len() > 2 => {
+                Some(DebuggerCommand::SaveBreakpoints(tokens[2..].join(" ")))
+            }
+            "layout" => Some(DebuggerCommand::Tui(true)),
+            "tui" => match tokens.get(1) {
+                Some(&"enable") => Some(DebuggerCommand::Tui(true)),
+                Some(&"disable") => Some(DebuggerCommand::Tui(false)),
+                _ => None,
+            },
+            "checkpoint" => Some(DebuggerCommand::Checkpoint),
+            "restart" => Some(DebuggerCommand::Restart(tokens.get(1)?.parse().ok()?)),
+            "tty" => Some(DebuggerCommand::Tty(tokens.get(1).map(|tty| tty.to_string()))),
             // Default case:
             _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_abbreviations() {
+        assert_eq!(expand_abbreviations(&["fin"]), Ok(vec!["finish"]));
+        assert_eq!(expand_abbreviations(&["i", "func", "main"]), Ok(vec!["info", "functions", "main"]));
+        assert_eq!(expand_abbreviations(&["set", "env", "A=1"]), Ok(vec!["set", "environment", "A=1"]));
+        // an argument that isn't a subcommand
+        assert_eq!(expand_abbreviations(&["del", "3"]), Ok(vec!["delete", "3"]));
+        assert_eq!(expand_abbreviations(&["d", "check", "1"]), Ok(vec!["delete", "checkpoint", "1"]));
+        assert_eq!(expand_abbreviations(&["t"]), Err(vec!["trace", "trace-calls", "tty", "tui"]));
+        assert_eq!(expand_abbreviations(&["xyz"]), Err(vec![]));
+    }
+
+    #[test]
+    fn test_parse_run_args() {
+        let run = RunArgs::parse(r#"a "b c" 'd "e"' f\ g < in.txt >out.txt 2> err.txt"#).unwrap();
+        assert_eq!(run.args, vec!["a", "b c", "d \"e\"", "f g"]);
+        assert_eq!(run.stdin.as_deref(), Some("in.txt"));
+        assert_eq!(run.stdout.as_deref(), Some("out.txt"));
+        assert_eq!(run.stderr.as_deref(), Some("err.txt"));
+        // quoted operators and a 2 that isn't followed by > are arguments
+        assert_eq!(RunArgs::parse("'>' 2 x2>y").unwrap().args, vec![">", "2", "x2"]);
+        assert!(RunArgs::parse("\"unterminated").is_err());
+        assert!(RunArgs::parse("a >").is_err());
+    }
+}
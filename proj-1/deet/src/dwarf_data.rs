@@ -1,23 +1,125 @@
-use crate::gimli_wrapper;
+use crate::arch::PIE_LOAD_ADDRESS;
+use crate::dwarf_index::{self, NameIndex, UnitRange};
+use crate::gimli_wrapper::{self, UnitHeader};
+use crate::symbols::SymbolTable;
+use addr2line::gimli::{self, Reader, UnwindSection};
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection};
+use std::cell::{OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{fmt, fs};
 
+/// Where distributions install the debugging information stripped from their programs.
+const DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+type Slice = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
 #[derive(Debug)]
 pub enum Error {
     ErrorOpeningFile,
     DwarfFormatError(gimli_wrapper::Error),
 }
 
+/// The debugging information of a program. Compilation units are only read when something in
+/// them is looked up: by address through the units' address ranges, and by name through the
+/// program's name index (.gdb_index or .debug_names) if it has one.
 pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    units: Vec<Unit>,
+    /// The address ranges of the units' code as (begin, end, index in `units`), sorted.
+    unit_ranges: Vec<(usize, usize, usize)>,
+    /// The units (indices in `units`) defining each function or global variable. Without one,
+    /// looking up a name reads every unit.
+    name_index: Option<HashMap<String, Vec<usize>>>,
+    /// The units defining each qualified name of `name_index` by its last component (`area` for
+    /// `shapes::area`), so that unqualified names are found without scanning the index.
+    short_names: HashMap<String, Vec<usize>>,
+    /// What has to be added to the addresses in the debugging information to get the addresses
+    /// of the running program. This is nonzero for position-independent executables.
+    load_bias: usize,
+    addr2line: Context<Slice>,
+    /// The directory of the program, where split DWARF files are also looked for.
+    directory: PathBuf,
+    /// The program's split DWARF package (<program>.dwp), if it has one.
+    package: Option<gimli::DwarfPackage<Slice>>,
+    /// The split DWARF files found missing, which are only reported once each.
+    missing_dwos: RefCell<HashSet<PathBuf>>,
+    /// The program's ELF symbols, for code without debugging information.
+    symbols: SymbolTable,
+    /// The address the program starts running at, once the dynamic linker is done.
+    entry_point: usize,
+    /// The program's build ID (.note.gnu.build-id), which changes whenever it is rebuilt.
+    build_id: Option<Vec<u8>>,
+    /// The program's call frame information, if it has any.
+    call_frames: Option<CallFrames>,
+}
+
+/// The call frame information in .eh_frame, which tells how to find each function's canonical
+/// frame address (the caller's stack pointer before the call) at any instruction.
+struct CallFrames {
+    eh_frame: gimli::EhFrame<Slice>,
+    bases: gimli::BaseAddresses,
+    /// The lookup table of .eh_frame_hdr, without which .eh_frame is searched from its start.
+    header: Option<gimli::ParsedEhFrameHdr<Slice>>,
+}
+
+impl CallFrames {
+    fn from_object(object: &object::File) -> Option<CallFrames> {
+        let section_address = |name| object.section_by_name(name).map(|section| section.address());
+        let mut bases = gimli::BaseAddresses::default().set_eh_frame(section_address(".eh_frame")?);
+        if let Some(address) = section_address(".eh_frame_hdr") {
+            bases = bases.set_eh_frame_hdr(address);
+        }
+        if let Some(address) = section_address(".text") {
+            bases = bases.set_text(address);
+        }
+        if let Some(address) = section_address(".got") {
+            bases = bases.set_got(address);
+        }
+        let header = gimli::EhFrameHdr::from(load_section(object, Some(".eh_frame_hdr")))
+            .parse(&bases, 8)
+            .ok();
+        let mut eh_frame = gimli::EhFrame::from(load_section(object, Some(".eh_frame")));
+        eh_frame.set_address_size(8);
+        Some(CallFrames { eh_frame, bases, header })
+    }
+
+    /// Returns the register and offset the canonical frame address is computed from at `addr`.
+    fn cfa_rule(&self, addr: u64) -> Option<(gimli::Register, i64)> {
+        let mut context = gimli::UnwindContext::new();
+        let get_cie = gimli::EhFrame::cie_from_offset;
+        let row = match self.header.as_ref().and_then(|header| header.table()) {
+            Some(table) => table.unwind_info_for_address(&self.eh_frame, &self.bases, &mut context, addr, get_cie),
+            None => self.eh_frame.unwind_info_for_address(&self.bases, &mut context, addr, get_cie),
+        };
+        match row.ok()?.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => Some((*register, *offset)),
+            gimli::CfaRule::Expression(_) => None,
+        }
+    }
+}
+
+/// A compilation unit, which is read the first time it is needed.
+struct Unit {
+    /// The offset of the unit in .debug_info.
+    offset: usize,
+    name: String,
+    contents: OnceCell<UnitContents>,
+}
+
+#[derive(Default)]
+struct UnitContents {
+    file: File,
+    /// The unit's types, keyed by the offset of their entries in .debug_info.
+    types: HashMap<usize, Type>,
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        let names: Vec<&str> = self.units.iter().map(|unit| unit.name.as_str()).collect();
+        write!(f, "DwarfData {{units: {:?}}}", names)
     }
 }
 
@@ -27,71 +129,503 @@ impl From<gimli_wrapper::Error> for Error {
     }
 }
 
+/// Finds the file holding the debugging information of a program whose own was stripped:
+/// /usr/lib/debug/.build-id/xx/yyyy.debug for a program with build ID xxyyyy, or the file its
+/// .gnu_debuglink section names, next to the program, in a .debug directory next to it, or in
+/// the same directory under /usr/lib/debug.
+fn find_debug_file(path: &str, object: &object::File) -> Option<PathBuf> {
+    if let Ok(Some(build_id)) = object.build_id() {
+        let hex: String = build_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        if hex.len() > 2 {
+            let debug_path = Path::new(DEBUG_FILE_DIRECTORY)
+                .join(".build-id")
+                .join(&hex[..2])
+                .join(format!("{}.debug", &hex[2..]));
+            if debug_path.is_file() {
+                return Some(debug_path);
+            }
+        }
+    }
+    let (name, crc) = object.gnu_debuglink().ok()??;
+    let name = std::str::from_utf8(name).ok()?;
+    let directory = fs::canonicalize(path).ok()?.parent()?.to_path_buf();
+    let candidates = [
+        directory.join(name),
+        directory.join(".debug").join(name),
+        Path::new(DEBUG_FILE_DIRECTORY)
+            .join(directory.strip_prefix("/").ok()?)
+            .join(name),
+    ];
+    // the checksum makes sure the file belongs to this build of the program
+    candidates
+        .into_iter()
+        .find(|candidate| fs::read(candidate).is_ok_and(|data| crc32fast::hash(&data) == crc))
+}
+
+/// Reads the section of `object` named `name`, or an empty one if it has none.
+fn load_section(object: &object::File, name: Option<&str>) -> Slice {
+    let endian = if object.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    let data = name
+        .and_then(|name| object.section_by_name(name))
+        .and_then(|section| section.uncompressed_data().ok());
+    Slice::new(Rc::from(data.as_deref().unwrap_or(&[])), endian)
+}
+
+/// Reads the DWARF sections of a .dwo file.
+fn load_dwo(path: &Path) -> Option<gimli::Dwarf<Slice>> {
+    let data = fs::read(path).ok()?;
+    let object = object::File::parse(&*data).ok()?;
+    gimli::Dwarf::load(|id| Ok::<_, ()>(load_section(&object, id.dwo_name()))).ok()
+}
+
+/// Reads a .dwp package, which gathers the .dwo files of a program.
+fn load_dwarf_package(path: &str) -> Option<gimli::DwarfPackage<Slice>> {
+    let data = fs::read(path).ok()?;
+    let object = object::File::parse(&*data).ok()?;
+    let empty = load_section(&object, None);
+    let load = |skip: Option<gimli::SectionId>| {
+        let section = |id: gimli::SectionId| match Some(id) == skip {
+            true => Ok::<_, gimli::Error>(empty.clone()),
+            false => Ok(load_section(&object, id.dwo_name())),
+        };
+        gimli::DwarfPackage::load(section, empty.clone()).ok()
+    };
+    // binutils' dwp writes a type unit index with no slots when there are no type units, which
+    // gimli rejects, so the package is read again without it
+    load(None).or_else(|| load(Some(gimli::SectionId::DebugTuIndex)))
+}
+
+/// Maps the last component of each qualified name in `names` to the units defining it.
+fn short_name_index(names: &HashMap<String, Vec<usize>>) -> HashMap<String, Vec<usize>> {
+    let mut short_names: HashMap<String, Vec<usize>> = HashMap::new();
+    for (name, units) in names {
+        if let Some((_, short_name)) = name.rsplit_once("::") {
+            short_names.entry(short_name.to_string()).or_default().extend(units);
+        }
+    }
+    short_names
+}
+
 impl DwarfData {
+    /// Reads the debugging information of the program at `path`, assuming it is loaded where
+    /// Linux loads it when address randomization is off.
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+        DwarfData::from_file_loaded_at(path, None)
+    }
+
+    /// Reads the debugging information of the program at `path`, with its addresses moved by
+    /// `load_bias` if given, which is where a running inferior actually loaded it.
+    pub fn from_file_loaded_at(path: &str, load_bias: Option<usize>) -> Result<DwarfData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap2::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
-        let endian = if object.is_little_endian() {
-            gimli::RunTimeEndian::Little
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
+        let load_bias = match load_bias {
+            Some(load_bias) => load_bias,
+            None if object.kind() == object::ObjectKind::Dynamic => PIE_LOAD_ADDRESS,
+            None => 0,
+        };
+        let symbols = SymbolTable::from_object(&object, load_bias);
+        let entry_point = object.entry() as usize + load_bias;
+        let build_id = object.build_id().ok().flatten().map(<[u8]>::to_vec);
+        // the separate debugging information file leaves .eh_frame out
+        let call_frames = CallFrames::from_object(&object);
+        // a stripped program's debugging information is in a separate file
+        let debug_mmap = match object.section_by_name(".debug_info") {
+            Some(_) => None,
+            None => find_debug_file(path, &object).and_then(|debug_path| {
+                let debug_file = fs::File::open(debug_path).ok()?;
+                unsafe { memmap2::Mmap::map(&debug_file).ok() }
+            }),
+        };
+        let debug_object = match &debug_mmap {
+            Some(debug_mmap) => Some(
+                object::File::parse(&**debug_mmap)
+                    .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let object = debug_object.as_ref().unwrap_or(&object);
+        let addr2line = Context::new(object).map_err(gimli_wrapper::Error::from)?;
+        let dwarf = addr2line.dwarf();
+        let units: Vec<Unit> = gimli_wrapper::load_unit_headers(dwarf)?
+            .into_iter()
+            .map(|UnitHeader { offset, name }| Unit {
+                offset,
+                name,
+                contents: OnceCell::new(),
+            })
+            .collect();
+
+        let section = |name| object.section_by_name(name).and_then(|section| section.uncompressed_data().ok());
+        let (name_index, index_ranges): (Option<NameIndex>, Vec<UnitRange>) =
+            match section(".gdb_index").and_then(|data| dwarf_index::parse_gdb_index(&data)) {
+                Some((names, ranges)) => (Some(names), ranges),
+                None => {
+                    let read_str = |offset| {
+                        let name = dwarf.debug_str.get_str(gimli::DebugStrOffset(offset)).ok()?;
+                        Some(name.to_string_lossy().ok()?.into_owned())
+                    };
+                    let names = section(".debug_names")
+                        .and_then(|data| dwarf_index::parse_debug_names(&data, &read_str));
+                    (names, Vec::new())
+                }
+            };
+        let ranges = if index_ranges.is_empty() {
+            gimli_wrapper::load_unit_ranges(dwarf)?
         } else {
-            gimli::RunTimeEndian::Big
+            index_ranges
         };
+
+        let unit_index = |offset: usize| units.binary_search_by_key(&offset, |unit| unit.offset).ok();
+        let mut unit_ranges: Vec<(usize, usize, usize)> = ranges
+            .into_iter()
+            .filter_map(|(begin, end, offset)| {
+                Some((begin as usize + load_bias, end as usize + load_bias, unit_index(offset)?))
+            })
+            .collect();
+        unit_ranges.sort_unstable();
+        let name_index = name_index.map(|names| {
+            names
+                .into_iter()
+                .map(|(name, offsets)| (name, offsets.into_iter().filter_map(unit_index).collect()))
+                .collect()
+        });
+        let short_names = name_index.as_ref().map(short_name_index).unwrap_or_default();
+        let directory = fs::canonicalize(path)
+            .ok()
+            .and_then(|path| Some(path.parent()?.to_path_buf()))
+            .unwrap_or_default();
+        let package = load_dwarf_package(&format!("{}.dwp", path));
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            units,
+            unit_ranges,
+            name_index,
+            short_names,
+            load_bias,
+            addr2line,
+            directory,
+            package,
+            missing_dwos: RefCell::new(HashSet::new()),
+            symbols,
+            entry_point,
+            build_id,
+            call_frames,
         })
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+    pub fn build_id(&self) -> Option<&[u8]> {
+        self.build_id.as_deref()
+    }
+
+    /// Returns how the canonical frame address of the function executing at `addr` is found:
+    /// the register it is computed from, and the offset added to it.
+    pub fn get_cfa_rule(&self, addr: usize) -> Option<(gimli::Register, i64)> {
+        let addr = addr.checked_sub(self.load_bias)?;
+        self.call_frames.as_ref()?.cfa_rule(addr as u64)
+    }
+
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn entry_point(&self) -> usize {
+        self.entry_point
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Finds the split DWARF of a skeleton unit: its unit in the program's .dwp package, or
+    /// otherwise the .dwo file the skeleton names, in its compilation directory or next to the
+    /// program.
+    fn load_split_unit(&self, skeleton: &gimli::Unit<Slice>) -> Option<gimli::Dwarf<Slice>> {
+        let parent = self.addr2line.dwarf();
+        if let (Some(package), Some(dwo_id)) = (&self.package, skeleton.dwo_id) {
+            if let Ok(Some(dwarf)) = package.find_cu(dwo_id, parent) {
+                return Some(dwarf);
+            }
+        }
+        let name = parent.attr_string(skeleton, skeleton.dwo_name().ok()??).ok()?;
+        let name = PathBuf::from(name.to_string_lossy().ok()?.as_ref());
+        let mut path = PathBuf::new();
+        if let Some(dir) = &skeleton.comp_dir {
+            path.push(dir.to_string_lossy().ok()?.as_ref());
+        }
+        path.push(&name);
+        let mut dwarf = load_dwo(&path).or_else(|| load_dwo(&self.directory.join(name.file_name()?)));
+        match &mut dwarf {
+            Some(dwarf) => dwarf.make_dwo(parent),
+            // on stderr, since units are read in the middle of other commands' output
+            None if self.missing_dwos.borrow_mut().insert(path.clone()) => {
+                eprintln!("Could not find split debugging information {}", path.display())
+            }
+            None => {}
+        }
+        dwarf
+    }
+
+    /// Returns the contents of unit `index`, reading it if this is the first time it is needed.
+    fn unit_contents(&self, index: usize) -> &UnitContents {
+        let unit = &self.units[index];
+        unit.contents.get_or_init(|| {
+            let load_split = |skeleton: &gimli::Unit<Slice>| self.load_split_unit(skeleton);
+            let dwarf = self.addr2line.dwarf();
+            match gimli_wrapper::load_unit(dwarf, unit.offset, self.load_bias, &load_split) {
+                Ok((file, types)) => UnitContents { file, types },
+                Err(err) => {
+                    // units are read in the middle of any command, whose output this mustn't
+                    // interrupt (e.g. a JSON or DAP stream)
+                    eprintln!("Could not read debugging information of {}: {:?}", unit.name, err);
+                    UnitContents::default()
+                }
+            }
         })
     }
 
-    #[allow(dead_code)]
-    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+    fn unit_file(&self, index: usize) -> &File {
+        &self.unit_contents(index).file
+    }
+
+    /// Returns a row of unit `index`'s line table with its file as the path `get_line_from_addr`
+    /// reports, which is absolute even if the unit's name is relative to the compilation
+    /// directory.
+    fn unit_line(&self, index: usize, row: &Line) -> Line {
+        let file = self.unit_file(index);
+        Line {
+            file: Path::new(&file.comp_dir).join(&row.file).to_string_lossy().into_owned(),
+            ..row.clone()
+        }
+    }
+
+    /// Returns the index of the unit whose code contains `curr_addr`.
+    fn unit_containing(&self, curr_addr: usize) -> Option<usize> {
+        // the last range starting at or before the address
+        let after = self.unit_ranges.partition_point(|(begin, _, _)| *begin <= curr_addr);
+        let (_, end, index) = self.unit_ranges[after.checked_sub(1)?];
+        (curr_addr < end).then_some(index)
+    }
+
+    /// Finds the units of a source file by name. A bare file name matches a file in any
+    /// directory, and an absolute path (as sent by editors) matches a file recorded relative to
+    /// the build directory. Without a file name, this is the first unit.
+    fn units_of_file(&self, file: Option<&str>) -> Vec<usize> {
+        let file = match file {
+            Some(file) => file,
+            None => return if self.units.is_empty() { Vec::new() } else { vec![0] },
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        let matching: Vec<usize> = (0..self.units.len())
+            .filter(|index| {
+                let name = &self.units[*index].name;
+                *name == file
+                    || (!file.contains('/') && name.ends_with(&format!("/{}", file)))
+                    || (file.starts_with('/') && file.ends_with(&format!("/{}", name)))
+            })
+            .collect();
+        // rustc splits a crate into several units with the same name
+        match matching.first() {
+            Some(first) => {
+                let name = &self.units[*first].name;
+                matching.into_iter().filter(|index| self.units[*index].name == *name).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the units that may define `name` (a function or global variable, possibly
+    /// qualified), limited to the units of `file` if given.
+    fn units_defining(&self, file: Option<&str>, name: &str) -> Vec<usize> {
+        let mut candidates: Vec<usize> = match &self.name_index {
+            Some(names) => match names.get(name) {
+                Some(units) => units.clone(),
+                None if !name.contains("::") => self.short_names.get(name).cloned().unwrap_or_default(),
+                None => Vec::new(),
+            },
+            // without an index, every unit is searched
+            None => (0..self.units.len()).collect(),
+        };
+        candidates.sort_unstable();
+        candidates.dedup();
+        match file {
+            Some(_) => {
+                let units = self.units_of_file(file);
+                candidates.retain(|index| units.contains(index));
+                candidates
+            }
+            None => candidates,
+        }
     }
 
     #[allow(dead_code)]
+    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
+        let lines: Vec<&Line> = self
+            .units_of_file(file)
+            .into_iter()
+            .flat_map(|index| self.unit_file(index).lines.iter())
+            .collect();
+        // line tables aren't sorted by line number, so find the closest line that has code and
+        // then the first address of that line
+        let closest = lines
+            .iter()
+            .map(|line| line.number)
+            .filter(|number| *number >= line_number)
+            .min()?;
+        lines
+            .iter()
+            .filter(|line| line.number == closest)
+            .map(|line| line.address)
+            .min()
+    }
+
+    /// Finds a function by its name or by its path (e.g. `krate::module::function` or
+    /// `ns::Class::method`).
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
+        self.units_defining(file, func_name).into_iter().find_map(|index| {
+            self.unit_file(index)
+                .functions
+                .iter()
+                .find(|func| func.name == func_name || func.qualified_name == func_name)
+                .map(|func| func.address)
+        })
+    }
+
+    /// Returns the address of the first line of `func_name`'s body, i.e. the address right after
+    /// the prologue that sets up its stack frame. Breakpoints on a function are placed here so
+    /// that its variables can be read once it is hit.
+    pub fn get_addr_after_prologue(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        let func_addr = self.get_addr_for_function(file, func_name)?;
+        let func = self.get_function_containing(func_addr)?;
+        Some(self.get_prologue_end(func))
+    }
+
+    /// Returns the addresses where the copies of `func_name` inlined into other functions are
+    /// entered.
+    pub fn get_inlined_addrs(&self, file: Option<&str>, func_name: &str) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .units_defining(file, func_name)
+            .into_iter()
+            .flat_map(|index| self.unit_file(index).inlined_calls.iter())
+            .filter(|call| call.name == func_name || call.qualified_name == func_name)
+            .map(|call| call.address)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Returns the address of the first line of `func`'s body, or its entry point if the line
+    /// table has no later line for it.
+    pub fn get_prologue_end(&self, func: &Function) -> usize {
+        let index = match self.unit_containing(func.address) {
+            Some(index) => index,
+            None => return func.address,
+        };
+        self.unit_file(index)
+            .lines
+            .iter()
+            .map(|line| line.address)
+            .filter(|addr| *addr > func.address && *addr < func.address + func.text_length)
+            .min()
+            .unwrap_or(func.address)
+    }
+
+    /// Returns the line whose code starts exactly at `curr_addr`, if any.
+    pub fn get_line_starting_at(&self, curr_addr: usize) -> Option<Line> {
+        let index = self.unit_containing(curr_addr)?;
+        let row = self.unit_file(index).lines.iter().find(|line| line.address == curr_addr)?;
+        Some(self.unit_line(index, row))
+    }
+
+    /// Returns the function whose code contains `curr_addr`.
+    pub fn get_function_containing(&self, curr_addr: usize) -> Option<&Function> {
+        let index = self.unit_containing(curr_addr)?;
+        self.unit_file(index).functions.iter().find(|func| {
+            func.address <= curr_addr && curr_addr < func.address + func.text_length
+        })
+    }
+
+    /// Looks up a variable visible at `curr_addr`: a local variable or parameter of the
+    /// function containing that address, or otherwise a global variable.
+    pub fn get_variable(&self, curr_addr: usize, name: &str) -> Option<&Variable> {
+        if let Some(func) = self.get_function_containing(curr_addr) {
+            if let Some(var) = func.variables.iter().find(|var| var.name == name) {
+                return Some(var);
             }
         }
+        // globals of the current unit come first
+        let mut units = self.units_defining(None, name);
+        if let Some(current) = self.unit_containing(curr_addr) {
+            units.insert(0, current);
+        }
+        units.into_iter().find_map(|index| {
+            self.unit_file(index)
+                .global_variables
+                .iter()
+                .find(|var| var.name == name)
+        })
+    }
+
+    /// Returns the file of the unit containing `curr_addr`.
+    pub fn get_file_containing(&self, curr_addr: usize) -> Option<&File> {
+        Some(self.unit_file(self.unit_containing(curr_addr)?))
+    }
+
+    /// Reads every compilation unit, for queries over the whole program, and returns the file
+    /// and the types of each.
+    pub fn all_units(&self) -> Vec<(&File, &HashMap<usize, Type>)> {
+        (0..self.units.len())
+            .map(|index| {
+                let contents = self.unit_contents(index);
+                (&contents.file, &contents.types)
+            })
+            .collect()
+    }
+
+    /// Returns the names of the compilation units' source files, without reading the units.
+    pub fn unit_names(&self) -> Vec<&str> {
+        self.units.iter().map(|unit| unit.name.as_str()).collect()
+    }
+
+    /// Returns the line `curr_addr` is in, along with the address its code ends at: that of the
+    /// next row of the line table that is for another line.
+    pub fn get_line_range(&self, curr_addr: usize) -> Option<(Line, usize)> {
+        let index = self.unit_containing(curr_addr)?;
+        let mut rows: Vec<&Line> = self.unit_file(index).lines.iter().collect();
+        rows.sort_by_key(|row| row.address);
+        let start = rows.partition_point(|row| row.address <= curr_addr).checked_sub(1)?;
+        let line = self.unit_line(index, rows[start]);
+        let end = rows[start..]
+            .iter()
+            .find(|row| row.number != line.number)
+            .map_or(curr_addr, |row| row.address);
+        Some((line, end))
+    }
+
+    /// Returns the type whose entry is at `offset` in .debug_info.
+    pub fn get_type(&self, offset: usize) -> Option<&Type> {
+        let index = match offset >> 32 {
+            // the last unit starting before the entry
+            0 => self.units.partition_point(|unit| unit.offset <= offset).checked_sub(1)?,
+            // a type of a split unit, keyed as gimli_wrapper::split_type_base describes
+            skeleton => self
+                .units
+                .binary_search_by_key(&(skeleton - 1), |unit| unit.offset)
+                .ok()?,
+        };
+        self.unit_contents(index).types.get(&offset)
     }
 
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location(curr_addr.checked_sub(self.load_bias)?.try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -102,67 +636,156 @@ impl DwarfData {
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+        self.get_frames_from_addr(curr_addr).into_iter().next()?.0
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
-
-            println!("Global variables:");
-            for var in &file.global_variables {
-                println!(
-                    "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
-                );
-            }
+    /// Returns the chain of functions executing at `curr_addr`, innermost first: the functions
+    /// inlined there, and finally the function their code was inlined into. Each comes with the
+    /// line it is at, which for the outer ones is where the inner one was inlined.
+    pub fn get_frames_from_addr(&self, curr_addr: usize) -> Vec<(Option<String>, Option<Line>)> {
+        let probe = match curr_addr.checked_sub(self.load_bias) {
+            Some(probe) => probe as u64,
+            None => return Vec::new(),
+        };
+        let mut frames = match self.addr2line.find_frames(probe) {
+            Ok(frames) => frames,
+            Err(_) => return Vec::new(),
+        };
+        let mut result = Vec::new();
+        while let Ok(Some(frame)) = frames.next() {
+            let function = frame
+                .function
+                .and_then(|function| function.raw_name().ok().map(|name| demangle(&name)));
+            let line = frame.location.and_then(|location| {
+                Some(Line {
+                    file: location.file?.to_string(),
+                    number: location.line?.try_into().unwrap(),
+                    address: curr_addr,
+                })
+            });
+            result.push((function, line));
+        }
+        // addr2line doesn't read split DWARF, so it knows the lines of split units but not
+        // their functions
+        if let Some((function @ None, _)) = result.last_mut() {
+            *function = self
+                .get_function_containing(curr_addr)
+                .map(|func| func.qualified_name.clone());
+        }
+        result
+    }
 
-            println!("Functions:");
-            for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
-                for var in &func.variables {
-                    println!(
-                        "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
-                    );
-                }
-            }
+    /// Returns how many inlined functions are executing at `curr_addr`.
+    pub fn get_inline_depth(&self, curr_addr: usize) -> usize {
+        self.get_frames_from_addr(curr_addr).len().saturating_sub(1)
+    }
+}
 
-            println!("Line numbers:");
-            for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+/// Demangles a Rust (legacy or v0) or Itanium C++ symbol name. Other names are returned as is.
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // the alternate format leaves out the hash at the end of legacy Rust symbols
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
             }
         }
     }
+    name.to_string()
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
-        }
+    pub fn new(name: String, size: usize, kind: TypeKind) -> Self {
+        Type { name, size, kind }
     }
 }
 
+/// The shape of a type. Types refer to other types by their offset in .debug_info, which is the
+/// key to look them up with `DwarfData::get_type`.
+#[derive(Debug, Clone, Default)]
+pub enum TypeKind {
+    Base(Encoding),
+    /// A pointer or reference, and the type it points to (None for `void *`).
+    Pointer(Option<usize>),
+    /// A structure, class or union.
+    Struct(Struct),
+    /// A C-style enumeration, with the names and values of its enumerators.
+    Enum(Vec<(String, i64)>),
+    Array { element: usize, count: Option<usize> },
+    /// A typedef or a const/volatile qualified type (None for `const void`).
+    Alias(Option<usize>),
+    #[default]
+    Unknown,
+}
+
+/// How the bits of a base type are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    Float,
+    Boolean,
+    SignedChar,
+    UnsignedChar,
+    /// A Unicode scalar value, such as Rust's `char`.
+    Utf,
+    Other,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Struct {
+    pub members: Vec<Member>,
+    /// The generic parameters of the type, e.g. `T` of `Vec<T>`.
+    pub template_params: Vec<(String, usize)>,
+    /// Set for Rust enums, which are structures holding one of several variants.
+    pub variants: Option<Variants>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// The offset of the member from the start of the structure.
+    pub offset: usize,
+    pub type_offset: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Variants {
+    /// The member holding the discriminant, which tells which variant is present.
+    pub discriminant: Option<Member>,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The discriminant value of the variant. The variant without a value is the one present
+    /// when no other variant's value matches.
+    pub discr_value: Option<u64>,
+    pub member: Member,
+}
+
+/// What the frame base that local variable locations are relative to is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameBase {
+    /// The canonical frame address (gcc).
+    #[default]
+    Cfa,
+    /// The value of the frame pointer, %rbp or x29 (clang and rustc with frame pointers).
+    FramePointer,
+    /// The value of the stack pointer (rustc without frame pointers).
+    StackPointer,
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
@@ -191,23 +814,50 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    /// Set for a function's parameters (DW_TAG_formal_parameter), unset for its locals.
+    pub parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct Function {
     pub name: String,
+    /// The name including the enclosing namespaces, modules and types, e.g. `krate::module::f`.
+    pub qualified_name: String,
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
+    pub frame_base: FrameBase,
     pub variables: Vec<Variable>,
+    /// None for functions that return nothing.
+    pub return_type: Option<Type>,
+}
+
+/// A copy of a function inlined into another one (DW_TAG_inlined_subroutine).
+#[derive(Debug, Default, Clone)]
+pub struct InlinedCall {
+    pub name: String,
+    pub qualified_name: String,
+    /// Where the inlined code is entered.
+    pub address: usize,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    /// The directory the unit was compiled in, which relative file names are relative to.
+    pub comp_dir: String,
+    /// The compiler that produced the unit (DW_AT_producer).
+    pub producer: Option<String>,
+    /// The source language, named like gdb does (e.g. "c", "c++", "rust").
+    pub language: Option<String>,
+    pub dwarf_version: u16,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
+    pub inlined_calls: Vec<InlinedCall>,
     pub lines: Vec<Line>,
+    /// The addresses of the rows of `lines` that are marked as statements (is_stmt): the
+    /// places recommended for breakpoints.
+    pub statements: HashSet<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -224,3 +874,30 @@ impl fmt::Display for Line {
 }
 
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(demangle("_ZN5hello6shapes4area17h0123456789abcdefE"), "hello::shapes::area");
+        assert_eq!(demangle("_RNvNtCs1234_5hello6shapes4area"), "hello::shapes::area");
+        assert_eq!(demangle("_ZN3foo3barEi"), "foo::bar(int)");
+        assert_eq!(demangle("main"), "main");
+    }
+
+    #[test]
+    fn test_short_name_index() {
+        let names = HashMap::from([
+            ("main".to_string(), vec![0]),
+            ("shapes::area".to_string(), vec![1]),
+            ("hello::shapes::area".to_string(), vec![2, 3]),
+        ]);
+        let short_names = short_name_index(&names);
+        let mut units = short_names["area"].clone();
+        units.sort_unstable();
+        assert_eq!(units, vec![1, 2, 3]);
+        assert!(!short_names.contains_key("main"));
+    }
+}
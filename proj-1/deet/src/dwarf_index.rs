@@ -0,0 +1,292 @@
+//! Readers for the accelerator tables that map names to compilation units, so that a function can
+//! be found without reading every unit: `.gdb_index` (written by `gdb-add-index`, or by gold and
+//! lld with `--gdb-index`) and DWARF 5's `.debug_names`.
+
+use gimli::{EndianSlice, LittleEndian, Reader};
+use std::collections::HashMap;
+
+/// The compilation units defining each name, as offsets of the units in .debug_info.
+pub type NameIndex = HashMap<String, Vec<usize>>;
+
+/// An address range `[begin, end)` and the .debug_info offset of the unit whose code it is.
+pub type UnitRange = (u64, u64, usize);
+
+type Slice<'a> = EndianSlice<'a, LittleEndian>;
+
+/// Reads a `.gdb_index` section (versions 7 and 8), which lists both the names defined by each
+/// unit and the address ranges of its code.
+pub fn parse_gdb_index(data: &[u8]) -> Option<(NameIndex, Vec<UnitRange>)> {
+    let mut header = Slice::new(data, LittleEndian);
+    let version = header.read_u32().ok()?;
+    if !(7..=8).contains(&version) {
+        return None;
+    }
+    let mut offsets = [0usize; 5];
+    for offset in &mut offsets {
+        *offset = header.read_u32().ok()? as usize;
+    }
+    let [cu_list, types_cu_list, address_area, symbol_table, constant_pool] = offsets;
+    let area = |start: usize, end: usize| {
+        data.get(start..end)
+            .map(|bytes| Slice::new(bytes, LittleEndian))
+    };
+
+    let mut cus = area(cu_list, types_cu_list)?;
+    let mut unit_offsets = Vec::new();
+    while !cus.is_empty() {
+        unit_offsets.push(cus.read_u64().ok()? as usize);
+        cus.skip(8).ok()?;
+    }
+
+    let mut addresses = area(address_area, symbol_table)?;
+    let mut ranges = Vec::new();
+    while !addresses.is_empty() {
+        let begin = addresses.read_u64().ok()?;
+        let end = addresses.read_u64().ok()?;
+        let cu = addresses.read_u32().ok()? as usize;
+        if let Some(offset) = unit_offsets.get(cu) {
+            ranges.push((begin, end, *offset));
+        }
+    }
+
+    let pool = data.get(constant_pool..)?;
+    let mut symbols = area(symbol_table, constant_pool)?;
+    let mut names = NameIndex::new();
+    while !symbols.is_empty() {
+        let name_offset = symbols.read_u32().ok()? as usize;
+        let vector_offset = symbols.read_u32().ok()? as usize;
+        if name_offset == 0 && vector_offset == 0 {
+            // an empty slot of the hash table
+            continue;
+        }
+        let mut name = Slice::new(pool.get(name_offset..)?, LittleEndian);
+        let name = name
+            .read_null_terminated_slice()
+            .ok()?
+            .to_string_lossy()
+            .into_owned();
+        let mut vector = Slice::new(pool.get(vector_offset..)?, LittleEndian);
+        let count = vector.read_u32().ok()?;
+        let units = names.entry(name).or_default();
+        for _ in 0..count {
+            // the low 24 bits are the unit; the rest describe the kind of symbol
+            let cu = (vector.read_u32().ok()? & 0xff_ffff) as usize;
+            // units after the compilation units are type units, which have no code
+            if let Some(offset) = unit_offsets.get(cu) {
+                if !units.contains(offset) {
+                    units.push(*offset);
+                }
+            }
+        }
+    }
+    Some((names, ranges))
+}
+
+/// Reads a `.debug_names` section, which may hold one name index per compilation unit or one for
+/// the whole program. `read_str` reads a string from .debug_str.
+pub fn parse_debug_names(
+    data: &[u8],
+    read_str: &dyn Fn(usize) -> Option<String>,
+) -> Option<NameIndex> {
+    let mut names = NameIndex::new();
+    let mut section = Slice::new(data, LittleEndian);
+    while !section.is_empty() {
+        let mut length = section.read_u32().ok()? as usize;
+        let offset_size = if length == 0xffff_ffff {
+            length = section.read_u64().ok()? as usize;
+            8
+        } else {
+            4
+        };
+        let index = section.split(length).ok()?;
+        parse_name_index(index, offset_size, read_str, &mut names)?;
+    }
+    Some(names)
+}
+
+fn read_offset(input: &mut Slice, offset_size: usize) -> Option<usize> {
+    match offset_size {
+        8 => input.read_u64().ok().map(|offset| offset as usize),
+        _ => input.read_u32().ok().map(|offset| offset as usize),
+    }
+}
+
+/// An abbreviation of the entries in a name index: the tag of the DIE they describe and the
+/// (index attribute, form) pairs they consist of.
+struct Abbreviation {
+    tag: u64,
+    attributes: Vec<(u64, u64)>,
+}
+
+/// Reads one name index of a `.debug_names` section, after its unit length, into `names`.
+fn parse_name_index(
+    mut input: Slice,
+    offset_size: usize,
+    read_str: &dyn Fn(usize) -> Option<String>,
+    names: &mut NameIndex,
+) -> Option<()> {
+    if input.read_u16().ok()? != 5 {
+        return None;
+    }
+    input.skip(2).ok()?;
+    let comp_unit_count = input.read_u32().ok()? as usize;
+    let local_type_unit_count = input.read_u32().ok()? as usize;
+    let foreign_type_unit_count = input.read_u32().ok()? as usize;
+    let bucket_count = input.read_u32().ok()? as usize;
+    let name_count = input.read_u32().ok()? as usize;
+    let abbrev_table_size = input.read_u32().ok()? as usize;
+    let augmentation_size = input.read_u32().ok()? as usize;
+    input.skip(augmentation_size).ok()?;
+
+    let mut unit_offsets = Vec::new();
+    for _ in 0..comp_unit_count {
+        unit_offsets.push(read_offset(&mut input, offset_size)?);
+    }
+    input
+        .skip(local_type_unit_count * offset_size + foreign_type_unit_count * 8 + bucket_count * 4)
+        .ok()?;
+    if bucket_count > 0 {
+        input.skip(name_count * 4).ok()?;
+    }
+    let mut string_offsets = Vec::new();
+    for _ in 0..name_count {
+        string_offsets.push(read_offset(&mut input, offset_size)?);
+    }
+    let mut entry_offsets = Vec::new();
+    for _ in 0..name_count {
+        entry_offsets.push(read_offset(&mut input, offset_size)?);
+    }
+
+    let mut abbrev_table = input.split(abbrev_table_size).ok()?;
+    let mut abbreviations = HashMap::new();
+    loop {
+        let code = abbrev_table.read_uleb128().ok()?;
+        if code == 0 {
+            break;
+        }
+        let tag = abbrev_table.read_uleb128().ok()?;
+        let mut attributes = Vec::new();
+        loop {
+            let index = abbrev_table.read_uleb128().ok()?;
+            let form = abbrev_table.read_uleb128().ok()?;
+            if index == 0 && form == 0 {
+                break;
+            }
+            attributes.push((index, form));
+        }
+        abbreviations.insert(code, Abbreviation { tag, attributes });
+    }
+
+    // the entry pool is what remains of the name index
+    let pool = input;
+    for (string_offset, entry_offset) in string_offsets.into_iter().zip(entry_offsets) {
+        let name = match read_str(string_offset) {
+            Some(name) => name,
+            None => continue,
+        };
+        let mut entries = pool;
+        entries.skip(entry_offset).ok()?;
+        loop {
+            let code = entries.read_uleb128().ok()?;
+            if code == 0 {
+                break;
+            }
+            let abbreviation = abbreviations.get(&code)?;
+            // with a single unit, entries don't say which one they are in
+            let mut unit = if comp_unit_count == 1 { Some(0) } else { None };
+            let mut type_unit = false;
+            for (index, form) in &abbreviation.attributes {
+                let value = read_form(&mut entries, *form, offset_size)?;
+                match *index {
+                    1 => unit = Some(value as usize),
+                    2 => type_unit = true,
+                    _ => {}
+                }
+            }
+            let is_code = abbreviation.tag == gimli::DW_TAG_subprogram.0 as u64
+                || abbreviation.tag == gimli::DW_TAG_variable.0 as u64
+                || abbreviation.tag == gimli::DW_TAG_inlined_subroutine.0 as u64;
+            if let (false, true, Some(offset)) = (
+                type_unit,
+                is_code,
+                unit.and_then(|unit| unit_offsets.get(unit)),
+            ) {
+                let units = names.entry(name.clone()).or_default();
+                if !units.contains(offset) {
+                    units.push(*offset);
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Reads a value of the forms used in `.debug_names` entries.
+fn read_form(input: &mut Slice, form: u64, offset_size: usize) -> Option<u64> {
+    let form = gimli::DwForm(form as u16);
+    Some(match form {
+        gimli::DW_FORM_flag_present => 1,
+        gimli::DW_FORM_data1 | gimli::DW_FORM_ref1 | gimli::DW_FORM_flag => {
+            input.read_u8().ok()? as u64
+        }
+        gimli::DW_FORM_data2 | gimli::DW_FORM_ref2 => input.read_u16().ok()? as u64,
+        gimli::DW_FORM_data4 | gimli::DW_FORM_ref4 => input.read_u32().ok()? as u64,
+        gimli::DW_FORM_data8 | gimli::DW_FORM_ref8 | gimli::DW_FORM_ref_sig8 => {
+            input.read_u64().ok()?
+        }
+        gimli::DW_FORM_udata | gimli::DW_FORM_ref_udata => input.read_uleb128().ok()?,
+        gimli::DW_FORM_sdata => input.read_sleb128().ok()? as u64,
+        gimli::DW_FORM_sec_offset => read_offset(input, offset_size)? as u64,
+        gimli::DW_FORM_data16 => {
+            input.skip(16).ok()?;
+            0
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_gdb_index() {
+        // header, two units, one address range, a symbol table of two slots and the pool
+        let mut data = u32s(&[7, 24, 56, 56, 76, 92]);
+        data.extend(u32s(&[0x0, 0, 0x40, 0]));
+        data.extend(u32s(&[0x40, 0, 0x80, 0]));
+        data.extend(u32s(&[0x1000, 0, 0x1100, 0, 1]));
+        data.extend(u32s(&[0, 0, 0, 8]));
+        data.extend(b"main\0\0\0\0");
+        data.extend(u32s(&[1, 1]));
+        let (names, ranges) = parse_gdb_index(&data).unwrap();
+        assert_eq!(names.get("main"), Some(&vec![0x40]));
+        assert_eq!(ranges, vec![(0x1000, 0x1100, 0x40)]);
+    }
+
+    #[test]
+    fn test_parse_debug_names() {
+        let mut index = vec![5, 0, 0, 0];
+        // 2 units, no type units, no buckets, 1 name, 7 bytes of abbreviations, no augmentation
+        index.extend(u32s(&[2, 0, 0, 0, 1, 7, 0]));
+        index.extend(u32s(&[0x0, 0x80]));
+        // the name's string and entry offsets
+        index.extend(u32s(&[0x10, 0]));
+        // abbreviation 1: a subprogram with its unit as data1
+        index.extend([1, 0x2e, 1, 0x0b, 0, 0, 0]);
+        // one entry in the second unit
+        index.extend([1, 1, 0]);
+        let mut data = u32s(&[index.len() as u32]);
+        data.extend(index);
+        let read_str = |offset: usize| (offset == 0x10).then(|| "square".to_string());
+        let names = parse_debug_names(&data, &read_str).unwrap();
+        assert_eq!(names.get("square"), Some(&vec![0x80]));
+    }
+}
@@ -0,0 +1,552 @@
+//! A stub speaking the GDB Remote Serial Protocol, so that gdb, lldb or another debugger can
+//! control an inferior over TCP (`deet --gdbserver [host]:port target args...`). Only the core
+//! packets are supported: registers (`g`/`G`/`p`/`P`), memory (`m`/`M`), software breakpoints
+//! (`Z0`/`z0`), resuming (`c`/`C`/`s`/`S`/`vCont`), stop reasons (`?`), `qSupported`, kill and
+//! detach. Sending the interrupt byte (ctrl+c in gdb) stops a running inferior with SIGINT.
+
+use crate::arch::{self, Registers, GDB_REGISTERS};
+use crate::debugger::Breakpoint;
+use crate::inferior::{Inferior, LaunchOptions, Status};
+use nix::sys::ptrace;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Encodes register `num` of `GDB_REGISTERS` as little-endian hex.
+fn encode_register(regs: &mut Registers, num: usize) -> Option<String> {
+    let size = GDB_REGISTERS.get(num)?.1;
+    let value = *regs.gdb_register_mut(num)?;
+    Some(encode_hex(&value.to_le_bytes()[..size]))
+}
+
+/// Sets register `num` from little-endian hex. Returns the number of hex digits consumed.
+fn decode_register(regs: &mut Registers, num: usize, hex: &str) -> Option<usize> {
+    let size = GDB_REGISTERS.get(num)?.1;
+    let bytes = decode_hex(hex.get(..2 * size)?)?;
+    let mut raw = [0u8; 8];
+    raw[..size].copy_from_slice(&bytes);
+    *regs.gdb_register_mut(num)? = u64::from_le_bytes(raw);
+    Some(2 * size)
+}
+
+/// Linux signal numbers that differ from gdb's target-independent numbering, as (Linux, gdb)
+/// pairs. The other common signals have the same number in both.
+const SIGNAL_NUMBERS: [(i32, i32); 11] = [
+    (7, 10),  // SIGBUS
+    (10, 30), // SIGUSR1
+    (12, 31), // SIGUSR2
+    (17, 20), // SIGCHLD
+    (18, 19), // SIGCONT
+    (19, 17), // SIGSTOP
+    (20, 18), // SIGTSTP
+    (23, 16), // SIGURG
+    (29, 23), // SIGIO
+    (30, 32), // SIGPWR
+    (31, 12), // SIGSYS
+];
+
+fn signal_to_gdb(signal: Signal) -> i32 {
+    let signal = signal as i32;
+    SIGNAL_NUMBERS
+        .iter()
+        .find(|(linux, _)| *linux == signal)
+        .map_or(signal, |(_, gdb)| *gdb)
+}
+
+fn signal_from_gdb(hex: &str) -> Option<Signal> {
+    let signal = i32::from_str_radix(hex, 16).ok()?;
+    let signal = SIGNAL_NUMBERS
+        .iter()
+        .find(|(_, gdb)| *gdb == signal)
+        .map_or(signal, |(linux, _)| *linux);
+    Signal::try_from(signal).ok()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+/// Something received from the client.
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    /// The contents of a packet, and whether its checksum was correct.
+    Packet(String, bool),
+    /// The interrupt byte, sent when the user presses ctrl+c.
+    Interrupt,
+}
+
+/// Splits the bytes received from the client into packets. Acknowledgements are dropped.
+struct PacketReader<R> {
+    reader: R,
+}
+
+impl<R: Read> PacketReader<R> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Returns the next input, or None once the client disconnects.
+    fn next_input(&mut self) -> io::Result<Option<Input>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(Input::Interrupt)),
+                Some(b'$') => break,
+                // acknowledgements, or noise between packets
+                Some(_) => continue,
+            }
+        }
+        let mut data = Vec::new();
+        // the checksum is over the bytes as sent, escapes included
+        let mut sent_sum = 0u8;
+        loop {
+            let byte = match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => byte,
+            };
+            sent_sum = sent_sum.wrapping_add(byte);
+            if byte == b'}' {
+                // the escaped byte is xor'ed with 0x20
+                match self.read_byte()? {
+                    Some(escaped) => {
+                        sent_sum = sent_sum.wrapping_add(escaped);
+                        data.push(escaped ^ 0x20);
+                    }
+                    None => return Ok(None),
+                }
+            } else {
+                data.push(byte);
+            }
+        }
+        let mut sum = [0u8; 2];
+        self.reader.read_exact(&mut sum)?;
+        let expected = std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+        let data = String::from_utf8_lossy(&data).into_owned();
+        Ok(Some(Input::Packet(data, expected == Some(sent_sum))))
+    }
+}
+
+struct Stub {
+    inferior: Option<Inferior>,
+    breakpoints: HashMap<usize, Option<Breakpoint>>,
+    last_status: Status,
+    stream: TcpStream,
+    /// Set once the client asks for `QStartNoAckMode`, after which packets are not
+    /// acknowledged.
+    no_ack: bool,
+}
+
+/// Starts `target` and serves the first client to connect to `listen_addr` until it kills or
+/// detaches from the inferior, or disconnects.
+pub fn serve(target: &str, args: &[String], listen_addr: &str) -> io::Result<()> {
+    // like gdbserver, ":port" listens on all interfaces
+    let listen_addr = match listen_addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => listen_addr.to_string(),
+    };
+    let inferior = Inferior::new(target, args, &LaunchOptions::default())
+        .map_err(|err| io::Error::other(format!("{}: {}", target, err)))?;
+    let pid = inferior.pid();
+    eprintln!("Process {} created; pid = {}", target, pid);
+    let listener = TcpListener::bind(&listen_addr)?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    let (stream, client_addr) = listener.accept()?;
+    eprintln!("Remote debugging from host {}", client_addr);
+
+    let inputs = spawn_reader(stream.try_clone()?, pid);
+    let pc = Registers::read(pid).map_or(0, |regs| regs.pc());
+    let mut stub = Stub {
+        inferior: Some(inferior),
+        breakpoints: HashMap::new(),
+        last_status: Status::Stopped(Signal::SIGTRAP, pc),
+        stream,
+        no_ack: false,
+    };
+    for (data, checksum_ok) in inputs {
+        if !stub.no_ack {
+            stub.stream.write_all(if checksum_ok { b"+" } else { b"-" })?;
+            if !checksum_ok {
+                continue;
+            }
+        }
+        let reply = stub.handle(&data);
+        stub.send(&reply)?;
+        if stub.inferior.is_none() && (data == "k" || data.starts_with('D') || data.starts_with("vKill")) {
+            break;
+        }
+    }
+    if let Some(inferior) = stub.inferior.as_mut() {
+        inferior.kill();
+    }
+    Ok(())
+}
+
+/// Reads from the client on a separate thread, so that an interrupt arriving while the main
+/// thread waits for the inferior can stop it. Packets are passed on through the returned channel.
+fn spawn_reader(stream: TcpStream, pid: Pid) -> Receiver<(String, bool)> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = PacketReader { reader: stream };
+        while let Ok(Some(input)) = reader.next_input() {
+            match input {
+                Input::Interrupt => {
+                    let _ = signal::kill(pid, Signal::SIGINT);
+                }
+                Input::Packet(data, checksum_ok) => {
+                    if sender.send((data, checksum_ok)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    receiver
+}
+
+impl Stub {
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut escaped = String::with_capacity(data.len());
+        for c in data.chars() {
+            match c {
+                '#' | '$' | '}' | '*' => {
+                    escaped.push('}');
+                    escaped.push((c as u8 ^ 0x20) as char);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        write!(self.stream, "${}#{:02x}", escaped, checksum(&escaped))?;
+        self.stream.flush()
+    }
+
+    fn stop_reply(&self) -> String {
+        match self.last_status {
+            Status::Stopped(signal, _) => format!("S{:02x}", signal_to_gdb(signal)),
+            Status::Exited(code) => format!("W{:02x}", code as u8),
+            Status::Signaled(signal) => format!("X{:02x}", signal_to_gdb(signal)),
+            // system calls are not traced for gdb clients
+            Status::Syscall(_, _) => format!("S{:02x}", signal_to_gdb(Signal::SIGTRAP)),
+        }
+    }
+
+    /// Handles one packet and returns the reply. An empty reply tells the client that the
+    /// packet is not supported.
+    fn handle(&mut self, data: &str) -> String {
+        let packet = data.as_bytes().first().copied().unwrap_or_default();
+        let args = data.get(1..).unwrap_or_default();
+        match packet {
+            b'?' => self.stop_reply(),
+            b'q' => self.handle_query(data),
+            b'Q' if data == "QStartNoAckMode" => {
+                // the OK is still acknowledged by the client
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            b'H' | b'T' => "OK".to_string(),
+            b'g' => self.read_registers().unwrap_or_else(error_reply),
+            b'G' => self.write_registers(args).unwrap_or_else(error_reply),
+            b'p' => self.read_register(args).unwrap_or_else(error_reply),
+            b'P' => self.write_register(args).unwrap_or_else(error_reply),
+            b'm' => self.read_memory(args).unwrap_or_else(error_reply),
+            b'M' => self.write_memory(args).unwrap_or_else(error_reply),
+            b'Z' | b'z' => self.handle_breakpoint(packet == b'Z', args),
+            b'c' | b's' => self.resume(packet == b's', None, args),
+            b'C' | b'S' => {
+                let (signal, addr) = args.split_once(';').unwrap_or((args, ""));
+                self.resume(packet == b'S', signal_from_gdb(signal), addr)
+            }
+            b'v' => self.handle_v(data),
+            b'k' => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.kill();
+                    let _ = inferior.wait(None);
+                }
+                self.inferior = None;
+                "OK".to_string()
+            }
+            b'D' => self.detach(),
+            _ => String::new(),
+        }
+    }
+
+    fn handle_query(&self, data: &str) -> String {
+        let pid = self.inferior.as_ref().map_or(0, |inferior| inferior.pid().as_raw());
+        if data.starts_with("qSupported") {
+            "PacketSize=4000;QStartNoAckMode+;vContSupported+".to_string()
+        } else if data == "qAttached" {
+            // the process was started by us, so the client should kill it when it quits
+            "0".to_string()
+        } else if data == "qC" {
+            format!("QC{:x}", pid)
+        } else if data == "qfThreadInfo" {
+            format!("m{:x}", pid)
+        } else if data == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    fn handle_v(&mut self, data: &str) -> String {
+        if data == "vCont?" {
+            "vCont;c;C;s;S".to_string()
+        } else if let Some(actions) = data.strip_prefix("vCont;") {
+            // there is only one thread, so the first action applies to it
+            let action = actions.split(';').next().unwrap_or_default();
+            let action = action.split(':').next().unwrap_or_default();
+            let signal = action.get(1..).and_then(signal_from_gdb);
+            match action.chars().next() {
+                Some('c') => self.resume(false, None, ""),
+                Some('s') => self.resume(true, None, ""),
+                Some('C') => self.resume(false, signal, ""),
+                Some('S') => self.resume(true, signal, ""),
+                _ => "E01".to_string(),
+            }
+        } else if data.starts_with("vKill") {
+            self.handle("k")
+        } else {
+            String::new()
+        }
+    }
+
+    fn inferior(&self) -> Result<&Inferior, nix::Error> {
+        self.inferior.as_ref().ok_or(nix::Error::ESRCH)
+    }
+
+    fn read_registers(&self) -> Result<String, nix::Error> {
+        let mut regs = Registers::read(self.inferior()?.pid())?;
+        Ok((0..GDB_REGISTERS.len())
+            .filter_map(|num| encode_register(&mut regs, num))
+            .collect())
+    }
+
+    fn write_registers(&self, hex: &str) -> Result<String, nix::Error> {
+        let pid = self.inferior()?.pid();
+        let mut regs = Registers::read(pid)?;
+        let mut offset = 0;
+        for num in 0..GDB_REGISTERS.len() {
+            match hex.get(offset..).and_then(|hex| decode_register(&mut regs, num, hex)) {
+                Some(len) => offset += len,
+                // clients may send fewer registers than we know of
+                None => break,
+            }
+        }
+        regs.write(pid)?;
+        Ok("OK".to_string())
+    }
+
+    fn read_register(&self, args: &str) -> Result<String, nix::Error> {
+        let num = usize::from_str_radix(args, 16).map_err(|_| nix::Error::EINVAL)?;
+        let mut regs = Registers::read(self.inferior()?.pid())?;
+        encode_register(&mut regs, num).ok_or(nix::Error::EINVAL)
+    }
+
+    fn write_register(&self, args: &str) -> Result<String, nix::Error> {
+        let (num, value) = args.split_once('=').ok_or(nix::Error::EINVAL)?;
+        let num = usize::from_str_radix(num, 16).map_err(|_| nix::Error::EINVAL)?;
+        let pid = self.inferior()?.pid();
+        let mut regs = Registers::read(pid)?;
+        decode_register(&mut regs, num, value).ok_or(nix::Error::EINVAL)?;
+        regs.write(pid)?;
+        Ok("OK".to_string())
+    }
+
+    fn read_memory(&self, args: &str) -> Result<String, nix::Error> {
+        let (addr, len) = parse_addr_len(args).ok_or(nix::Error::EINVAL)?;
+        let mut bytes = self.inferior()?.read_memory(addr, len)?;
+        // hide our breakpoint instructions from the client
+        for breakpoint in self.breakpoints.values().flatten() {
+            breakpoint.restore_in(addr, &mut bytes);
+        }
+        Ok(encode_hex(&bytes))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Result<String, nix::Error> {
+        let (location, hex) = args.split_once(':').ok_or(nix::Error::EINVAL)?;
+        let (addr, len) = parse_addr_len(location).ok_or(nix::Error::EINVAL)?;
+        let bytes = decode_hex(hex).filter(|bytes| bytes.len() == len).ok_or(nix::Error::EINVAL)?;
+        let inferior = self.inferior.as_mut().ok_or(nix::Error::ESRCH)?;
+        for (i, byte) in bytes.into_iter().enumerate() {
+            // the breakpoint whose trap covers the byte, if any
+            let covering = (0..arch::TRAP.len())
+                .filter_map(|offset| (addr + i).checked_sub(offset))
+                .find(|start| matches!(self.breakpoints.get(start), Some(Some(_))));
+            match covering.and_then(|start| self.breakpoints.get_mut(&start)) {
+                // keep the breakpoint; it restores the new byte when it is removed
+                Some(Some(breakpoint)) => breakpoint.orig_bytes[addr + i - breakpoint.addr] = byte,
+                _ => {
+                    inferior.write_byte(addr + i, byte)?;
+                }
+            }
+        }
+        Ok("OK".to_string())
+    }
+
+    fn handle_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        // only software breakpoints (type 0) are supported
+        if fields.next() != Some("0") {
+            return String::new();
+        }
+        let addr = match fields.next().and_then(|addr| usize::from_str_radix(addr, 16).ok()) {
+            Some(addr) => addr,
+            None => return "E01".to_string(),
+        };
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return error_reply(nix::Error::ESRCH),
+        };
+        let result = if insert {
+            match self.breakpoints.entry(addr) {
+                Entry::Occupied(_) => Ok(()),
+                Entry::Vacant(entry) => inferior.insert_trap(addr).map(|breakpoint| {
+                    entry.insert(Some(breakpoint));
+                }),
+            }
+        } else {
+            match self.breakpoints.remove(&addr) {
+                Some(Some(breakpoint)) => inferior.remove_trap(&breakpoint),
+                _ => Ok(()),
+            }
+        };
+        match result {
+            Ok(()) => "OK".to_string(),
+            Err(err) => error_reply(err),
+        }
+    }
+
+    /// Resumes the inferior, optionally at `addr`, and returns the stop reply once it stops.
+    fn resume(&mut self, step: bool, signal: Option<Signal>, addr: &str) -> String {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return self.stop_reply(),
+        };
+        if let Ok(addr) = usize::from_str_radix(addr, 16) {
+            if let Ok(mut regs) = Registers::read(inferior.pid()) {
+                regs.set_pc(addr);
+                let _ = regs.write(inferior.pid());
+            }
+        }
+        match inferior.resume(&self.breakpoints, step, signal) {
+            Ok(status) => {
+                self.last_status = status;
+                if !matches!(status, Status::Stopped(_, _)) {
+                    self.inferior = None;
+                }
+                self.stop_reply()
+            }
+            Err(err) => error_reply(err),
+        }
+    }
+
+    /// Removes our breakpoints and lets the inferior run on its own.
+    fn detach(&mut self) -> String {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return "OK".to_string(),
+        };
+        for breakpoint in self.breakpoints.drain().filter_map(|(_, breakpoint)| breakpoint) {
+            let _ = inferior.remove_trap(&breakpoint);
+        }
+        match ptrace::detach(inferior.pid(), None) {
+            Ok(()) => {
+                // dropping the inferior doesn't kill it
+                self.inferior = None;
+                "OK".to_string()
+            }
+            Err(err) => error_reply(err),
+        }
+    }
+}
+
+fn parse_addr_len(args: &str) -> Option<(usize, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn error_reply(err: nix::Error) -> String {
+    format!("E{:02x}", (err as i32).clamp(1, 0xff))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_all(bytes: &[u8]) -> Vec<Input> {
+        let mut reader = PacketReader { reader: bytes };
+        let mut inputs = Vec::new();
+        while let Some(input) = reader.next_input().unwrap() {
+            inputs.push(input);
+        }
+        inputs
+    }
+
+    #[test]
+    fn test_packet_reader() {
+        assert_eq!(
+            read_all(b"+$qSupported:multiprocess+#c6+\x03$g#67$m0,4#00"),
+            vec![
+                Input::Packet("qSupported:multiprocess+".to_string(), true),
+                Input::Interrupt,
+                Input::Packet("g".to_string(), true),
+                Input::Packet("m0,4".to_string(), false),
+            ]
+        );
+        // "}\x03" is an escaped '#'
+        assert_eq!(
+            read_all(b"$X0,1:}\x03#9f"),
+            vec![Input::Packet("X0,1:#".to_string(), true)]
+        );
+        // the checksum is over the bytes as sent, whatever is escaped and whether or not they
+        // are UTF-8
+        assert_eq!(read_all(b"$X0,1:}\x41#dd"), vec![Input::Packet("X0,1:a".to_string(), true)]);
+        assert_eq!(read_all(b"$X0,1:\xff#1e"), vec![Input::Packet("X0,1:\u{fffd}".to_string(), true)]);
+    }
+
+    #[test]
+    fn test_registers() {
+        let number = |names: [&str; 2]| GDB_REGISTERS.iter().position(|(name, _)| names.contains(name)).unwrap();
+        let mut regs = Registers::default();
+        regs.set_pc(0x401136);
+        *regs.gdb_register_mut(number(["eflags", "cpsr"])).unwrap() = 0x246;
+        assert_eq!(encode_register(&mut regs, number(["rip", "pc"])).unwrap(), "3611400000000000");
+        assert_eq!(encode_register(&mut regs, number(["eflags", "cpsr"])).unwrap(), "46020000");
+        assert_eq!(decode_register(&mut regs, number(["rsp", "sp"]), "f0dfffffff7f0000"), Some(16));
+        assert_eq!(regs.stack_pointer(), 0x7fffffffdff0);
+        assert_eq!(decode_register(&mut regs, GDB_REGISTERS.len(), "00"), None);
+
+        assert_eq!(decode_hex("0g"), None);
+        assert_eq!(parse_addr_len("401136,10"), Some((0x401136, 16)));
+        assert_eq!(signal_to_gdb(Signal::SIGUSR1), 30);
+        assert_eq!(signal_from_gdb("1e"), Some(Signal::SIGUSR1));
+        assert_eq!(signal_from_gdb("0b"), Some(Signal::SIGSEGV));
+    }
+}
@@ -5,261 +5,718 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
-use object::{Object, ObjectSection};
-use std::borrow;
+use crate::arch;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    Encoding, File, FrameBase, Function, InlinedCall, Line, Location, Member, Struct, Type, TypeKind, Variable,
+    Variant, Variants,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        match object.section_by_name(id.name()) {
-            Some(ref section) => Ok(section
-                .uncompressed_data()
-                .unwrap_or(borrow::Cow::Borrowed(&[][..]))),
-            None => Ok(borrow::Cow::Borrowed(&[][..])),
+/// A compilation unit, as listed before any of its entries are read.
+pub struct UnitHeader {
+    /// The offset of the unit in .debug_info.
+    pub offset: usize,
+    pub name: String,
+}
+
+/// Lists the compilation units, reading just the name of each.
+pub fn load_unit_headers<R: Reader>(dwarf: &gimli::Dwarf<R>) -> Result<Vec<UnitHeader>, Error> {
+    let mut headers = Vec::new();
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let offset = match header.offset() {
+            UnitSectionOffset::DebugInfoOffset(offset) => offset.0,
+            UnitSectionOffset::DebugTypesOffset(offset) => offset.0,
+        };
+        let unit = dwarf.unit(header)?;
+        let mut entries = unit.entries();
+        let name = match entries.next_dfs()? {
+            Some((_, entry)) => entry_name(entry, &unit, dwarf),
+            None => None,
+        };
+        // the skeleton units of split DWARF have no name, but the first file of their line
+        // table is the unit's source file
+        let name = name.or_else(|| {
+            let program = unit.line_program.as_ref()?;
+            let file = program.header().file(0)?;
+            let name = dwarf.attr_string(&unit, file.path_name()).ok()?;
+            Some(name.to_string_lossy().ok()?.into_owned())
+        });
+        let name = name.unwrap_or_else(|| "<unknown>".to_string());
+        // rustc names its units "<crate root>/@/<codegen unit>"; all codegen units of a crate
+        // are found under the crate root
+        let name = name.split("/@/").next().unwrap().to_string();
+        headers.push(UnitHeader { offset, name });
+    }
+    Ok(headers)
+}
+
+/// Reads the address ranges of the units' code, as (begin, end, unit offset). These come from
+/// .debug_aranges if the program has it, and otherwise from the units' root entries.
+pub fn load_unit_ranges<R: Reader>(dwarf: &gimli::Dwarf<R>) -> Result<Vec<(u64, u64, usize)>, Error> {
+    let mut ranges = Vec::new();
+    let mut headers = dwarf.debug_aranges.headers();
+    while let Some(header) = headers.next()? {
+        let offset = header.debug_info_offset().0;
+        let mut entries = header.entries();
+        while let Some(entry) = entries.next()? {
+            let range = entry.range();
+            if range.begin < range.end {
+                ranges.push((range.begin, range.end, offset));
+            }
         }
+    }
+    if !ranges.is_empty() {
+        return Ok(ranges);
+    }
+    let mut iter = dwarf.units();
+    while let Some(header) = iter.next()? {
+        let offset = match header.offset() {
+            UnitSectionOffset::DebugInfoOffset(offset) => offset.0,
+            UnitSectionOffset::DebugTypesOffset(_) => continue,
+        };
+        let unit = dwarf.unit(header)?;
+        let mut unit_ranges = dwarf.unit_ranges(&unit)?;
+        while let Some(range) = unit_ranges.next()? {
+            if range.begin < range.end {
+                ranges.push((range.begin, range.end, offset));
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+/// Finds the split DWARF (the .dwo file or the unit in a .dwp package) of a skeleton unit.
+pub type SplitLoader<'a, R> = &'a dyn Fn(&gimli::Unit<R>) -> Option<gimli::Dwarf<R>>;
+
+/// Reads the functions, variables, lines and types of the compilation unit at `offset` in
+/// .debug_info. The entries of a skeleton unit are read from its split DWARF, which
+/// `load_split` finds.
+pub fn load_unit<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    offset: usize,
+    load_bias: usize,
+    load_split: SplitLoader<R>,
+) -> Result<(File, HashMap<usize, Type>), Error> {
+    let header = dwarf.debug_info.header_from_offset(gimli::DebugInfoOffset(offset))?;
+    let skeleton = dwarf.unit(header)?;
+    let split = match skeleton.dwo_id {
+        Some(_) => load_split(&skeleton),
+        None => None,
+    };
+    let split = match split {
+        Some(split) => split,
+        None => return read_unit(dwarf, &skeleton, dwarf, &skeleton, load_bias),
     };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    // let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+    let mut units = split.units();
+    let mut unit = None;
+    while let Some(header) = units.next()? {
+        let candidate = split.unit(header)?;
+        if candidate.dwo_id == skeleton.dwo_id {
+            unit = Some(candidate);
+            break;
+        }
+    }
+    let mut unit = match unit {
+        Some(unit) => unit,
+        None => return read_unit(dwarf, &skeleton, dwarf, &skeleton, load_bias),
+    };
+    unit.copy_relocated_attributes(&skeleton);
+    let (mut file, types) = read_unit(&split, &unit, dwarf, &skeleton, load_bias)?;
 
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section)?;
+    // offsets in the .dwo file start over at 0 for every unit, so the unit's types are moved
+    // past those of the other units
+    let base = split_type_base(offset);
+    let types = types
+        .into_iter()
+        .map(|(offset, mut entity_type)| {
+            rebase_type(&mut entity_type, base);
+            (offset + base, entity_type)
+        })
+        .collect();
+    let functions = file.functions.iter_mut().flat_map(|func| func.variables.iter_mut());
+    for var in functions.chain(file.global_variables.iter_mut()) {
+        rebase_type(&mut var.entity_type, base);
+    }
+    for return_type in file.functions.iter_mut().filter_map(|func| func.return_type.as_mut()) {
+        rebase_type(return_type, base);
+    }
+    Ok((file, types))
+}
 
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+/// Where the types of the split unit whose skeleton is at `skeleton_offset` in .debug_info are
+/// keyed: this plus their offset in the split unit's .debug_info.dwo.
+pub fn split_type_base(skeleton_offset: usize) -> usize {
+    (skeleton_offset + 1) << 32
+}
 
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
+/// Adds `base` to the offsets of the types `entity_type` refers to.
+fn rebase_type(entity_type: &mut Type, base: usize) {
+    match &mut entity_type.kind {
+        TypeKind::Pointer(target) | TypeKind::Alias(target) => {
+            if let Some(target) = target {
+                *target += base;
+            }
+        }
+        TypeKind::Array { element, .. } => *element += base,
+        TypeKind::Struct(structure) => {
+            for member in &mut structure.members {
+                member.type_offset += base;
+            }
+            for (_, offset) in &mut structure.template_params {
+                *offset += base;
+            }
+            if let Some(variants) = &mut structure.variants {
+                if let Some(discriminant) = &mut variants.discriminant {
+                    discriminant.type_offset += base;
+                }
+                for variant in &mut variants.variants {
+                    variant.member.type_offset += base;
+                }
+            }
+        }
+        _ => {}
+    }
+}
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+/// Reads the entries of `unit` and the lines of `line_unit`, which for split DWARF is the
+/// skeleton unit in the executable.
+fn read_unit<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    line_dwarf: &gimli::Dwarf<R>,
+    line_unit: &gimli::Unit<R>,
+    load_bias: usize,
+) -> Result<(File, HashMap<usize, Type>), Error> {
+    // Types may be used before they are defined, so they are all read up front
+    let offset_to_type = load_types(unit, dwarf)?;
 
-    let mut compilation_units: Vec<File> = Vec::new();
+    let mut file = File::default();
 
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
+    // Names and qualified names of functions by offset, for definitions that refer to their
+    // declaration (DW_AT_specification) instead of having a name of their own
+    let mut function_names: HashMap<usize, (String, String)> = HashMap::new();
+    // Inlined calls by index, with the offset of the function they are a copy of. That
+    // function's entry may come later, so their names are filled in at the end.
+    let mut inlined_origins: Vec<(usize, usize)> = Vec::new();
 
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
-        let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+    let comp_dir = match &line_unit.comp_dir {
+        Some(dir) => path::PathBuf::from(dir.to_string_lossy()?.into_owned()),
+        None => path::PathBuf::new(),
+    };
+    // The entries enclosing the current one
+    let mut scopes: Vec<Scope> = Vec::new();
+
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        scopes.truncate(depth as usize);
+        let mut scope = Scope {
+            tag: entry.tag(),
+            name: None,
+            function: None,
+        };
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                let name = entry_name(entry, unit, dwarf).unwrap_or_else(|| "<unknown>".to_string());
+                // rustc names its units "<crate root>/@/<codegen unit>"
+                file.name = name.split("/@/").next().unwrap().to_string();
+                file.comp_dir = comp_dir.to_string_lossy().into_owned();
+                if let Some(attr) = entry.attr(gimli::DW_AT_producer)? {
+                    if let Ok(DebugValue::Str(producer)) = get_attr_value(&attr, unit, dwarf) {
+                        file.producer = Some(producer);
+                    }
+                }
+                if let Some(gimli::AttributeValue::Language(language)) = entry.attr_value(gimli::DW_AT_language)? {
+                    file.language = Some(language_name(language).to_string());
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+                file.dwarf_version = unit.header.version();
+            }
+            gimli::DW_TAG_namespace
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_class_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_enumeration_type => {
+                scope.name = entry_name(entry, unit, dwarf);
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func: Function = Default::default();
+                let mut declaration = false;
+                let mut specification = None;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
+                            }
                         }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(high_pc)) = val {
+                                func.text_length = high_pc.try_into().unwrap();
+                            }
                         }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_frame_base => {
+                            func.frame_base = get_frame_base(&attr, unit);
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                func.return_type = offset_to_type.get(&offset).cloned();
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_declaration => declaration = true,
+                        gimli::DW_AT_specification | gimli::DW_AT_abstract_origin => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                specification = Some(offset);
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
                 }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                let declared = specification.and_then(|offset| function_names.get(&offset));
+                if let (true, Some((name, qualified_name))) = (func.name.is_empty(), declared) {
+                    func.name = name.clone();
+                    func.qualified_name = qualified_name.clone();
+                } else {
+                    let mut path: Vec<&str> =
+                        scopes.iter().filter_map(|scope| scope.name.as_deref()).collect();
+                    path.push(&func.name);
+                    func.qualified_name = path.join("::");
+                }
+                function_names.insert(
+                    global_offset(entry.offset(), unit),
+                    (func.name.clone(), func.qualified_name.clone()),
+                );
+                // declarations and abstract instances of inlined functions have no code
+                if !declaration && func.address != 0 {
+                    func.address += load_bias;
+                    let functions = &mut file.functions;
+                    functions.push(func);
+                    scope.function = Some(functions.len() - 1);
+                }
+            }
+            gimli::DW_TAG_inlined_subroutine => {
+                let origin = match entry.attr_value(gimli::DW_AT_abstract_origin)? {
+                    Some(gimli::AttributeValue::UnitRef(offset)) => Some(global_offset(offset, unit)),
+                    Some(gimli::AttributeValue::DebugInfoRef(offset)) => Some(offset.0),
+                    _ => None,
+                };
+                // the code is entered at DW_AT_entry_pc, or else at its lowest address
+                let entry_pc = match entry.attr_value(gimli::DW_AT_entry_pc)? {
+                    Some(value) => dwarf.attr_address(unit, value)?,
+                    None => None,
+                };
+                let mut lowest = None;
+                let mut ranges = dwarf.die_ranges(unit, entry)?;
+                while let Some(range) = ranges.next()? {
+                    if range.begin < range.end {
+                        lowest = Some(lowest.map_or(range.begin, |low: u64| low.min(range.begin)));
+                    }
+                }
+                if let (Some(origin), Some(address)) = (origin, entry_pc.or(lowest)) {
+                    let address: usize = address.try_into().unwrap();
+                    let calls = &mut file.inlined_calls;
+                    calls.push(InlinedCall {
+                        address: address + load_bias,
+                        ..Default::default()
+                    });
+                    inlined_origins.push((calls.len() - 1, origin));
+                }
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut entity_type: Option<Type> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                if let Some(dtype) = offset_to_type.get(&offset) {
+                                    entity_type = Some(dtype.clone());
                                 }
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, unit, dwarf) {
+                                location = Some(match loc {
+                                    Location::Address(addr) => Location::Address(addr + load_bias),
+                                    loc => loc,
+                                });
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                }
+                if let (Some(entity_type), Some(location)) = (entity_type, location) {
+                    let var = Variable {
+                        name,
+                        entity_type,
+                        location,
+                        line_number: line_number.try_into().unwrap(),
+                        parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
+                    };
+                    match scopes.iter().rev().find(|scope| scope.tag == gimli::DW_TAG_subprogram) {
+                        Some(Scope { function: Some(index), .. }) => {
+                            file.functions[*index].variables.push(var);
+                        }
+                        // a variable of a function without code
+                        Some(_) => {}
+                        None => {
+                            let global = scopes.iter().all(|scope| {
+                                scope.tag == gimli::DW_TAG_compile_unit
+                                    || scope.tag == gimli::DW_TAG_namespace
+                            });
+                            if global {
+                                file.global_variables.push(var);
+                            }
                         }
                     }
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
-            }
-        }
-
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
-                        }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
+            }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
+        }
+        scopes.push(scope);
+    }
+
+    // Get line numbers
+    if let Some(program) = line_unit.line_program.clone() {
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Real applications should cache this for performance.
+                let mut path = path::PathBuf::new();
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = file.directory(header) {
+                        path.push(line_dwarf.attr_string(line_unit, dir)?.to_string_lossy()?.as_ref());
                     }
+                    path.push(
+                        line_dwarf
+                            .attr_string(line_unit, file.path_name())?
+                            .to_string_lossy()?
+                            .as_ref(),
+                    );
+                }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
-
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = if let Some(line) = row.line() {
-                        line.get().try_into().unwrap()
-                    } else {
-                        0
-                    };
+                // Only lines of the unit's own file are kept. Its name and the line table's
+                // directories may each be relative to the compilation directory.
+                let own_file = comp_dir.join(&file.name) == comp_dir.join(&path);
+
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = if let Some(line) = row.line() {
+                    line.get().try_into().unwrap()
+                } else {
+                    0
+                };
+
+                if own_file {
+                    let address: usize = row.address().try_into().unwrap();
+                    file.lines.push(Line {
+                        file: file.name.clone(),
+                        number: line.try_into().unwrap(),
+                        address: address + load_bias,
+                    });
+                    if row.is_stmt() {
+                        file.statements.insert(address + load_bias);
+                    }
+                }
+            }
+        }
+    }
+    for (index, origin) in inlined_origins {
+        if let Some((name, qualified_name)) = function_names.get(&origin) {
+            let call = &mut file.inlined_calls[index];
+            call.name = name.clone();
+            call.qualified_name = qualified_name.clone();
+        }
+    }
+    Ok((file, offset_to_type))
+}
+
+/// Names a source language the way gdb does.
+fn language_name(language: gimli::DwLang) -> &'static str {
+    match language {
+        gimli::DW_LANG_C89 | gimli::DW_LANG_C | gimli::DW_LANG_C99 | gimli::DW_LANG_C11 | gimli::DW_LANG_C17 => "c",
+        gimli::DW_LANG_C_plus_plus
+        | gimli::DW_LANG_C_plus_plus_03
+        | gimli::DW_LANG_C_plus_plus_11
+        | gimli::DW_LANG_C_plus_plus_14
+        | gimli::DW_LANG_C_plus_plus_17
+        | gimli::DW_LANG_C_plus_plus_20 => "c++",
+        gimli::DW_LANG_Rust => "rust",
+        gimli::DW_LANG_Mips_Assembler => "asm",
+        _ => "unknown",
+    }
+}
+
+/// An entry enclosing the one being read.
+struct Scope {
+    tag: gimli::DwTag,
+    /// The name, for entries that contribute to qualified names (namespaces and types).
+    name: Option<String>,
+    /// For a subprogram, its index in the functions of the current File.
+    function: Option<usize>,
+}
+
+/// Reads every type in `unit`, keyed by the offset of its entry in .debug_info (which is what
+/// DW_AT_type attributes refer to).
+fn load_types<R: Reader>(unit: &gimli::Unit<R>, dwarf: &gimli::Dwarf<R>) -> Result<HashMap<usize, Type>, Error> {
+    let mut types = HashMap::new();
+    let mut tree = unit.entries_tree(None)?;
+    load_types_in(tree.root()?, unit, dwarf, &mut types)?;
+    Ok(types)
+}
+
+/// Reads the type at `node`, if it is one, and the types nested in it.
+fn load_types_in<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    types: &mut HashMap<usize, Type>,
+) -> Result<(), Error> {
+    let entry = node.entry().clone();
+    let target = entry_type_offset(&entry, unit, dwarf);
+    let mut kind = match entry.tag() {
+        gimli::DW_TAG_base_type => Some(TypeKind::Base(get_encoding(&entry))),
+        gimli::DW_TAG_pointer_type
+        | gimli::DW_TAG_reference_type
+        | gimli::DW_TAG_rvalue_reference_type => Some(TypeKind::Pointer(target)),
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+            Some(TypeKind::Struct(Struct::default()))
+        }
+        gimli::DW_TAG_enumeration_type => Some(TypeKind::Enum(Vec::new())),
+        gimli::DW_TAG_array_type => target.map(|element| TypeKind::Array { element, count: None }),
+        gimli::DW_TAG_typedef
+        | gimli::DW_TAG_const_type
+        | gimli::DW_TAG_volatile_type
+        | gimli::DW_TAG_restrict_type => Some(TypeKind::Alias(target)),
+        _ => None,
+    };
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let child_entry = child.entry();
+        match (&mut kind, child_entry.tag()) {
+            // static members are declarations and not stored in the structure
+            (Some(TypeKind::Struct(structure)), gimli::DW_TAG_member)
+                if child_entry.attr_value(gimli::DW_AT_declaration)?.is_none() =>
+            {
+                if let Some(member) = get_member(child_entry, unit, dwarf) {
+                    structure.members.push(member);
+                }
+            }
+            (Some(TypeKind::Struct(structure)), gimli::DW_TAG_template_type_parameter) => {
+                let name = entry_name(child_entry, unit, dwarf);
+                if let (Some(name), Some(offset)) = (name, entry_type_offset(child_entry, unit, dwarf)) {
+                    structure.template_params.push((name, offset));
+                }
+            }
+            (Some(TypeKind::Struct(structure)), gimli::DW_TAG_variant_part) => {
+                structure.variants = Some(load_variants(child, unit, dwarf)?);
+                continue;
+            }
+            (Some(TypeKind::Enum(enumerators)), gimli::DW_TAG_enumerator) => {
+                let name = entry_name(child_entry, unit, dwarf);
+                let value = entry_constant(child_entry, gimli::DW_AT_const_value);
+                if let (Some(name), Some(value)) = (name, value) {
+                    enumerators.push((name, value as i64));
+                }
+            }
+            (Some(TypeKind::Array { count, .. }), gimli::DW_TAG_subrange_type) => {
+                let length = entry_constant(child_entry, gimli::DW_AT_count).or_else(|| {
+                    entry_constant(child_entry, gimli::DW_AT_upper_bound)
+                        .filter(|upper_bound| (*upper_bound as i64) >= 0)
+                        .map(|upper_bound| upper_bound + 1)
+                });
+                // the dimensions of a multi-dimensional array are flattened
+                *count = match (*count, length) {
+                    (Some(count), Some(length)) => Some(count * length as usize),
+                    (None, length) => length.map(|length| length as usize),
+                    (count, None) => count,
+                };
+            }
+            _ => {}
+        }
+        load_types_in(child, unit, dwarf, types)?;
+    }
+    if let Some(kind) = kind {
+        let name = entry_name(&entry, unit, dwarf).unwrap_or_default();
+        let size = match (entry_constant(&entry, gimli::DW_AT_byte_size), &kind) {
+            (Some(size), _) => size,
+            // rustc leaves out the size of pointers
+            (None, TypeKind::Pointer(_)) => unit.encoding().address_size as u64,
+            (None, _) => 0,
+        };
+        types.insert(
+            global_offset(entry.offset(), unit),
+            Type::new(name, size as usize, kind),
+        );
+    }
+    Ok(())
+}
 
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
+/// Reads the variants of a Rust enum from its DW_TAG_variant_part.
+fn load_variants<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Variants, Error> {
+    let mut variants = Variants::default();
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        match child.entry().tag() {
+            gimli::DW_TAG_member => variants.discriminant = get_member(child.entry(), unit, dwarf),
+            gimli::DW_TAG_variant => {
+                let discr_value = entry_constant(child.entry(), gimli::DW_AT_discr_value);
+                let mut members = child.children();
+                while let Some(member) = members.next()? {
+                    if member.entry().tag() == gimli::DW_TAG_member {
+                        if let Some(member) = get_member(member.entry(), unit, dwarf) {
+                            variants.variants.push(Variant { discr_value, member });
+                        }
                     }
                 }
             }
+            _ => {}
+        }
+    }
+    Ok(variants)
+}
+
+fn get_member<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Member> {
+    Some(Member {
+        // the discriminant of a Rust enum has no name
+        name: entry_name(entry, unit, dwarf).unwrap_or_default(),
+        offset: entry_constant(entry, gimli::DW_AT_data_member_location).unwrap_or(0) as usize,
+        type_offset: entry_type_offset(entry, unit, dwarf)?,
+    })
+}
+
+fn get_encoding<R: Reader>(entry: &gimli::DebuggingInformationEntry<R>) -> Encoding {
+    match entry.attr_value(gimli::DW_AT_encoding) {
+        Ok(Some(gimli::AttributeValue::Encoding(encoding))) => match encoding {
+            gimli::DW_ATE_signed => Encoding::Signed,
+            gimli::DW_ATE_unsigned => Encoding::Unsigned,
+            gimli::DW_ATE_float => Encoding::Float,
+            gimli::DW_ATE_boolean => Encoding::Boolean,
+            gimli::DW_ATE_signed_char => Encoding::SignedChar,
+            gimli::DW_ATE_unsigned_char => Encoding::UnsignedChar,
+            gimli::DW_ATE_UTF => Encoding::Utf,
+            _ => Encoding::Other,
+        },
+        _ => Encoding::Other,
+    }
+}
+
+fn get_frame_base<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> FrameBase {
+    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+        let mut pc = data.0.clone();
+        if let Ok(gimli::Operation::Register { register }) = gimli::Operation::parse(&mut pc, unit.encoding()) {
+            return match register {
+                arch::DWARF_FRAME_POINTER => FrameBase::FramePointer,
+                arch::DWARF_STACK_POINTER => FrameBase::StackPointer,
+                _ => FrameBase::Cfa,
+            };
         }
     }
-    Ok(compilation_units)
+    FrameBase::Cfa
+}
+
+/// Converts an offset within a unit to an offset within .debug_info, which is how other units
+/// refer to the entry.
+fn global_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+fn entry_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    let attr = entry.attr(gimli::DW_AT_name).ok()??;
+    match get_attr_value(&attr, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn entry_type_offset<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    let attr = entry.attr(gimli::DW_AT_type).ok()??;
+    match get_attr_value(&attr, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
+/// Reads an attribute holding a constant of any size.
+fn entry_constant<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Option<u64> {
+    let value = entry.attr_value(name).ok()??;
+    value.udata_value().or_else(|| value.sdata_value().map(|value| value as u64))
 }
 
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
     Uint(u64),
+    #[allow(dead_code)]
     Int(i64),
     Size(usize),
     NoVal,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     GimliError(gimli::Error),
     // Addr2lineError(addr2line::gimli::Error),
@@ -291,14 +748,15 @@ impl From<std::fmt::Error> for Error {
     }
 }
 
-impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
-    Endian: gimli::Endianity + Send + Sync
-{
-}
+impl<R: gimli::Reader<Offset = usize>> Reader for R {}
 
-trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
+pub trait Reader: gimli::Reader<Offset = usize> {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
         let encoding = unit.encoding();
         let mut pc = data.0.clone();
@@ -311,6 +769,11 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
                     gimli::Operation::Address { address } => {
                         return Some(Location::Address(address.try_into().unwrap()));
                     }
+                    // split DWARF keeps addresses in the executable's .debug_addr
+                    gimli::Operation::AddressIndex { index } => {
+                        let address = dwarf.address(unit, index).ok()?;
+                        return Some(Location::Address(address.try_into().unwrap()));
+                    }
                     _ => {}
                 }
             }
@@ -351,6 +814,11 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        gimli::AttributeValue::DebugStrOffsetsIndex(_) => {
+            let s = dwarf.attr_string(unit, value)?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
+        gimli::AttributeValue::DebugAddrIndex(index) => Ok(DebugValue::Uint(dwarf.address(unit, index)?)),
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
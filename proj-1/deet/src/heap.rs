@@ -0,0 +1,148 @@
+//! Heap tracking for `leak-check` and `info heap`. The debugger sets breakpoints on `malloc`,
+//! `calloc`, `realloc` and `free` and tells the tracker about each call; the tracker keeps the
+//! live allocations with the backtrace of the code that made them, catches invalid frees, and
+//! summarizes what is still allocated by allocation site.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// The allocation functions that are intercepted.
+pub const FUNCTIONS: &[&str] = &["malloc", "calloc", "realloc", "free"];
+
+/// A backtrace, innermost frame first, as the lines `backtrace` shows.
+pub type Site = Vec<String>;
+
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub size: usize,
+    pub site: Site,
+}
+
+/// Why a pointer passed to `free` or `realloc` is invalid.
+#[derive(Debug, PartialEq)]
+pub enum InvalidFree {
+    /// It was freed already, at this site.
+    DoubleFree(Site),
+    /// It isn't the start of any allocation.
+    Unknown,
+}
+
+#[derive(Default)]
+pub struct HeapTracker {
+    live: HashMap<usize, Allocation>,
+    /// Pointers freed since they were last allocated, with the site that freed them.
+    freed: HashMap<usize, Site>,
+}
+
+impl HeapTracker {
+    /// Records the allocation of `size` bytes at `ptr`.
+    pub fn allocated(&mut self, ptr: usize, size: usize, site: Site) {
+        if ptr == 0 {
+            return;
+        }
+        self.freed.remove(&ptr);
+        self.live.insert(ptr, Allocation { size, site });
+    }
+
+    /// Records that `ptr` was freed at `site`. Freeing a null pointer does nothing.
+    pub fn freed(&mut self, ptr: usize, site: Site) -> Result<(), InvalidFree> {
+        if ptr == 0 {
+            return Ok(());
+        }
+        if self.live.remove(&ptr).is_some() {
+            self.freed.insert(ptr, site);
+            return Ok(());
+        }
+        match self.freed.get(&ptr) {
+            Some(first) => Err(InvalidFree::DoubleFree(first.clone())),
+            None => Err(InvalidFree::Unknown),
+        }
+    }
+
+    /// Checks the pointer passed to `free` or `realloc`, without recording anything.
+    pub fn check(&self, ptr: usize) -> Result<(), InvalidFree> {
+        if ptr == 0 || self.live.contains_key(&ptr) {
+            return Ok(());
+        }
+        match self.freed.get(&ptr) {
+            Some(first) => Err(InvalidFree::DoubleFree(first.clone())),
+            None => Err(InvalidFree::Unknown),
+        }
+    }
+
+    /// The live allocations, by address.
+    pub fn live(&self) -> Vec<(usize, &Allocation)> {
+        let mut live: Vec<(usize, &Allocation)> = self.live.iter().map(|(ptr, allocation)| (*ptr, allocation)).collect();
+        live.sort_by_key(|(ptr, _)| *ptr);
+        live
+    }
+
+    /// Summarizes the live allocations grouped by the site that made them, largest total
+    /// first, or returns None if there are none.
+    pub fn leak_report(&self) -> Option<String> {
+        if self.live.is_empty() {
+            return None;
+        }
+        let mut sites: HashMap<&Site, (usize, usize)> = HashMap::new();
+        for allocation in self.live.values() {
+            let (bytes, count) = sites.entry(&allocation.site).or_default();
+            *bytes += allocation.size;
+            *count += 1;
+        }
+        let mut sites: Vec<(&Site, (usize, usize))> = sites.into_iter().collect();
+        sites.sort_by(|a, b| (b.1, a.0).cmp(&(a.1, b.0)));
+        let total: usize = self.live.values().map(|allocation| allocation.size).sum();
+        let mut out = format!(
+            "{} bytes in {} still live, from {} site{}:",
+            total,
+            allocations(self.live.len()),
+            sites.len(),
+            if sites.len() == 1 { "" } else { "s" }
+        );
+        for (site, (bytes, count)) in sites {
+            let _ = write!(out, "\n{} bytes in {} from\n{}", bytes, allocations(count), format_site(site));
+        }
+        Some(out)
+    }
+}
+
+/// Counts allocations, e.g. "1 allocation".
+pub fn allocations(count: usize) -> String {
+    match count {
+        1 => "1 allocation".to_string(),
+        count => format!("{} allocations", count),
+    }
+}
+
+/// Formats a site like a backtrace, indented.
+pub fn format_site(site: &Site) -> String {
+    let lines: Vec<String> = site.iter().enumerate().map(|(index, frame)| format!("    #{} {}", index, frame)).collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn site(name: &str) -> Site {
+        vec![name.to_string(), "main".to_string()]
+    }
+
+    #[test]
+    fn test_heap_tracker() {
+        let mut heap = HeapTracker::default();
+        heap.allocated(0x1000, 16, site("a"));
+        heap.allocated(0x2000, 8, site("b"));
+        heap.allocated(0x3000, 8, site("b"));
+        assert_eq!(heap.freed(0, site("f")), Ok(()));
+        assert_eq!(heap.freed(0x1000, site("f")), Ok(()));
+        assert_eq!(heap.freed(0x1000, site("g")), Err(InvalidFree::DoubleFree(site("f"))));
+        assert_eq!(heap.check(0x1234), Err(InvalidFree::Unknown));
+        // reused by a later allocation
+        heap.allocated(0x1000, 4, site("c"));
+        assert_eq!(heap.check(0x1000), Ok(()));
+        assert_eq!(heap.live().len(), 3);
+        let report = heap.leak_report().unwrap();
+        assert!(report.starts_with("20 bytes in 3 allocations still live, from 2 sites:\n16 bytes in 2 allocations from\n    #0 b\n    #1 main\n"));
+    }
+}
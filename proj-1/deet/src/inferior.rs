@@ -1,14 +1,21 @@
 use nix::sys::ptrace;
-use nix::sys::signal;
+use nix::sys::personality::{self, Persona};
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::process::{Child, Command};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::process::{Child, ChildStderr, ChildStdout, Command, Stdio};
 use std::os::unix::process::CommandExt;
 use std::mem::size_of;
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use std::collections::HashMap;
+use crate::arch::{self, Registers};
 use crate::debugger::Breakpoint;
 
+#[derive(Clone, Copy)]
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
@@ -20,15 +27,94 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped entering a system call, or leaving it if the flag is set.
+    /// This only happens while system calls are traced. Contains the instruction pointer as well.
+    Syscall(bool, usize),
+}
+
+/// The pid of the inferior deet's SIGINT handler interrupts, or 0 if none is running.
+static INTERRUPT_PID: AtomicI32 = AtomicI32::new(0);
+
+/// Makes ctrl+c (or a SIGINT sent to deet) interrupt the inferior, so that it stops with SIGINT
+/// and deet returns to the prompt with it paused. deet itself is not interrupted.
+pub fn install_interrupt_handler() -> Result<(), nix::Error> {
+    let action = SigAction::new(SigHandler::SigAction(forward_interrupt), SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { signal::sigaction(signal::Signal::SIGINT, &action) }.map(|_| ())
+}
+
+extern "C" fn forward_interrupt(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let pid = INTERRUPT_PID.load(Ordering::SeqCst);
+    if pid == 0 {
+        return;
+    }
+    // ctrl+c typed at the terminal already reaches an inferior in deet's process group, and
+    // sending it another SIGINT would stop it a second time
+    unsafe {
+        let from_terminal = (*info).si_code == libc::SI_KERNEL;
+        if !from_terminal || libc::getpgid(pid) != libc::getpgrp() {
+            libc::kill(pid, libc::SIGINT);
+        }
+    }
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    // position-independent executables (e.g. everything rustc builds) are then always loaded at
+    // the address DwarfData assumes. Whether this worked is checked with address_randomized()
+    // once the target is running, since the child can't report anything safely here.
+    if let Ok(persona) = personality::get() {
+        personality::set(persona | Persona::ADDR_NO_RANDOMIZE).ok();
+    }
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
+}
+
+/// How the inferior is started: where its standard streams go, and the environment and
+/// directory it runs in.
+#[derive(Clone, Default)]
+pub struct LaunchOptions {
+    /// Files the standard streams are redirected to, from `run < in > out 2> err`.
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    /// The terminal the streams that aren't redirected use instead of deet's, set with `tty`.
+    /// The inferior also gets it as its controlling terminal.
+    pub tty: Option<String>,
+    /// The inferior's whole environment, once it has been changed from deet's.
+    pub environment: Option<BTreeMap<String, String>>,
+    pub cwd: Option<String>,
+    /// Connects stdout and stderr that go nowhere else to pipes, which can be taken with
+    /// `take_stdout` and `take_stderr`.
+    pub capture_output: bool,
+    /// Gives the inferior /dev/null as stdin unless it goes somewhere else, when deet's own
+    /// stdin carries the requests of a program driving it.
+    pub null_stdin: bool,
+}
+
+impl LaunchOptions {
+    /// Opens the files and terminal the inferior's standard streams go to. Returns an error
+    /// message if one can't be opened.
+    fn stdio(&self) -> Result<[Stdio; 3], String> {
+        let open = |path: &str, options: &mut OpenOptions| -> Result<File, String> {
+            options.open(path).map_err(|err| format!("{}: {}", path, err))
+        };
+        let tty = match &self.tty {
+            Some(tty) => Some(open(tty, OpenOptions::new().read(true).write(true))?),
+            None => None,
+        };
+        let stream = |path: &Option<String>, input: bool| -> Result<Stdio, String> {
+            Ok(match (path, &tty) {
+                (Some(path), _) if input => open(path, OpenOptions::new().read(true))?.into(),
+                (Some(path), _) => open(path, OpenOptions::new().write(true).create(true).truncate(true))?.into(),
+                (None, Some(tty)) => tty.try_clone().map_err(|err| err.to_string())?.into(),
+                (None, None) if self.null_stdin && input => Stdio::null(),
+                (None, None) if self.capture_output && !input => Stdio::piped(),
+                (None, None) => Stdio::inherit(),
+            })
+        };
+        Ok([stream(&self.stdin, true)?, stream(&self.stdout, false)?, stream(&self.stderr, false)?])
+    }
 }
 
 fn align_addr_to_word(addr: usize) -> usize {
@@ -36,111 +122,328 @@ fn align_addr_to_word(addr: usize) -> usize {
 }
 
 pub struct Inferior {
-    child: Child,
+    /// None for a copy made with `fork`, which is the inferior's child rather than deet's.
+    child: Option<Child>,
+    pid: Pid,
+    /// When set, the inferior stops at the entry to and the exit from every system call.
+    pub trace_syscalls: bool,
+    /// Set between a system call's entry stop and its exit stop.
+    in_syscall: bool,
+    /// The arguments of the system call at whose entry the inferior last stopped.
+    syscall_arguments: [u64; 6],
 }
 
 impl Inferior {
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &mut HashMap<usize, Option<Breakpoint>>) -> Option<Inferior> {
+    /// Attempts to start a new inferior process, stopped once it has exec'd `target` and before
+    /// it runs anything. Returns an error message if it can't be started.
+    pub fn new(target: &str, args: &[String], launch: &LaunchOptions) -> Result<Inferior, String> {
         let mut cmd = Command::new(target);
         cmd.args(args);
+        let [stdin, stdout, stderr] = launch.stdio()?;
+        cmd.stdin(stdin).stdout(stdout).stderr(stderr);
+        if let Some(environment) = &launch.environment {
+            cmd.env_clear().envs(environment);
+        }
+        if let Some(cwd) = &launch.cwd {
+            cmd.current_dir(cwd);
+        }
+        let new_session = launch.tty.is_some();
         // The unsafe block acts as a warning to avoid allocating memory or accessing 
         // shared data in the presence of threads
         unsafe {
-            cmd.pre_exec(child_traceme);
+            cmd.pre_exec(move || {
+                // a terminal of its own, so that e.g. ctrl+c typed there interrupts the inferior
+                if new_session && nix::unistd::setsid().is_ok() {
+                    nix::libc::ioctl(0, nix::libc::TIOCSCTTY, 0);
+                }
+                child_traceme()
+            });
         }
 
         if let Ok(child) = cmd.spawn() {
             // delivery the breakpoints information to child process
-            let mut inferior = Inferior { child: child };
-            for (addr, breakpoint ) in breakpoints {
-                match inferior.write_byte(*addr, 0xcc as u8) {
-                    Ok(orig_byte) => {
-                        *breakpoint = Some(
-                            Breakpoint {
-                                addr: *addr,
-                                orig_byte: orig_byte,
-                            }
-                        );
-                    },
-                    Err(err) => println!("Inferior::write_byte for breakpoint error {}", err),
-                }
+            let mut inferior = Inferior {
+                pid: Pid::from_raw(child.id() as i32),
+                child: Some(child),
+                trace_syscalls: false,
+                in_syscall: false,
+                syscall_arguments: [0; 6],
+            };
+            // the child stops with SIGTRAP once it execs the target; its memory can't be
+            // touched until then
+            match inferior.wait(None) {
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, _)) => (),
+                _ => return Err("Error starting subprocess".to_string()),
             }
-            Some(inferior) // return the child process
+            // tells system call stops apart from SIGTRAPs
+            ptrace::setoptions(inferior.pid(), ptrace::Options::PTRACE_O_TRACESYSGOOD)
+                .map_err(|err| format!("Error starting subprocess ({})", err))?;
+            inferior.make_interruptible();
+            Ok(inferior) // return the child process
         } else {
-            None
+            Err("Error starting subprocess".to_string())
         }
     }
 
-    pub fn continue_exec(&mut self, breakpoints: &HashMap<usize, Option<Breakpoint>>) -> Result<Status, nix::Error> {
-        // we need to update regs as long as we update rip
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        if let Some(breakpoint_wrapper) = breakpoints.get(&(rip - 1)) {
-            if let Some(breakpoint) = breakpoint_wrapper {
-                let addr = breakpoint.addr;
-                let orig_byte = breakpoint.orig_byte;
-                // restore the first byte of the instruction we replaced
-                // afterwards we update the instruction pointer address content
-                self.write_byte(addr, orig_byte).unwrap();
-                regs.rip = (rip - 1) as u64;
-                ptrace::setregs(self.pid(), regs).unwrap();
-                // step to next instruction
-                ptrace::step(self.pid(), None).unwrap();
-                match self.wait(None) {
-                    Ok(status) => {
-                        match status {
-                            Status::Exited(code) => return Ok(Status::Exited(code)),
-                            Status::Signaled(sig) => return Ok(Status::Signaled(sig)),
-                            _ => {
-                                // restore 0xcc in the breakpoint position
-                                self.write_byte(addr, 0xcc).unwrap();
-                            },
-                        }
-                    },
-                    Err(err) => println!("Inferior::continue_exec wait error {}", err),
+    /// If the instruction pointer is at one of our breakpoints, executes the instruction it
+    /// replaced (by temporarily restoring its original bytes) and returns the status after that
+    /// single step. `signal`, if any, is delivered during the step. Returns None if there is no
+    /// breakpoint to step over.
+    fn step_over_breakpoint(&mut self, breakpoints: &HashMap<usize, Option<Breakpoint>>, signal: Option<signal::Signal>) -> Result<Option<Status>, nix::Error> {
+        let pc = Registers::read(self.pid())?.pc();
+        if let Some(Some(breakpoint)) = breakpoints.get(&pc) {
+            // restore the instruction we replaced
+            self.remove_trap(breakpoint)?;
+            // step to next instruction
+            ptrace::step(self.pid(), signal)?;
+            let status = self.wait(None)?;
+            if let Status::Stopped(_, _) = status {
+                // put the trap back in the breakpoint position
+                self.insert_trap(breakpoint.addr)?;
+            }
+            return Ok(Some(status));
+        }
+        Ok(None)
+    }
+
+    /// After a SIGTRAP caused by one of our traps, the instruction pointer may be past the
+    /// breakpoint (by one byte on x86-64). Moves it back so that the inferior is stopped at the
+    /// breakpoint address, ready to run the original instruction when it resumes.
+    fn rewind_breakpoint_trap(&mut self, status: Status, breakpoints: &HashMap<usize, Option<Breakpoint>>) -> Result<Status, nix::Error> {
+        if let Status::Stopped(signal::Signal::SIGTRAP, pc) = status {
+            let addr = pc.wrapping_sub(arch::TRAP_PC_OFFSET);
+            if let Some(Some(_)) = breakpoints.get(&addr) {
+                if addr != pc {
+                    let mut regs = Registers::read(self.pid())?;
+                    regs.set_pc(addr);
+                    regs.write(self.pid())?;
                 }
+                return Ok(Status::Stopped(signal::Signal::SIGTRAP, addr));
             }
         }
-        ptrace::cont(self.pid(), None)?; // Restart the stopped trace process
-        self.wait(None)
+        Ok(status)
+    }
+
+    pub fn continue_exec(&mut self, breakpoints: &HashMap<usize, Option<Breakpoint>>) -> Result<Status, nix::Error> {
+        self.resume(breakpoints, false, None)
+    }
+
+    /// Executes a single instruction.
+    pub fn step_instruction(&mut self, breakpoints: &HashMap<usize, Option<Breakpoint>>) -> Result<Status, nix::Error> {
+        self.resume(breakpoints, true, None)
+    }
+
+    /// Resumes the inferior until it stops again, or for a single instruction if `step` is set.
+    /// `signal`, if any, is delivered to the inferior as it resumes.
+    pub fn resume(&mut self, breakpoints: &HashMap<usize, Option<Breakpoint>>, step: bool, signal: Option<signal::Signal>) -> Result<Status, nix::Error> {
+        let signal = match self.step_over_breakpoint(breakpoints, signal)? {
+            Some(status) if step => return Ok(status),
+            Some(Status::Stopped(signal::Signal::SIGTRAP, _)) => None,
+            // the process exited or got a signal while executing the breakpoint's instruction
+            Some(status) => return Ok(status),
+            None => signal,
+        };
+        // resuming from a system call's entry other than with PTRACE_SYSCALL skips its exit stop
+        if step {
+            self.in_syscall = false;
+            ptrace::step(self.pid(), signal)?;
+            return self.wait(None);
+        }
+        if self.trace_syscalls {
+            ptrace::syscall(self.pid(), signal)?;
+        } else {
+            self.in_syscall = false;
+            ptrace::cont(self.pid(), signal)?; // Restart the stopped trace process
+        }
+        let status = self.wait(None)?;
+        self.rewind_breakpoint_trap(status, breakpoints)
+    }
+
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.as_mut()?.stdout.take()
+    }
+
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.as_mut()?.stderr.take()
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Writes the traps of `breakpoints`. Breakpoints that can't be written are left unset
+    /// (None).
+    pub fn insert_breakpoints(&mut self, breakpoints: &mut HashMap<usize, Option<Breakpoint>>) {
+        for (addr, breakpoint) in breakpoints {
+            *breakpoint = self.insert_trap(*addr).ok();
+        }
+    }
+
+    /// Returns the address the program starts at (its AT_ENTRY auxiliary vector entry), which
+    /// tells where it was loaded.
+    pub fn entry_point(&self) -> Result<usize, nix::Error> {
+        let auxv = std::fs::read(format!("/proc/{}/auxv", self.pid)).map_err(|_| nix::Error::EIO)?;
+        auxv.chunks_exact(16)
+            .map(|entry| {
+                let word = |bytes: &[u8]| u64::from_ne_bytes(bytes.try_into().unwrap());
+                (word(&entry[..8]), word(&entry[8..]))
+            })
+            .find(|(key, _)| *key == libc::AT_ENTRY)
+            .map(|(_, value)| value as usize)
+            .ok_or(nix::Error::ENOENT)
+    }
+
+    /// Checks whether the inferior's address space is randomized, i.e. whether `Inferior::new`
+    /// couldn't turn address randomization off.
+    pub fn address_randomized(&self) -> Result<bool, nix::Error> {
+        let persona = std::fs::read_to_string(format!("/proc/{}/personality", self.pid)).map_err(|_| nix::Error::EIO)?;
+        let persona = i32::from_str_radix(persona.trim(), 16).map_err(|_| nix::Error::EINVAL)?;
+        Ok(persona & Persona::ADDR_NO_RANDOMIZE.bits() == 0)
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
+    pub fn wait(&mut self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
         Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+            WaitStatus::Exited(_pid, exit_code) => {
+                self.stop_interrupts();
+                Status::Exited(exit_code)
+            }
+            WaitStatus::Signaled(_pid, signal, _core_dumped) => {
+                self.stop_interrupts();
+                Status::Signaled(signal)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            WaitStatus::Stopped(_pid, signal) => Status::Stopped(signal, Registers::read(self.pid())?.pc()),
+            WaitStatus::PtraceSyscall(_pid) => {
+                // the stops alternate between entries and exits
+                self.in_syscall = !self.in_syscall;
+                let regs = Registers::read(self.pid())?;
+                if self.in_syscall {
+                    // aarch64 overwrites the first one with the result
+                    self.syscall_arguments = regs.syscall_arguments();
+                }
+                Status::Syscall(!self.in_syscall, regs.pc())
+            }
+            WaitStatus::PtraceEvent(_pid, signal, _event) => Status::Stopped(signal, Registers::read(self.pid())?.pc()),
+            // only reported with WNOHANG or WCONTINUED, which aren't used; the inferior hasn't
+            // stopped
+            _ => return Err(nix::Error::EAGAIN),
         })
     }
 
+    /// Returns whether the inferior is stopped at the entry to a system call.
+    pub fn in_syscall(&self) -> bool {
+        self.in_syscall
+    }
+
+    /// Returns the arguments of the system call at whose entry the inferior last stopped.
+    pub fn syscall_arguments(&self) -> [u64; 6] {
+        self.syscall_arguments
+    }
+
+    /// Makes this the inferior deet's SIGINT handler interrupts.
+    pub fn make_interruptible(&self) {
+        INTERRUPT_PID.store(self.pid.as_raw(), Ordering::SeqCst);
+    }
+
+    /// Stops the SIGINT handler from interrupting this inferior, once its pid may be reused.
+    fn stop_interrupts(&self) {
+        let _ = INTERRUPT_PID.compare_exchange(self.pid().as_raw(), 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
     pub fn kill(&mut self) {
-        println!("Killing running inferior (pid {})", self.pid());
-        match self.child.try_wait() {
-            Ok(None) => self.child.kill().expect("Child has already exited before you call 'kill'"),
-            _ => (), // child has exited or no child need to wait
+        self.stop_interrupts();
+        match self.child.as_mut() {
+            // if the child has already exited there is nothing to kill
+            Some(child) => {
+                if let Ok(None) = child.try_wait() {
+                    child.kill().expect("Child has already exited before you call 'kill'");
+                }
+            }
+            None => {
+                if signal::kill(self.pid, signal::Signal::SIGKILL).is_ok() {
+                    let _ = waitpid(self.pid, None);
+                }
+            }
+        }
+    }
+
+    /// Makes the stopped inferior call fork(), and returns the copy it creates, stopped at the
+    /// same point. deet traces the copy, which keeps the inferior's state from this moment for
+    /// as long as it stays stopped.
+    pub fn fork(&mut self) -> Result<Inferior, nix::Error> {
+        let pid = self.pid;
+        let regs = Registers::read(pid)?;
+        let pc = regs.pc();
+        // run a syscall instruction in place of the code at pc, then put everything back
+        let saved = self.write_bytes(pc, &arch::SYSCALL)?;
+        regs.fork_call().write(pid)?;
+        ptrace::setoptions(pid, ptrace::Options::PTRACE_O_TRACESYSGOOD | ptrace::Options::PTRACE_O_TRACEFORK)?;
+        let forked = self.run_fork();
+        ptrace::setoptions(pid, ptrace::Options::PTRACE_O_TRACESYSGOOD)?;
+        self.write_bytes(pc, &saved)?;
+        regs.write(pid)?;
+        let mut copy = Inferior {
+            child: None,
+            pid: forked?,
+            trace_syscalls: false,
+            in_syscall: false,
+            syscall_arguments: [0; 6],
+        };
+        // the copy starts out stopped by SIGSTOP, with the same memory and registers as the
+        // inferior right after the fork
+        waitpid(copy.pid, None)?;
+        copy.write_bytes(pc, &saved)?;
+        regs.write(copy.pid)?;
+        Ok(copy)
+    }
+
+    /// Steps through the fork() set up by `fork`, returning the new process's pid.
+    fn run_fork(&mut self) -> Result<Pid, nix::Error> {
+        loop {
+            ptrace::step(self.pid, None)?;
+            match waitpid(self.pid, None)? {
+                WaitStatus::PtraceEvent(_, _, event) if event == ptrace::Event::PTRACE_EVENT_FORK as i32 => {
+                    let child = Pid::from_raw(ptrace::getevent(self.pid)? as i32);
+                    // finish the system call
+                    ptrace::step(self.pid, None)?;
+                    waitpid(self.pid, None)?;
+                    return Ok(child);
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {
+                    // fork() failed, and returned the error
+                    let ret = Registers::read(self.pid)?.syscall_return() as i64;
+                    return Err(nix::Error::from_i32(-ret as i32));
+                }
+                // a signal that was pending, such as the SIGCHLD of a copy made earlier
+                // exiting, is discarded rather than delivered before the fork
+                WaitStatus::Stopped(..) => continue,
+                _ => return Err(nix::Error::ESRCH),
+            }
         }
     }
 
+    /// Reads `len` bytes of the inferior's memory starting at `addr`.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < addr + len {
+            let word = ptrace::read(self.pid(), word_addr as ptrace::AddressType)? as u64;
+            bytes.extend_from_slice(&word.to_ne_bytes());
+            word_addr += size_of::<usize>();
+        }
+        let start = addr - align_addr_to_word(addr);
+        Ok(bytes[start..start + len].to_vec())
+    }
+
     pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         let aligned_addr = align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
         let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
+        let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+        let masked_word = word & !(0xff << (8 * byte_offset));
+        let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
         unsafe {
             ptrace::write(
                 self.pid(),
@@ -151,4 +454,21 @@ impl Inferior {
         Ok(orig_byte as u8)
     }
 
+    /// Writes `bytes` to the inferior's memory at `addr`, returning the bytes they replaced.
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<Vec<u8>, nix::Error> {
+        bytes.iter().enumerate().map(|(i, byte)| self.write_byte(addr + i, *byte)).collect()
+    }
+
+    /// Plants a trap at `addr`, returning the breakpoint that records what it replaced.
+    pub fn insert_trap(&mut self, addr: usize) -> Result<Breakpoint, nix::Error> {
+        let mut orig_bytes = [0; arch::TRAP.len()];
+        orig_bytes.copy_from_slice(&self.write_bytes(addr, &arch::TRAP)?);
+        Ok(Breakpoint { addr, orig_bytes })
+    }
+
+    /// Puts back the instruction `breakpoint`'s trap replaced.
+    pub fn remove_trap(&mut self, breakpoint: &Breakpoint) -> Result<(), nix::Error> {
+        self.write_bytes(breakpoint.addr, &breakpoint.orig_bytes).map(|_| ())
+    }
 }
+
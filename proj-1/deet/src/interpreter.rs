@@ -0,0 +1,252 @@
+//! Output of the debugger. Everything deet reports is an `Event`, which is either printed for a
+//! person (the console interpreter) or written as one JSON record per line for programs driving
+//! deet (`--interpreter=json`). The DAP server (`--dap`) collects events and translates them into
+//! Debug Adapter Protocol messages.
+
+use crate::dwarf_data::Line;
+use nix::sys::signal::Signal;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Receives the inferior's output as (stream name, text) pairs.
+pub type OutputHandler = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Interpreter {
+    Console,
+    Json,
+    Dap,
+}
+
+impl Interpreter {
+    pub fn from_name(name: &str) -> Option<Interpreter> {
+        match name {
+            "console" => Some(Interpreter::Console),
+            "json" => Some(Interpreter::Json),
+            _ => None,
+        }
+    }
+}
+
+/// One frame of a backtrace.
+#[derive(Clone)]
+pub struct Frame {
+    pub function: Option<String>,
+    pub line: Option<Line>,
+    pub addr: usize,
+    /// The frame's frame pointer and stack pointer, which locate its variables. They are not shown
+    /// to the user.
+    pub base_ptr: usize,
+    pub stack_ptr: usize,
+    /// Set for a function inlined into the frame below it, which shares that frame's registers.
+    pub inlined: bool,
+}
+
+/// Why the inferior stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    /// It hit the user breakpoint with this number.
+    Breakpoint(usize),
+    /// A `step`, `next` or `finish` completed.
+    Step,
+    /// It entered or returned from a system call caught by the catchpoint with this number.
+    Catchpoint(usize),
+    Signal,
+}
+
+pub enum Event {
+    Stopped {
+        signal: Signal,
+        addr: usize,
+        function: Option<String>,
+        line: Option<Line>,
+        reason: StopReason,
+    },
+    Exited(i32),
+    Signaled(Signal),
+    /// A breakpoint was set at these addresses (several for a function that was inlined).
+    BreakpointSet { num: usize, addrs: Vec<usize> },
+    Backtrace(Vec<Frame>),
+    Value { name: String, value: String },
+    /// Informational output that has no structure of its own.
+    Message(String),
+    Error(String),
+}
+
+impl Event {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Event::Stopped {
+                signal,
+                addr,
+                function,
+                line,
+                reason,
+            } => {
+                let (reason, breakpoint) = match reason {
+                    StopReason::Breakpoint(num) => ("breakpoint", Some(num)),
+                    StopReason::Step => ("step", None),
+                    StopReason::Catchpoint(num) => ("catchpoint", Some(num)),
+                    StopReason::Signal => ("signal", None),
+                };
+                json!({
+                    "type": "event",
+                    "event": "stopped",
+                    "reason": reason,
+                    "signal": signal.as_str(),
+                    "address": addr,
+                    "function": function,
+                    "file": line.as_ref().map(|line| &line.file),
+                    "line": line.as_ref().map(|line| line.number),
+                    "breakpoint": breakpoint,
+                })
+            }
+            Event::Exited(code) => json!({"type": "event", "event": "exited", "code": code}),
+            Event::Signaled(signal) => {
+                json!({"type": "event", "event": "signaled", "signal": signal.as_str()})
+            }
+            Event::BreakpointSet { num, addrs } => json!({
+                "type": "event",
+                "event": "breakpoint-created",
+                "number": num,
+                "address": addrs.first(),
+                "addresses": addrs,
+            }),
+            Event::Backtrace(frames) => {
+                let frames: Vec<Value> = frames
+                    .iter()
+                    .map(|frame| {
+                        json!({
+                            "function": frame.function,
+                            "file": frame.line.as_ref().map(|line| &line.file),
+                            "line": frame.line.as_ref().map(|line| line.number),
+                            "address": frame.addr,
+                            "inlined": frame.inlined,
+                        })
+                    })
+                    .collect();
+                json!({"type": "event", "event": "backtrace", "frames": frames})
+            }
+            Event::Value { name, value } => {
+                json!({"type": "event", "event": "value", "name": name, "value": value})
+            }
+            Event::Message(text) => json!({"type": "output", "text": text}),
+            Event::Error(message) => json!({"type": "error", "message": message}),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inlined = if self.inlined { " [inlined]" } else { "" };
+        match (&self.function, &self.line) {
+            (Some(function), Some(line)) => write!(f, "{}{} ({})", function, inlined, line),
+            (Some(function), None) => write!(f, "{}{} ({:#x})", function, inlined, self.addr),
+            _ => write!(f, "?? ({:#x})", self.addr),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Stopped {
+                signal,
+                addr,
+                function,
+                line,
+                reason,
+            } => {
+                // a completed step is expected, so only where it ended up is interesting, and
+                // a catchpoint has already described its stop
+                if !matches!(reason, StopReason::Step | StopReason::Catchpoint(_)) {
+                    writeln!(f, "Child stopped (signal {}, address {:#x})", signal, addr)?;
+                }
+                let frame = Frame {
+                    function: function.clone(),
+                    line: line.clone(),
+                    addr: *addr,
+                    base_ptr: 0,
+                    stack_ptr: 0,
+                    inlined: false,
+                };
+                write!(f, "Stopped at {}", frame)
+            }
+            Event::Exited(code) => write!(f, "Child exited (status {})", code),
+            Event::Signaled(signal) => write!(f, "Child exited (signal {})", signal),
+            Event::BreakpointSet { num, addrs } => {
+                let addrs: Vec<String> = addrs.iter().map(|addr| format!("{:#x}", addr)).collect();
+                write!(f, "Set breakpoint {} at {}", num, addrs.join(", "))
+            }
+            Event::Backtrace(frames) => {
+                let lines: Vec<String> = frames.iter().map(|frame| frame.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Event::Value { name, value } => write!(f, "{} = {}", name, value),
+            Event::Message(text) | Event::Error(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Parses a JSON request line of the form `{"id": 1, "command": "break main"}`. The command
+/// may contain several lines (e.g. a whole `define` block). Returns the id, if any, and the
+/// command lines.
+pub fn parse_request(line: &str) -> Result<(Option<Value>, Vec<String>), String> {
+    let request: Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let command = request
+        .get("command")
+        .and_then(Value::as_str)
+        .ok_or("request has no \"command\" string")?;
+    let commands = command.lines().map(|line| line.to_string()).collect();
+    Ok((request.get("id").cloned(), commands))
+}
+
+/// Prints a chunk of the inferior's output as a `target-output` record, so that it does not
+/// corrupt the JSON stream on deet's own stdout.
+pub fn print_target_output_record(stream: &str, text: &str) {
+    println!(
+        "{}",
+        json!({"type": "target-output", "stream": stream, "text": text})
+    );
+}
+
+/// Passes everything the inferior writes to `reader` on to `handler`. The returned thread
+/// finishes once the inferior closes its end of the pipe.
+pub fn forward_target_output<R: Read + Send + 'static>(
+    stream: &'static str,
+    mut reader: R,
+    handler: OutputHandler,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok(len) = reader.read(&mut buf) {
+            if len == 0 {
+                break;
+            }
+            handler(stream, &String::from_utf8_lossy(&buf[..len]));
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let (id, commands) =
+            parse_request(r#"{"id": 7, "command": "define foo\nprint $arg0\nend"}"#).unwrap();
+        assert_eq!(id, Some(json!(7)));
+        assert_eq!(commands, vec!["define foo", "print $arg0", "end"]);
+
+        let (id, commands) = parse_request(r#"{"command": "bt"}"#).unwrap();
+        assert_eq!(id, None);
+        assert_eq!(commands, vec!["bt"]);
+
+        assert!(parse_request(r#"{"id": 1}"#).is_err());
+        assert!(parse_request("bt").is_err());
+    }
+}
@@ -1,25 +1,182 @@
+mod arch;
+mod completion;
+mod coverage;
+mod dap;
 mod debugger;
 mod debugger_command;
+mod gdbserver;
+mod heap;
 mod inferior;
+mod interpreter;
+mod profile;
+mod session;
 // debugging symbols
 mod dwarf_data;
+mod dwarf_index;
 mod gimli_wrapper;
+mod symbols;
+mod syscalls;
+mod tui;
+mod value_format;
 
 use crate::debugger::Debugger;
-use nix::sys::signal::{signal, SigHandler, Signal};
+use crate::interpreter::Interpreter;
 use std::env;
 
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} [-x <command file>]... [-batch] [-nx] [--tui] [--interpreter=console|json] [--dap[=[host]:port]] <target program>",
+        program
+    );
+    println!("       {} --gdbserver [host]:port <target program> [args]...", program);
+    println!("       {} profile [--hz <samples per second>] [-o <folded stacks file>] <target program> [args]...", program);
+    println!("       {} coverage [-o <lcov file>] <target program> [args]...", program);
+    std::process::exit(1);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    if args.get(1).map(String::as_str) == Some("profile") {
+        profile(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("coverage") {
+        coverage(&args);
+    }
+    let mut command_files = Vec::new();
+    let mut batch = false;
+    let mut read_init_file = true;
+    let mut tui = false;
+    let mut interpreter = Interpreter::Console;
+    let mut dap = None;
+    let mut gdbserver = None;
+    let mut target = None;
+    let mut target_args = Vec::new();
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            // everything after the target program is passed to it
+            _ if target.is_some() => target_args.push(arg.clone()),
+            "-x" | "--command" => match iter.next() {
+                Some(file) => command_files.push(file.clone()),
+                None => usage(&args[0]),
+            },
+            "-batch" | "--batch" => batch = true,
+            "-nx" | "--nx" => read_init_file = false,
+            "--tui" => tui = true,
+            _ if arg.starts_with("--interpreter=") => {
+                match Interpreter::from_name(&arg["--interpreter=".len()..]) {
+                    Some(selected) => interpreter = selected,
+                    None => usage(&args[0]),
+                }
+            }
+            "--gdbserver" => match iter.next() {
+                Some(addr) => gdbserver = Some(addr.clone()),
+                None => usage(&args[0]),
+            },
+            "--dap" => dap = Some(None),
+            _ if arg.starts_with("--dap=") => dap = Some(Some(arg["--dap=".len()..].to_string())),
+            _ if !arg.starts_with('-') => target = Some(arg.clone()),
+            _ => usage(&args[0]),
+        }
+    }
+    let target = target.unwrap_or_else(|| usage(&args[0]));
+    // only gdbserver starts the program itself; otherwise its arguments are given to `run`
+    if gdbserver.is_none() && !target_args.is_empty() {
+        usage(&args[0]);
+    }
+
+    if let Some(listen_addr) = gdbserver {
+        if let Err(err) = gdbserver::serve(&target, &target_args, &listen_addr) {
+            eprintln!("gdbserver: {}", err);
+            std::process::exit(1);
+        }
+        return;
     }
-    let target = &args[1];
 
-    // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
-    // processes)
-    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+    // ctrl+c interrupts the inferior rather than deet
+    inferior::install_interrupt_handler().expect("Error installing SIGINT handler");
+
+    if dap.is_some() {
+        interpreter = Interpreter::Dap;
+    }
+    let mut debugger = Debugger::new(&target, interpreter);
+    if read_init_file {
+        debugger.source_init_file();
+        // a batch run is a script of its own, which the session would change under it
+        if !batch {
+            debugger.load_session();
+        }
+    }
+    for file in &command_files {
+        if let Err(err) = debugger.source(file) {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
+        }
+    }
+    if let Some(listen_addr) = dap {
+        // run the startup commands (e.g. breakpoints) before the client takes over
+        debugger.execute_commands(Vec::new());
+        if let Err(err) = dap::serve(&mut debugger, listen_addr.as_deref()) {
+            eprintln!("DAP server error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    debugger.set_batch(batch);
+    if tui && !batch {
+        debugger.set_tui(true);
+    }
+    debugger.run();
+
+    if batch {
+        std::process::exit(debugger.exit_status());
+    }
+}
+
+/// `deet profile`: runs the target under the sampling profiler, and exits with its exit status.
+fn profile(args: &[String]) -> ! {
+    let mut hz = 1000;
+    let mut folded_path = "deet.folded".to_string();
+    let mut iter = args.iter().skip(2);
+    let target = loop {
+        match iter.next().map(String::as_str) {
+            Some("--hz") => match iter.next().and_then(|hz| hz.parse().ok()) {
+                Some(selected) if selected > 0 => hz = selected,
+                _ => usage(&args[0]),
+            },
+            Some("-o") => match iter.next() {
+                Some(path) => folded_path = path.clone(),
+                None => usage(&args[0]),
+            },
+            Some(target) if !target.starts_with('-') => break target.to_string(),
+            _ => usage(&args[0]),
+        }
+    };
+    let target_args: Vec<String> = iter.cloned().collect();
+    inferior::install_interrupt_handler().expect("Error installing SIGINT handler");
+    let mut debugger = Debugger::new(&target, Interpreter::Console);
+    debugger.profile(&target_args, hz, &folded_path);
+    std::process::exit(debugger.exit_status());
+}
 
-    Debugger::new(target).run();
+/// `deet coverage`: runs the target with line coverage, and exits with its exit status.
+fn coverage(args: &[String]) -> ! {
+    let mut lcov_path = "coverage.info".to_string();
+    let mut iter = args.iter().skip(2);
+    let target = loop {
+        match iter.next().map(String::as_str) {
+            Some("-o") => match iter.next() {
+                Some(path) => lcov_path = path.clone(),
+                None => usage(&args[0]),
+            },
+            Some(target) if !target.starts_with('-') => break target.to_string(),
+            _ => usage(&args[0]),
+        }
+    };
+    let target_args: Vec<String> = iter.cloned().collect();
+    inferior::install_interrupt_handler().expect("Error installing SIGINT handler");
+    let mut debugger = Debugger::new(&target, Interpreter::Console);
+    debugger.coverage(&target_args, &lcov_path);
+    std::process::exit(debugger.exit_status());
 }
@@ -0,0 +1,112 @@
+//! The sampling profiler run by `deet profile`. The inferior is stopped at regular intervals and
+//! its stack unwound; the stacks are counted by function and by source line for a flat profile,
+//! and written out whole as folded stacks (`main;f;g 42`), which flamegraph tools read.
+
+use crate::interpreter::Frame;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+#[derive(Default)]
+pub struct Profile {
+    samples: usize,
+    /// The samples each function was executing in itself, and those it was on the stack in.
+    functions: HashMap<String, (usize, usize)>,
+    /// The samples each source line was executing in.
+    lines: HashMap<String, usize>,
+    /// The samples of each stack, outermost function first, joined with `;`.
+    stacks: HashMap<String, usize>,
+}
+
+impl Profile {
+    /// Counts a sample of the stack `frames`, innermost first like a backtrace.
+    pub fn add_sample(&mut self, frames: &[Frame]) {
+        self.samples += 1;
+        let names: Vec<String> = frames
+            .iter()
+            .map(|frame| frame.function.clone().unwrap_or_else(|| format!("{:#x}", frame.addr)))
+            .collect();
+        // a recursive function counts once towards its total in each sample
+        let mut seen = HashSet::new();
+        for (index, name) in names.iter().enumerate() {
+            let counts = self.functions.entry(name.clone()).or_default();
+            if index == 0 {
+                counts.0 += 1;
+            }
+            if seen.insert(name) {
+                counts.1 += 1;
+            }
+        }
+        if let Some(line) = frames.first().and_then(|frame| frame.line.as_ref()) {
+            *self.lines.entry(line.to_string()).or_default() += 1;
+        }
+        let stack: Vec<&str> = names.iter().rev().map(String::as_str).collect();
+        *self.stacks.entry(stack.join(";")).or_default() += 1;
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Formats the flat profile: the functions by the samples they were executing in, with the
+    /// samples they were on the stack in, then the source lines by the samples they were
+    /// executing in.
+    pub fn report(&self) -> String {
+        let percent = |count: usize| 100.0 * count as f64 / self.samples.max(1) as f64;
+        let mut functions: Vec<(&String, &(usize, usize))> = self.functions.iter().collect();
+        functions.sort_by(|a, b| (b.1 .0, b.1 .1, a.0).cmp(&(a.1 .0, a.1 .1, b.0)));
+        let mut out = format!("{:>7} {:>7} {:>7} {:>7}  function\n", "self%", "self", "total%", "total");
+        for (name, (own, total)) in functions {
+            let _ = writeln!(out, "{:>6.2}% {:>7} {:>6.2}% {:>7}  {}", percent(*own), own, percent(*total), total, name);
+        }
+        let mut lines: Vec<(&String, &usize)> = self.lines.iter().collect();
+        lines.sort_by(|a, b| (b.1, a.0).cmp(&(a.1, b.0)));
+        let _ = write!(out, "\n{:>7} {:>7}  line\n", "self%", "self");
+        for (line, count) in lines {
+            let _ = writeln!(out, "{:>6.2}% {:>7}  {}", percent(*count), count, line);
+        }
+        out
+    }
+
+    /// Formats the stacks in the folded format, one stack per line with its sample count.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<(&String, &usize)> = self.stacks.iter().collect();
+        stacks.sort();
+        stacks.iter().map(|(stack, count)| format!("{} {}\n", stack, count)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::Line;
+
+    fn frame(function: &str, line: usize) -> Frame {
+        Frame {
+            function: Some(function.to_string()),
+            line: Some(Line {
+                file: "fib.c".to_string(),
+                number: line,
+                address: 0,
+            }),
+            addr: 0x1000,
+            base_ptr: 0,
+            stack_ptr: 0,
+            inlined: false,
+        }
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut profile = Profile::default();
+        profile.add_sample(&[frame("fib", 8), frame("fib", 8), frame("main", 12)]);
+        profile.add_sample(&[frame("main", 13)]);
+        profile.add_sample(&[frame("fib", 9), frame("main", 12)]);
+        assert_eq!(profile.samples(), 3);
+        assert_eq!(profile.functions["fib"], (2, 2));
+        assert_eq!(profile.functions["main"], (1, 3));
+        assert_eq!(profile.folded(), "main 1\nmain;fib 1\nmain;fib;fib 1\n");
+        let report = profile.report();
+        assert!(report.contains(" 66.67%       2  66.67%       2  fib\n"));
+        assert!(report.contains(" 33.33%       1  fib.c:8\n"));
+    }
+}
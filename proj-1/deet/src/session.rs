@@ -0,0 +1,90 @@
+//! Sessions saved between runs of deet. The breakpoints and settings are saved as the commands
+//! that recreate them, with breakpoints on symbolic locations so that they still apply after
+//! the program is rebuilt. A project's `.deet/session` file holds a section per target, each
+//! headed by a `# target <path>` line.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The session file, relative to the directory deet is started in.
+pub const SESSION_PATH: &str = ".deet/session";
+
+const HEADER: &str = "# target ";
+
+/// Returns the commands in the section of `contents` for `target`.
+pub fn section(contents: &str, target: &str) -> Vec<String> {
+    let mut lines = contents.lines().skip_while(|line| line.strip_prefix(HEADER) != Some(target));
+    lines.next();
+    lines.take_while(|line| !line.starts_with(HEADER)).map(|line| line.to_string()).collect()
+}
+
+/// Returns `contents` with the section for `target` replaced by `commands`, or added at the end
+/// if it has none.
+pub fn replace_section(contents: &str, target: &str, commands: &[String]) -> String {
+    let mut out = String::new();
+    let mut skipping = false;
+    for line in contents.lines() {
+        if let Some(header) = line.strip_prefix(HEADER) {
+            skipping = header == target;
+        }
+        if !skipping {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out.push_str(HEADER);
+    out.push_str(target);
+    out.push('\n');
+    for command in commands {
+        out.push_str(command);
+        out.push('\n');
+    }
+    out
+}
+
+/// Reads the commands saved for `target`, if the session file exists.
+pub fn load(target: &str) -> io::Result<Vec<String>> {
+    match fs::read_to_string(SESSION_PATH) {
+        Ok(contents) => Ok(section(&contents, target)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Saves `commands` for `target`, keeping the other targets' sections. Does nothing unless the
+/// project has a `.deet` directory, so sessions are only kept where they were asked for.
+pub fn save(target: &str, commands: &[String]) -> io::Result<()> {
+    if !Path::new(SESSION_PATH).parent().is_some_and(Path::is_dir) {
+        return Ok(());
+    }
+    let contents = match fs::read_to_string(SESSION_PATH) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    fs::write(SESSION_PATH, replace_section(&contents, target, commands))
+}
+
+/// Returns the key a target's section is saved under: its absolute path.
+pub fn target_key(target: &str) -> String {
+    fs::canonicalize(target).map_or_else(|_| target.to_string(), |path| path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sections() {
+        let commands = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect::<Vec<String>>();
+        let contents = replace_section("", "/p/a", &commands(&["break main", "commands", "print x", "end"]));
+        let contents = replace_section(&contents, "/p/b", &commands(&["break f"]));
+        assert_eq!(contents, "# target /p/a\nbreak main\ncommands\nprint x\nend\n# target /p/b\nbreak f\n");
+        assert_eq!(section(&contents, "/p/a"), commands(&["break main", "commands", "print x", "end"]));
+        let contents = replace_section(&contents, "/p/a", &commands(&["trace syscalls on"]));
+        assert_eq!(contents, "# target /p/b\nbreak f\n# target /p/a\ntrace syscalls on\n");
+        assert_eq!(section(&contents, "/p/b"), commands(&["break f"]));
+        assert!(section(&contents, "/p/c").is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! ELF symbol tables, which name the functions and variables of code that has no debugging
+//! information: programs built without `-g` and the shared libraries they use, such as the C
+//! library.
+
+use crate::dwarf_data::demangle;
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::fs;
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// The address of the symbol in the running program.
+    pub address: usize,
+    /// The size in bytes.
+    pub size: usize,
+    pub section: String,
+    /// Set for functions, unset for variables.
+    pub function: bool,
+}
+
+/// The function and variable symbols of an ELF file, sorted by address.
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    /// The file the symbols come from, for shared libraries.
+    pub library: Option<String>,
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Reads the symbols of `object`, which is loaded `load_bias` bytes above the addresses it
+    /// was linked at. .symtab is used if the file has one; stripped files only have .dynsym.
+    pub fn from_object(object: &object::File, load_bias: usize) -> SymbolTable {
+        let mut symbols: Vec<(bool, Symbol)> = Vec::new();
+        let table = match object.symbols().next() {
+            Some(_) => object.symbols(),
+            None => object.dynamic_symbols(),
+        };
+        for symbol in table {
+            if !symbol.is_definition() || !matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Data) {
+                continue;
+            }
+            let name = match symbol.name() {
+                Ok(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            let section = match symbol.section_index().and_then(|index| object.section_by_index(index).ok()) {
+                Some(section) => section,
+                None => continue,
+            };
+            // a symbol without a size (e.g. one defined in assembly) extends to the end of
+            // its section at most
+            let size = match symbol.size() {
+                0 => (section.address() + section.size()).saturating_sub(symbol.address()),
+                size => size,
+            };
+            let preferred = symbol.is_global() && !symbol.is_weak();
+            symbols.push((
+                preferred,
+                Symbol {
+                    name: demangle(name),
+                    address: symbol.address() as usize + load_bias,
+                    size: size as usize,
+                    section: section.name().unwrap_or_default().to_string(),
+                    function: symbol.kind() == SymbolKind::Text,
+                },
+            ));
+        }
+        // of several names for one address (e.g. malloc and __libc_malloc), the global one is
+        // listed first, which is the one addresses are shown with
+        symbols.sort_by_key(|(preferred, symbol)| (symbol.address, !preferred));
+        SymbolTable {
+            library: None,
+            symbols: symbols.into_iter().map(|(_, symbol)| symbol).collect(),
+        }
+    }
+
+    /// Reads the symbols of the shared library at `path`, whose first segment is mapped at
+    /// `start`.
+    pub fn from_library(path: &str, start: usize) -> Option<SymbolTable> {
+        let data = fs::read(path).ok()?;
+        let object = object::File::parse(&*data).ok()?;
+        let first_segment = object.segments().map(|segment| segment.address()).min()? as usize;
+        let load_bias = start.checked_sub(first_segment & !0xfff)?;
+        let mut table = SymbolTable::from_object(&object, load_bias);
+        table.library = Some(path.to_string());
+        Some(table)
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Looks up a symbol by name.
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Finds the symbol `addr` is in, along with the offset of `addr` from its start.
+    pub fn containing(&self, addr: usize) -> Option<(&Symbol, usize)> {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= addr).checked_sub(1)?;
+        // the first of the symbols at that address
+        let start = self.symbols[index].address;
+        let symbol = &self.symbols[self.symbols.partition_point(|symbol| symbol.address < start)];
+        let offset = addr - symbol.address;
+        (offset < symbol.size).then_some((symbol, offset))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn symbol(name: &str, address: usize, size: usize) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            address,
+            size,
+            section: ".text".to_string(),
+            function: true,
+        }
+    }
+
+    #[test]
+    fn test_containing() {
+        let table = SymbolTable {
+            library: None,
+            symbols: vec![
+                symbol("malloc", 0x1000, 0x20),
+                symbol("__libc_malloc", 0x1000, 0x20),
+                symbol("_start", 0x1040, 0x10),
+            ],
+        };
+        let (found, offset) = table.containing(0x1010).unwrap();
+        assert_eq!((found.name.as_str(), offset), ("malloc", 0x10));
+        // between two symbols
+        assert!(table.containing(0x1030).is_none());
+        assert_eq!(table.containing(0x104f).unwrap().1, 0xf);
+        assert!(table.containing(0x1050).is_none());
+        assert!(table.containing(0xfff).is_none());
+        assert_eq!(table.lookup("__libc_malloc").unwrap().address, 0x1000);
+    }
+}
@@ -0,0 +1,336 @@
+//! A terminal UI with panes showing the source around the current line, the disassembly around
+//! the program counter, the registers and the backtrace. The panes are drawn with ANSI escape
+//! sequences above a scrolling region at the bottom of the terminal, where the prompt and the
+//! output of commands (and of the inferior) carry on as usual.
+
+use crate::dwarf_data::Line;
+use crate::interpreter::Frame;
+#[cfg(target_arch = "x86_64")]
+use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, IntelFormatter};
+use nix::libc;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+
+/// The smallest terminal the panes fit in.
+const MIN_ROWS: u16 = 20;
+const MIN_COLS: u16 = 60;
+
+/// What the panes show, gathered by the debugger each time they are drawn.
+#[derive(Default)]
+pub struct View {
+    /// The line the inferior is stopped at.
+    pub line: Option<Line>,
+    /// The file and line number of every breakpoint.
+    pub breakpoint_lines: HashSet<(String, usize)>,
+    /// None when there is no inferior.
+    pub pc: Option<usize>,
+    /// The machine code of the function being executed (with breakpoints removed), and the
+    /// address it starts at.
+    pub code: (usize, Vec<u8>),
+    pub breakpoint_addrs: HashSet<usize>,
+    pub registers: Vec<(&'static str, u64)>,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Normal,
+    /// The current line or instruction.
+    Current,
+    /// A register that changed at the last stop.
+    Changed,
+}
+
+/// A line of a pane, in pieces that may be styled differently.
+type PaneLine = Vec<(String, Style)>;
+
+pub struct Tui {
+    /// The terminal size the layout was set up for.
+    size: (u16, u16),
+    /// Source files that have been shown, by path.
+    sources: HashMap<String, Vec<String>>,
+    /// The registers as last drawn, and which of them had changed then. The highlighting only
+    /// moves on once the registers change again, so commands like `print` leave it alone.
+    registers: Vec<(&'static str, u64)>,
+    changed: HashSet<&'static str>,
+}
+
+impl Tui {
+    /// Switches the terminal to the TUI layout, or returns None if stdout isn't a terminal
+    /// large enough for it.
+    pub fn enable() -> Option<Tui> {
+        let size = terminal_size()?;
+        if size.0 < MIN_ROWS || size.1 < MIN_COLS {
+            return None;
+        }
+        let mut tui = Tui {
+            size,
+            sources: HashMap::new(),
+            registers: Vec::new(),
+            changed: HashSet::new(),
+        };
+        // on the alternate screen, so that the terminal's contents come back afterwards
+        print!("\x1b[?1049h");
+        tui.set_up();
+        Some(tui)
+    }
+
+    /// Clears the terminal and limits scrolling to the command area below the panes, leaving
+    /// the cursor at its bottom.
+    fn set_up(&mut self) {
+        let (rows, _) = self.size;
+        print!("\x1b[2J\x1b[{};{}r\x1b[{};1H", rows - command_rows(rows) + 1, rows, rows);
+        let _ = io::stdout().flush();
+    }
+
+    /// Redraws every pane, keeping the cursor where it is in the command area.
+    pub fn draw(&mut self, view: &View) {
+        if let Some(size) = terminal_size() {
+            if size != self.size && size.0 >= MIN_ROWS && size.1 >= MIN_COLS {
+                self.size = size;
+                self.set_up();
+            }
+        }
+        if view.registers != self.registers {
+            self.changed = view
+                .registers
+                .iter()
+                .filter(|(name, value)| self.registers.iter().any(|(other, old)| other == name && old != value))
+                .map(|(name, _)| *name)
+                .collect();
+            self.registers = view.registers.clone();
+        }
+        // the registers go across the top, above the source and disassembly on the left and
+        // the backtrace on the right
+        let (rows, cols) = self.size;
+        let registers = self.register_lines(view, cols as usize - 2);
+        // in a small terminal the registers that don't fit are cut off
+        let register_rows = ((registers.len() as u16).max(1) + 2).min((rows - command_rows(rows)) / 3);
+        let pane_rows = rows - command_rows(rows) - register_rows;
+        let (upper, lower) = (pane_rows / 2, pane_rows - pane_rows / 2);
+        let (left, right) = (cols * 2 / 3, cols - cols * 2 / 3);
+
+        let source_title = match &view.line {
+            Some(line) => format!("Source: {}", line.file),
+            None => "Source".to_string(),
+        };
+        let source = self.source_lines(view, upper.saturating_sub(2) as usize);
+        let disassembly = disassembly_lines(view, lower.saturating_sub(2) as usize);
+        let backtrace = view
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| vec![(format!("#{} {}", index, frame), Style::Normal)])
+            .collect();
+
+        // save the cursor, and restore it once the panes are drawn
+        let mut out = String::from("\x1b7");
+        let top = register_rows + 1;
+        draw_pane(&mut out, (1, 1), (register_rows, cols), "Registers", registers);
+        draw_pane(&mut out, (top, 1), (upper, left), &source_title, source);
+        draw_pane(&mut out, (top + upper, 1), (lower, left), "Disassembly", disassembly);
+        draw_pane(&mut out, (top, left + 1), (pane_rows, right), "Backtrace", backtrace);
+        out.push_str("\x1b8");
+        print!("{}", out);
+        let _ = io::stdout().flush();
+    }
+
+    /// The lines of the current file around the current line, which is highlighted. Lines
+    /// with breakpoints are marked with `b`.
+    fn source_lines(&mut self, view: &View, height: usize) -> Vec<PaneLine> {
+        let line = match &view.line {
+            Some(line) => line,
+            None => {
+                let message = if view.pc.is_some() { "[ No source available ]" } else { "[ No process ]" };
+                return vec![vec![(message.to_string(), Style::Normal)]];
+            }
+        };
+        let text = self.sources.entry(line.file.clone()).or_insert_with(|| {
+            fs::read_to_string(&line.file)
+                .map(|text| text.lines().map(|line| line.replace('\t', "    ")).collect())
+                .unwrap_or_default()
+        });
+        if text.is_empty() {
+            return vec![vec![(format!("[ Can't read {} ]", line.file), Style::Normal)]];
+        }
+        let first = line.number.saturating_sub(height / 2).max(1).min(text.len().saturating_sub(height) + 1);
+        (first..(first + height).min(text.len() + 1))
+            .map(|number| {
+                let breakpoint = view.breakpoint_lines.contains(&(line.file.clone(), number));
+                let marker = match (number == line.number, breakpoint) {
+                    (true, _) => '>',
+                    (false, true) => 'b',
+                    (false, false) => ' ',
+                };
+                let style = if number == line.number { Style::Current } else { Style::Normal };
+                vec![(format!("{}{:>5}  {}", marker, number, text[number - 1]), style)]
+            })
+            .collect()
+    }
+
+    /// The registers, in as many columns as fit in `width`, with the ones that changed at the
+    /// last stop highlighted.
+    fn register_lines(&self, view: &View, width: usize) -> Vec<PaneLine> {
+        const ENTRY_WIDTH: usize = 28;
+        let columns = (width / ENTRY_WIDTH).max(1);
+        view.registers
+            .chunks(columns)
+            .map(|row| {
+                row.iter()
+                    .map(|(name, value)| {
+                        let style = if self.changed.contains(name) { Style::Changed } else { Style::Normal };
+                        (format!("{:<8}{:#018x}  ", name, value), style)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Restores the terminal to scroll as a whole, and to what it showed before the TUI.
+impl Drop for Tui {
+    fn drop(&mut self) {
+        print!("\x1b[r\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// The rows at the bottom of the terminal that are left for the prompt and command output.
+fn command_rows(rows: u16) -> u16 {
+    (rows / 4).max(6)
+}
+
+/// Returns the rows and columns of the terminal stdout is connected to.
+fn terminal_size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+/// The instructions around the pc, which is highlighted. Instructions with breakpoints are
+/// marked with `b`.
+fn disassembly_lines(view: &View, height: usize) -> Vec<PaneLine> {
+    let pc = match view.pc {
+        Some(pc) => pc,
+        None => return Vec::new(),
+    };
+    let (start, code) = &view.code;
+    let instructions = disassemble(*start, code);
+    let current = instructions.iter().position(|(addr, _)| *addr == pc).unwrap_or(0);
+    let first = current.saturating_sub(height / 3);
+    instructions[first..]
+        .iter()
+        .take(height)
+        .map(|(addr, text)| {
+            let marker = match (*addr == pc, view.breakpoint_addrs.contains(addr)) {
+                (true, _) => '>',
+                (false, true) => 'b',
+                (false, false) => ' ',
+            };
+            let style = if *addr == pc { Style::Current } else { Style::Normal };
+            vec![(format!("{} {:#x}  {}", marker, addr, text), style)]
+        })
+        .collect()
+}
+
+/// Decodes the machine code `code` at address `start`, returning the instructions' addresses
+/// and text.
+#[cfg(target_arch = "x86_64")]
+fn disassemble(start: usize, code: &[u8]) -> Vec<(usize, String)> {
+    let mut decoder = Decoder::with_ip(64, code, start as u64, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    // numbers written like everywhere else in deet
+    formatter.options_mut().set_hex_prefix("0x");
+    formatter.options_mut().set_hex_suffix("");
+    formatter.options_mut().set_branch_leading_zeros(false);
+    formatter.options_mut().set_space_after_operand_separator(true);
+    let mut instruction = Instruction::default();
+    let mut instructions = Vec::new();
+    while decoder.can_decode() {
+        decoder.decode_out(&mut instruction);
+        let mut text = String::new();
+        formatter.format(&instruction, &mut text);
+        instructions.push((instruction.ip() as usize, text));
+    }
+    instructions
+}
+
+/// Lists the machine code `code` at address `start` as raw instruction words, which is as far
+/// as deet decodes aarch64 code (whose support is experimental, see `arch`).
+#[cfg(not(target_arch = "x86_64"))]
+fn disassemble(start: usize, code: &[u8]) -> Vec<(usize, String)> {
+    code.chunks_exact(4)
+        .enumerate()
+        .map(|(i, word)| (start + 4 * i, format!(".inst {:#010x}", u32::from_le_bytes(word.try_into().unwrap()))))
+        .collect()
+}
+
+/// Draws a box with its top left corner at `(row, col)` and the given outer size, with `title`
+/// in its top border and `lines` inside, cut off at its edges.
+fn draw_pane(out: &mut String, (row, col): (u16, u16), (height, width): (u16, u16), title: &str, lines: Vec<PaneLine>) {
+    if height < 2 || width < 4 {
+        return;
+    }
+    let inner = width as usize - 2;
+    let title: String = format!(" {} ", title).chars().take(inner).collect();
+    let border = "─".repeat(inner - title.chars().count());
+    out.push_str(&format!("\x1b[{};{}H┌{}{}┐", row, col, title, border));
+    let mut lines = lines.into_iter();
+    for offset in 1..height - 1 {
+        out.push_str(&format!("\x1b[{};{}H│", row + offset, col));
+        let mut used = 0;
+        for (text, style) in lines.next().unwrap_or_default() {
+            if used == inner {
+                break;
+            }
+            let text: String = text.chars().take(inner - used).collect();
+            used += text.chars().count();
+            match style {
+                Style::Normal => out.push_str(&text),
+                Style::Current => out.push_str(&format!("\x1b[7m{}", text)),
+                Style::Changed => out.push_str(&format!("\x1b[1;31m{}\x1b[0m", text)),
+            }
+        }
+        // the highlight of the current line runs to the edge of the pane
+        out.push_str(&" ".repeat(inner - used));
+        out.push_str("\x1b[0m│");
+    }
+    out.push_str(&format!("\x1b[{};{}H└{}┘", row + height - 1, col, "─".repeat(inner)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_disassembly_lines() {
+
+        // push rbp; mov rbp, rsp; nop; pop rbp; ret
+        let view = View {
+            pc: Some(0x1004),
+            code: (0x1000, vec![0x55, 0x48, 0x89, 0xe5, 0x90, 0x5d, 0xc3]),
+            breakpoint_addrs: [0x1005].into_iter().collect(),
+            ..View::default()
+        };
+        let lines: Vec<String> = disassembly_lines(&view, 4).into_iter().map(|line| line[0].0.clone()).collect();
+        assert_eq!(lines, vec!["  0x1001  mov rbp, rsp", "> 0x1004  nop", "b 0x1005  pop rbp", "  0x1006  ret"]);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "x86_64"))]
+    fn test_disassembly_lines() {
+        // nop; ret
+        let view = View {
+            pc: Some(0x1004),
+            code: (0x1000, [0xd503201fu32, 0xd65f03c0].iter().flat_map(|word| word.to_le_bytes()).collect()),
+            breakpoint_addrs: [0x1000].into_iter().collect(),
+            ..View::default()
+        };
+        let lines: Vec<String> = disassembly_lines(&view, 4).into_iter().map(|line| line[0].0.clone()).collect();
+        assert_eq!(lines, vec!["b 0x1000  .inst 0xd503201f", "> 0x1004  .inst 0xd65f03c0"]);
+    }
+}
@@ -0,0 +1,578 @@
+//! Formats values read from the inferior's memory according to their DWARF types. Structures are
+//! shown like Rust's `{:?}` shows them, and common Rust standard library types (`String`, `&str`,
+//! `Vec<T>`, slices, `Option<T>` and other enums) are shown by their contents rather than their
+//! internal layout.
+
+use crate::dwarf_data::{Encoding, Struct, Type, TypeKind};
+
+/// How deeply nested values (and references) are followed.
+const MAX_DEPTH: usize = 8;
+/// How many elements of a `Vec` or array are shown.
+const MAX_ELEMENTS: usize = 100;
+/// How many bytes of a string are shown.
+const MAX_STRING_LEN: usize = 1024;
+
+type GetType<'a> = &'a dyn Fn(usize) -> Option<&'a Type>;
+type ReadMemory<'a> = &'a dyn Fn(usize, usize) -> Result<Vec<u8>, nix::Error>;
+
+pub struct ValueFormatter<'a> {
+    get_type: GetType<'a>,
+    read_memory: ReadMemory<'a>,
+}
+
+impl<'a> ValueFormatter<'a> {
+    /// `get_type(offset)` looks up the type whose entry is at `offset` in .debug_info, and
+    /// `read_memory(addr, len)` reads `len` bytes of the inferior's memory.
+    pub fn new(get_type: GetType<'a>, read_memory: ReadMemory<'a>) -> Self {
+        ValueFormatter { get_type, read_memory }
+    }
+
+    /// Formats the value of type `entity_type` stored at `addr`.
+    pub fn format(&self, addr: usize, entity_type: &Type) -> Result<String, nix::Error> {
+        self.format_at(addr, entity_type, 0)
+    }
+
+    fn format_at(
+        &self,
+        addr: usize,
+        entity_type: &Type,
+        depth: usize,
+    ) -> Result<String, nix::Error> {
+        if depth > MAX_DEPTH {
+            return Ok("...".to_string());
+        }
+        match &entity_type.kind {
+            TypeKind::Base(encoding) => {
+                let bytes = (self.read_memory)(addr, entity_type.size)?;
+                Ok(format_base(&bytes, *encoding))
+            }
+            TypeKind::Enum(enumerators) => {
+                let value = self.read_uint(addr, entity_type.size)?;
+                let signed = sign_extend(value, entity_type.size);
+                match enumerators
+                    .iter()
+                    .find(|(_, enumerator)| *enumerator == signed)
+                {
+                    Some((name, _)) => Ok(name.clone()),
+                    None => Ok(signed.to_string()),
+                }
+            }
+            TypeKind::Pointer(pointee) => {
+                let ptr = self.read_uint(addr, 8)? as usize;
+                // Rust references and boxes always point to a valid value, so show it
+                let name = entity_type.name.as_str();
+                let is_reference = name.starts_with('&');
+                let is_box = name.starts_with("alloc::boxed::Box<");
+                if let Some(pointee) = pointee.and_then(self.get_type) {
+                    if (is_reference || is_box) && ptr != 0 {
+                        let value = self.format_at(ptr, pointee, depth + 1)?;
+                        return Ok(if is_box {
+                            format!("Box::new({})", value)
+                        } else {
+                            format!("&{}", value)
+                        });
+                    }
+                }
+                Ok(format!("{:#x}", ptr))
+            }
+            TypeKind::Array { element, count } => {
+                let element = self.type_at(*element)?;
+                let count = count.unwrap_or(0);
+                if matches!(
+                    element.kind,
+                    TypeKind::Base(Encoding::SignedChar | Encoding::UnsignedChar)
+                ) {
+                    // show C strings up to their terminating NUL
+                    let bytes = (self.read_memory)(addr, count.min(MAX_STRING_LEN))?;
+                    let len = bytes
+                        .iter()
+                        .position(|byte| *byte == 0)
+                        .unwrap_or(bytes.len());
+                    return Ok(format!("{:?}", String::from_utf8_lossy(&bytes[..len])));
+                }
+                let elements = self.format_elements(addr, element, count, depth)?;
+                Ok(format!("[{}]", elements))
+            }
+            TypeKind::Struct(structure) => self.format_struct(addr, entity_type, structure, depth),
+            TypeKind::Alias(Some(target)) => self.format_at(addr, self.type_at(*target)?, depth),
+            TypeKind::Alias(None) | TypeKind::Unknown => {
+                let bytes = (self.read_memory)(addr, entity_type.size)?;
+                Ok(format!("{:02x?}", bytes))
+            }
+        }
+    }
+
+    fn format_struct(
+        &self,
+        addr: usize,
+        entity_type: &Type,
+        structure: &Struct,
+        depth: usize,
+    ) -> Result<String, nix::Error> {
+        let name = entity_type.name.as_str();
+        if name == "&str" || name == "&mut str" {
+            let (ptr, len) = self.fat_pointer(addr, structure)?;
+            return self.format_string(ptr, len);
+        }
+        if name.starts_with("&[") || name.starts_with("&mut [") {
+            let (ptr, len) = self.fat_pointer(addr, structure)?;
+            let element = match self
+                .member(structure, "data_ptr")
+                .map(|member| &member.kind)
+            {
+                Some(TypeKind::Pointer(Some(element))) => self.type_at(*element)?,
+                _ => return Err(nix::Error::EINVAL),
+            };
+            return Ok(format!(
+                "[{}]",
+                self.format_elements(ptr, element, len, depth)?
+            ));
+        }
+        if name == "String" {
+            let vec = structure.members.iter().find(|member| member.name == "vec");
+            if let Some(vec) = vec {
+                if let TypeKind::Struct(vec_structure) = &self.type_at(vec.type_offset)?.kind {
+                    let (ptr, len, _) = self.vec_contents(addr + vec.offset, vec_structure)?;
+                    return self.format_string(ptr, len);
+                }
+            }
+        }
+        if name.starts_with("Vec<") {
+            let (ptr, len, element) = self.vec_contents(addr, structure)?;
+            return Ok(format!(
+                "vec![{}]",
+                self.format_elements(ptr, element, len, depth)?
+            ));
+        }
+        if let Some(variants) = &structure.variants {
+            let discr = match &variants.discriminant {
+                Some(member) => {
+                    let size = self.type_at(member.type_offset)?.size;
+                    Some(self.read_uint(addr + member.offset, size)?)
+                }
+                None => None,
+            };
+            let variant = variants
+                .variants
+                .iter()
+                .find(|variant| variant.discr_value.is_some() && variant.discr_value == discr)
+                .or_else(|| {
+                    variants
+                        .variants
+                        .iter()
+                        .find(|variant| variant.discr_value.is_none())
+                });
+            return match variant {
+                Some(variant) => {
+                    let variant_type = self.type_at(variant.member.type_offset)?;
+                    match &variant_type.kind {
+                        TypeKind::Struct(fields) => self.format_fields(
+                            &variant.member.name,
+                            addr + variant.member.offset,
+                            fields,
+                            depth,
+                        ),
+                        _ => Ok(variant.member.name.clone()),
+                    }
+                }
+                None => Ok(format!("<invalid discriminant {:?}>", discr)),
+            };
+        }
+        self.format_fields(name, addr, structure, depth)
+    }
+
+    /// Formats a structure's members as `Name { a: 1, b: 2 }`, or as `Name(1, 2)` for tuple
+    /// structures, whose members are named `__0`, `__1`...
+    fn format_fields(
+        &self,
+        name: &str,
+        addr: usize,
+        structure: &Struct,
+        depth: usize,
+    ) -> Result<String, nix::Error> {
+        if structure.members.is_empty() {
+            return Ok(name.to_string());
+        }
+        let is_tuple = structure
+            .members
+            .iter()
+            .all(|member| member.name.starts_with("__"));
+        let mut fields = Vec::new();
+        for member in &structure.members {
+            let value = self.format_at(
+                addr + member.offset,
+                self.type_at(member.type_offset)?,
+                depth + 1,
+            )?;
+            if is_tuple {
+                fields.push(value);
+            } else {
+                fields.push(format!("{}: {}", member.name, value));
+            }
+        }
+        let fields = fields.join(", ");
+        Ok(match (is_tuple, name) {
+            // Rust tuples are named after their element types, e.g. "(i32, f64)"
+            (true, name) if name.starts_with('(') || name.is_empty() => format!("({})", fields),
+            (true, name) => format!("{}({})", name, fields),
+            (false, "") => format!("{{ {} }}", fields),
+            (false, name) => format!("{} {{ {} }}", name, fields),
+        })
+    }
+
+    fn format_elements(
+        &self,
+        addr: usize,
+        element: &Type,
+        count: usize,
+        depth: usize,
+    ) -> Result<String, nix::Error> {
+        let size = self.size_of(element);
+        let mut elements = Vec::new();
+        for i in 0..count.min(MAX_ELEMENTS) {
+            elements.push(self.format_at(addr + i * size, element, depth + 1)?);
+        }
+        if count > MAX_ELEMENTS {
+            elements.push("...".to_string());
+        }
+        Ok(elements.join(", "))
+    }
+
+    fn format_string(&self, ptr: usize, len: usize) -> Result<String, nix::Error> {
+        let bytes = (self.read_memory)(ptr, len.min(MAX_STRING_LEN))?;
+        let text = format!("{:?}", String::from_utf8_lossy(&bytes));
+        if len > MAX_STRING_LEN {
+            Ok(format!("{}...", text))
+        } else {
+            Ok(text)
+        }
+    }
+
+    /// Reads the `data_ptr` and `length` of a Rust slice or `&str`.
+    fn fat_pointer(&self, addr: usize, structure: &Struct) -> Result<(usize, usize), nix::Error> {
+        let field = |name: &str| {
+            let member = structure.members.iter().find(|member| member.name == name);
+            member
+                .map(|member| addr + member.offset)
+                .ok_or(nix::Error::EINVAL)
+        };
+        let ptr = self.read_uint(field("data_ptr")?, 8)? as usize;
+        let len = self.read_uint(field("length")?, 8)? as usize;
+        Ok((ptr, len))
+    }
+
+    /// Finds the buffer, length and element type of a `Vec<T>`. The buffer pointer is the first
+    /// pointer inside the `buf` member, whose layout differs between Rust versions.
+    fn vec_contents(
+        &self,
+        addr: usize,
+        structure: &Struct,
+    ) -> Result<(usize, usize, &Type), nix::Error> {
+        let len_member = structure.members.iter().find(|member| member.name == "len");
+        let buf_member = structure.members.iter().find(|member| member.name == "buf");
+        let element = structure
+            .template_params
+            .iter()
+            .find(|(name, _)| name == "T");
+        match (len_member, buf_member, element) {
+            (Some(len_member), Some(buf_member), Some((_, element))) => {
+                let len = self.read_uint(addr + len_member.offset, 8)? as usize;
+                let buf_type = self.type_at(buf_member.type_offset)?;
+                let ptr = self
+                    .find_pointer(addr + buf_member.offset, buf_type, 0)?
+                    .ok_or(nix::Error::EINVAL)?;
+                Ok((ptr, len, self.type_at(*element)?))
+            }
+            _ => Err(nix::Error::EINVAL),
+        }
+    }
+
+    fn find_pointer(
+        &self,
+        addr: usize,
+        entity_type: &Type,
+        depth: usize,
+    ) -> Result<Option<usize>, nix::Error> {
+        if depth > MAX_DEPTH {
+            return Ok(None);
+        }
+        match &entity_type.kind {
+            TypeKind::Pointer(_) => Ok(Some(self.read_uint(addr, 8)? as usize)),
+            TypeKind::Alias(Some(target)) => {
+                self.find_pointer(addr, self.type_at(*target)?, depth + 1)
+            }
+            TypeKind::Struct(structure) => {
+                for member in &structure.members {
+                    let member_type = self.type_at(member.type_offset)?;
+                    if let Some(ptr) =
+                        self.find_pointer(addr + member.offset, member_type, depth + 1)?
+                    {
+                        return Ok(Some(ptr));
+                    }
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the type of the member `name`.
+    fn member(&self, structure: &Struct, name: &str) -> Option<&Type> {
+        let member = structure
+            .members
+            .iter()
+            .find(|member| member.name == name)?;
+        (self.get_type)(member.type_offset)
+    }
+
+    fn type_at(&self, offset: usize) -> Result<&Type, nix::Error> {
+        (self.get_type)(offset).ok_or(nix::Error::EINVAL)
+    }
+
+    /// Returns the size of a value of `entity_type`, looking through typedefs.
+    fn size_of(&self, entity_type: &Type) -> usize {
+        match &entity_type.kind {
+            TypeKind::Alias(Some(target)) if entity_type.size == 0 => (self.get_type)(*target).map_or(0, |target| self.size_of(target)),
+            TypeKind::Array { element, count } if entity_type.size == 0 => {
+                let element_size = (self.get_type)(*element).map_or(0, |element| self.size_of(element));
+                element_size * count.unwrap_or(0)
+            }
+            _ => entity_type.size,
+        }
+    }
+
+    fn read_uint(&self, addr: usize, size: usize) -> Result<u64, nix::Error> {
+        let bytes = (self.read_memory)(addr, size.min(8))?;
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(&bytes);
+        Ok(u64::from_le_bytes(raw))
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    let shift = 64 - 8 * size.clamp(1, 8) as u32;
+    ((value << shift) as i64) >> shift
+}
+
+/// Formats the raw bytes of a value of a base type.
+fn format_base(bytes: &[u8], encoding: Encoding) -> String {
+    let mut raw = [0u8; 8];
+    if bytes.len() > raw.len() {
+        return format!("{:02x?}", bytes);
+    }
+    raw[..bytes.len()].copy_from_slice(bytes);
+    let unsigned = u64::from_le_bytes(raw);
+    let signed = sign_extend(unsigned, bytes.len());
+    match encoding {
+        Encoding::Float if bytes.len() == 4 => f32::from_bits(unsigned as u32).to_string(),
+        Encoding::Float if bytes.len() == 8 => f64::from_bits(unsigned).to_string(),
+        Encoding::Boolean => (unsigned != 0).to_string(),
+        Encoding::SignedChar if bytes.len() == 1 => {
+            format!("{} {:?}", signed, unsigned as u8 as char)
+        }
+        Encoding::UnsignedChar if bytes.len() == 1 => {
+            format!("{} {:?}", unsigned, unsigned as u8 as char)
+        }
+        Encoding::Utf => match char::from_u32(unsigned as u32) {
+            Some(c) => format!("{:?}", c),
+            None => unsigned.to_string(),
+        },
+        Encoding::Unsigned | Encoding::UnsignedChar => unsigned.to_string(),
+        _ => signed.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::{Member, Variant, Variants};
+    use std::collections::HashMap;
+
+    const I32: usize = 1;
+    const U8: usize = 2;
+    const U8_PTR: usize = 3;
+    const STR: usize = 4;
+    const POINT: usize = 5;
+    const OPTION: usize = 6;
+    const SOME: usize = 7;
+    const NONE: usize = 8;
+    const U32: usize = 9;
+    const VEC: usize = 10;
+    const RAW_VEC: usize = 11;
+    const STRING: usize = 12;
+    const VEC_U8: usize = 13;
+
+    fn member(name: &str, offset: usize, type_offset: usize) -> Member {
+        Member {
+            name: name.to_string(),
+            offset,
+            type_offset,
+        }
+    }
+
+    fn structure(members: Vec<Member>) -> TypeKind {
+        TypeKind::Struct(Struct {
+            members,
+            ..Default::default()
+        })
+    }
+
+    fn types() -> HashMap<usize, Type> {
+        let vec = |element| {
+            TypeKind::Struct(Struct {
+                members: vec![member("buf", 0, RAW_VEC), member("len", 16, U32)],
+                template_params: vec![("T".to_string(), element)],
+                variants: None,
+            })
+        };
+        let option = TypeKind::Struct(Struct {
+            variants: Some(Variants {
+                discriminant: Some(member("", 0, U32)),
+                variants: vec![
+                    Variant {
+                        discr_value: Some(0),
+                        member: member("None", 0, NONE),
+                    },
+                    Variant {
+                        discr_value: Some(1),
+                        member: member("Some", 0, SOME),
+                    },
+                ],
+            }),
+            ..Default::default()
+        });
+        HashMap::from([
+            (
+                I32,
+                Type::new("i32".to_string(), 4, TypeKind::Base(Encoding::Signed)),
+            ),
+            (
+                U32,
+                Type::new("u32".to_string(), 4, TypeKind::Base(Encoding::Unsigned)),
+            ),
+            (
+                U8,
+                Type::new("u8".to_string(), 1, TypeKind::Base(Encoding::Unsigned)),
+            ),
+            (
+                U8_PTR,
+                Type::new("*const u8".to_string(), 8, TypeKind::Pointer(Some(U8))),
+            ),
+            (
+                STR,
+                Type::new(
+                    "&str".to_string(),
+                    16,
+                    structure(vec![
+                        member("data_ptr", 0, U8_PTR),
+                        member("length", 8, U32),
+                    ]),
+                ),
+            ),
+            (
+                POINT,
+                Type::new(
+                    "Point".to_string(),
+                    8,
+                    structure(vec![member("x", 0, I32), member("y", 4, I32)]),
+                ),
+            ),
+            (OPTION, Type::new("Option<i32>".to_string(), 8, option)),
+            (
+                SOME,
+                Type::new(
+                    "Some".to_string(),
+                    8,
+                    structure(vec![member("__0", 4, I32)]),
+                ),
+            ),
+            (NONE, Type::new("None".to_string(), 8, structure(vec![]))),
+            (
+                RAW_VEC,
+                Type::new(
+                    "RawVec<u8>".to_string(),
+                    16,
+                    structure(vec![member("ptr", 0, U8_PTR)]),
+                ),
+            ),
+            (
+                VEC,
+                Type::new("Vec<i32, alloc::alloc::Global>".to_string(), 24, vec(I32)),
+            ),
+            (
+                VEC_U8,
+                Type::new("Vec<u8, alloc::alloc::Global>".to_string(), 24, vec(U8)),
+            ),
+            (
+                STRING,
+                Type::new(
+                    "String".to_string(),
+                    24,
+                    structure(vec![member("vec", 0, VEC_U8)]),
+                ),
+            ),
+        ])
+    }
+
+    /// Formats a value of type `offset`, with `memory` mapped at address 0x1000.
+    fn format(offset: usize, memory: &[u8]) -> String {
+        let types = types();
+        let read_memory = |addr: usize, len: usize| {
+            let start = addr.checked_sub(0x1000).ok_or(nix::Error::EFAULT)?;
+            memory
+                .get(start..start + len)
+                .map(|bytes| bytes.to_vec())
+                .ok_or(nix::Error::EFAULT)
+        };
+        let get_type = |offset| types.get(&offset);
+        let formatter = ValueFormatter::new(&get_type, &read_memory);
+        formatter.format(0x1000, &types[&offset]).unwrap()
+    }
+
+    #[test]
+    fn test_format_values() {
+        assert_eq!(format(I32, &(-5i32).to_le_bytes()), "-5");
+        assert_eq!(
+            format(POINT, &[3, 0, 0, 0, 4, 0, 0, 0]),
+            "Point { x: 3, y: 4 }"
+        );
+        assert_eq!(format(OPTION, &[1, 0, 0, 0, 7, 0, 0, 0]), "Some(7)");
+        assert_eq!(format(OPTION, &[0, 0, 0, 0, 7, 0, 0, 0]), "None");
+
+        // a fat pointer to the 2 bytes right after it
+        let mut str_memory = vec![0x10, 0x10, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+        str_memory.extend_from_slice(b"hi");
+        assert_eq!(format(STR, &str_memory), "\"hi\"");
+
+        // buf pointer, capacity, length and then the elements
+        let mut vec_memory = vec![
+            0x18, 0x10, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        for element in [1i32, 2, 3] {
+            vec_memory.extend_from_slice(&element.to_le_bytes());
+        }
+        assert_eq!(format(VEC, &vec_memory), "vec![1, 2, 3]");
+
+        let mut string_memory = vec![
+            0x18, 0x10, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        string_memory.extend_from_slice(b"hello");
+        assert_eq!(format(STRING, &string_memory), "\"hello\"");
+    }
+
+    #[test]
+    fn test_format_base() {
+        assert_eq!(format_base(&[0xff], Encoding::SignedChar), "-1 'ÿ'");
+        assert_eq!(format_base(&[65], Encoding::SignedChar), "65 'A'");
+        assert_eq!(format_base(&[0xff, 0xff], Encoding::Unsigned), "65535");
+        assert_eq!(
+            format_base(&0x1f600u32.to_le_bytes(), Encoding::Utf),
+            "'😀'"
+        );
+        assert_eq!(
+            format_base(&1.5f64.to_bits().to_le_bytes(), Encoding::Float),
+            "1.5"
+        );
+        assert_eq!(format_base(&[1], Encoding::Boolean), "true");
+    }
+}
@@ -0,0 +1,286 @@
+//! Builds the programs the integration tests debug, and runs deet on them with a command script,
+//! collecting the events it reports with `--interpreter=json`.
+
+use serde_json::Value;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a script may run before deet is taken to hang.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serializes builds, so that tests sharing a program don't write it at the same time.
+static BUILD_LOCK: Mutex<()> = Mutex::new(());
+
+static NEXT_SCRIPT: AtomicUsize = AtomicUsize::new(0);
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Builds `source` into `output` with `command`, unless it is up to date.
+fn build(source: &Path, output: &Path, command: &mut Command) -> PathBuf {
+    let _lock = BUILD_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    if modified(output).is_some() && modified(output) >= modified(source) {
+        return output.to_path_buf();
+    }
+    let status = command
+        .status()
+        .unwrap_or_else(|err| panic!("can't run the compiler for {}: {}", source.display(), err));
+    assert!(status.success(), "building {} failed", source.display());
+    output.to_path_buf()
+}
+
+/// Builds the C program `name`.c from samples/ or tests/fixtures/ the way the Makefile does,
+/// so that its debugging information names the source relative to the compilation directory.
+pub fn c_program(name: &str) -> PathBuf {
+    let file = format!("{}.c", name);
+    let relative_source = [Path::new("samples"), Path::new("tests/fixtures")]
+        .into_iter()
+        .map(|dir| dir.join(&file))
+        .find(|source| manifest_dir().join(source).exists())
+        .unwrap_or_else(|| panic!("no program {}", file));
+    let source = manifest_dir().join(&relative_source);
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    build(
+        &source,
+        &output,
+        Command::new(cc)
+            .current_dir(manifest_dir())
+            .args(["-O0", "-g", "-no-pie", "-fno-omit-frame-pointer", "-o"])
+            .arg(&output)
+            .arg(&relative_source),
+    )
+}
+
+/// Builds the Rust program tests/fixtures/`name`.rs, unoptimized and with frame pointers.
+pub fn rust_program(name: &str) -> PathBuf {
+    let source = manifest_dir().join("tests/fixtures").join(format!("{}.rs", name));
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    build(
+        &source,
+        &output,
+        Command::new("rustc")
+            .args(["-g", "-C", "opt-level=0", "-C", "force-frame-pointers=yes", "-o"])
+            .arg(&output)
+            .arg(&source),
+    )
+}
+
+/// Runs deet on `program` in `dir` the way a person would, with `options` and the lines of
+/// `input` typed at its prompt, and returns what it printed. `dir` is also its home directory,
+/// so that no ~/.deetinit of the user running the tests is read.
+pub fn run_interactive(program: &Path, dir: &Path, options: &[&str], input: &[&str]) -> String {
+    let mut deet = Command::new(env!("CARGO_BIN_EXE_deet"))
+        .args(options)
+        .arg(program)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("can't start deet");
+    let mut stdin = deet.stdin.take().unwrap();
+    stdin.write_all((input.join("\n") + "\n").as_bytes()).unwrap();
+    drop(stdin);
+    let output = deet.wait_with_output().unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// A running deet, whose JSON records are read as it writes them.
+struct Deet {
+    child: Child,
+    lines: Receiver<String>,
+    started: Instant,
+    program: PathBuf,
+}
+
+impl Deet {
+    fn spawn(command: &mut Command, program: &Path) -> Deet {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("can't start deet");
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Deet { child, lines, started: Instant::now(), program: program.to_path_buf() }
+    }
+
+    /// Kills deet if it has run too long, taking it to hang.
+    fn check_timeout(&mut self) {
+        if self.started.elapsed() > TIMEOUT {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            panic!("deet didn't finish on {}", self.program.display());
+        }
+    }
+
+    /// Returns the next record deet writes, or None once it closes its stdout.
+    fn next_record(&mut self) -> Option<Value> {
+        loop {
+            match self.lines.recv_timeout(Duration::from_millis(10)) {
+                Ok(line) => {
+                    return Some(
+                        serde_json::from_str(&line).unwrap_or_else(|_| panic!("not a JSON record: {}", line)),
+                    )
+                }
+                Err(RecvTimeoutError::Timeout) => self.check_timeout(),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Collects the rest of deet's records after `events` until it exits.
+    fn finish(mut self, mut events: Vec<Value>) -> Session {
+        while let Some(record) = self.next_record() {
+            events.push(record);
+        }
+        let status = loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                break status;
+            }
+            self.check_timeout();
+            thread::sleep(Duration::from_millis(10));
+        };
+        Session { events, status: status.code() }
+    }
+}
+
+/// What deet reported while running a script.
+pub struct Session {
+    pub events: Vec<Value>,
+    /// The status deet exited with.
+    pub status: Option<i32>,
+}
+
+impl Session {
+    /// Runs deet on `program` with the commands in `script`, one per line, and collects its
+    /// events until the script ends.
+    pub fn run(program: &Path, script: &[&str]) -> Session {
+        let script_path = Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("script-{}-{}", std::process::id(), NEXT_SCRIPT.fetch_add(1, Ordering::SeqCst)));
+        fs::write(&script_path, script.join("\n") + "\n").unwrap();
+        let deet = Deet::spawn(
+            Command::new(env!("CARGO_BIN_EXE_deet"))
+                .args(["-nx", "-batch", "--interpreter=json", "-x"])
+                .arg(&script_path)
+                .arg(program)
+                .stdin(Stdio::null()),
+            program,
+        );
+        let session = deet.finish(Vec::new());
+        let _ = fs::remove_file(&script_path);
+        session
+    }
+
+    /// Runs deet on `program` with `--interpreter=json`, sending it a request for each of
+    /// `commands` on its stdin once the previous one is done, the way a front end would, and
+    /// collects its events until it exits.
+    pub fn serve(program: &Path, commands: &[&str]) -> Session {
+        let mut deet = Deet::spawn(
+            Command::new(env!("CARGO_BIN_EXE_deet"))
+                .args(["-nx", "--interpreter=json"])
+                .arg(program)
+                .stdin(Stdio::piped()),
+            program,
+        );
+        let mut stdin = deet.child.stdin.take().unwrap();
+        let mut events = Vec::new();
+        for (id, command) in commands.iter().enumerate() {
+            writeln!(stdin, "{}", serde_json::json!({"id": id, "command": command})).unwrap();
+            while let Some(record) = deet.next_record() {
+                let done = record["type"] == "done" && record["id"] == id;
+                events.push(record);
+                if done {
+                    break;
+                }
+            }
+        }
+        drop(stdin);
+        deet.finish(events)
+    }
+
+    fn events_of(&self, event: &str) -> impl Iterator<Item = &Value> + '_ {
+        let event = event.to_string();
+        self.events.iter().filter(move |record| record["event"] == event.as_str())
+    }
+
+    /// The stops, as (function, line, reason).
+    pub fn stops(&self) -> Vec<(&str, u64, &str)> {
+        self.events_of("stopped")
+            .map(|stop| {
+                (
+                    stop["function"].as_str().unwrap_or("??"),
+                    stop["line"].as_u64().unwrap_or(0),
+                    stop["reason"].as_str().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// The numbers of the breakpoints stopped at, in order.
+    pub fn breakpoint_hits(&self) -> Vec<u64> {
+        self.events_of("stopped").filter_map(|stop| stop["breakpoint"].as_u64()).collect()
+    }
+
+    /// The backtraces printed, each as its frames' (function, line).
+    pub fn backtraces(&self) -> Vec<Vec<(&str, u64)>> {
+        self.events_of("backtrace")
+            .map(|backtrace| {
+                backtrace["frames"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|frame| (frame["function"].as_str().unwrap_or("??"), frame["line"].as_u64().unwrap_or(0)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The values printed, as (name, value).
+    pub fn values(&self) -> Vec<(&str, &str)> {
+        self.events_of("value")
+            .map(|value| (value["name"].as_str().unwrap(), value["value"].as_str().unwrap()))
+            .collect()
+    }
+
+    /// The exit codes of the runs that exited.
+    pub fn exit_codes(&self) -> Vec<i64> {
+        self.events_of("exited").map(|exited| exited["code"].as_i64().unwrap()).collect()
+    }
+
+    /// What the program wrote to its standard output.
+    pub fn output(&self) -> String {
+        self.events
+            .iter()
+            .filter(|record| record["type"] == "target-output" && record["stream"] == "stdout")
+            .map(|record| record["text"].as_str().unwrap())
+            .collect()
+    }
+
+    /// The error messages deet reported.
+    pub fn errors(&self) -> Vec<&str> {
+        self.events
+            .iter()
+            .filter(|record| record["type"] == "error")
+            .map(|record| record["message"].as_str().unwrap())
+            .collect()
+    }
+}
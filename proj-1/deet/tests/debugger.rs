@@ -0,0 +1,201 @@
+//! Runs deet on small C and Rust programs with command scripts, and checks where they stop, the
+//! backtraces and values printed, and how they exit.
+
+mod common;
+
+use common::{c_program, run_interactive, rust_program, Session};
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_breakpoints_by_function_and_line() {
+    let program = c_program("function_calls");
+    let session = Session::run(
+        &program,
+        &["break func3", "break function_calls.c:12", "run", "continue", "continue", "continue"],
+    );
+    assert_eq!(
+        session.stops(),
+        [("func2", 12, "breakpoint"), ("func3", 6, "breakpoint"), ("func3", 6, "breakpoint")]
+    );
+    assert_eq!(session.breakpoint_hits(), [1, 0, 0]);
+    assert_eq!(session.exit_codes(), [0]);
+    assert!(session.errors().is_empty(), "{:?}", session.errors());
+}
+
+#[test]
+fn test_backtrace() {
+    let program = c_program("function_calls");
+    let session = Session::run(&program, &["break func3", "run", "backtrace", "continue", "backtrace"]);
+    assert_eq!(
+        session.backtraces(),
+        [
+            vec![("func3", 6), ("func2", 13), ("func1", 18), ("main", 24)],
+            vec![("func3", 6), ("func1", 19), ("main", 24)],
+        ]
+    );
+}
+
+#[test]
+fn test_exit_status_and_output() {
+    let program = c_program("loop");
+    let session = Session::run(&program, &["run"]);
+    assert!(session.stops().is_empty());
+    assert_eq!(session.output(), "total = 14\n");
+    assert_eq!(session.exit_codes(), [14]);
+}
+
+/// In batch mode deet exits with the status of the last inferior, not one that ran before it.
+#[test]
+fn test_batch_exit_status() {
+    let program = c_program("loop");
+    let session = Session::run(&program, &["run", "break main", "run"]);
+    assert_eq!(session.exit_codes(), [14]);
+    assert_eq!(session.stops(), [("main", 8, "breakpoint")]);
+    assert_eq!(session.status, Some(0));
+}
+
+/// With `--interpreter=json`, deet's stdin carries the requests, which the program mustn't get.
+#[test]
+fn test_json_requests_not_read_by_program() {
+    let program = c_program("read_input");
+    let session = Session::serve(&program, &["run", "break main"]);
+    assert_eq!(session.output(), "no input\n");
+    assert_eq!(session.exit_codes(), [0]);
+    assert!(session.events.iter().any(|event| event["event"] == "breakpoint-created"));
+}
+
+#[test]
+fn test_signal() {
+    let program = c_program("segfault");
+    let session = Session::run(&program, &["run", "backtrace"]);
+    let stop = session.events.iter().find(|event| event["event"] == "stopped").unwrap();
+    assert_eq!(stop["signal"], "SIGSEGV");
+    assert_eq!(session.stops(), [("func2", 5, "signal")]);
+    assert_eq!(session.backtraces(), [vec![("func2", 5), ("func1", 11), ("main", 15)]]);
+    assert!(session.exit_codes().is_empty());
+}
+
+/// The instructions breakpoints replace have to run as if the breakpoints weren't there, however
+/// often they are hit and whether or not they are deleted while the program is stopped at them.
+#[test]
+fn test_breakpoint_restore() {
+    let program = c_program("loop");
+    let session = Session::run(
+        &program,
+        &["break square", "break 10", "run", "continue", "continue", "delete 0", "continue", "continue"],
+    );
+    assert_eq!(
+        session.stops(),
+        [
+            ("main", 10, "breakpoint"),
+            ("square", 4, "breakpoint"),
+            ("main", 10, "breakpoint"),
+            ("main", 10, "breakpoint"),
+        ]
+    );
+    assert_eq!(session.output(), "total = 14\n");
+    assert_eq!(session.exit_codes(), [14]);
+}
+
+#[test]
+fn test_breakpoints_across_run() {
+    let program = c_program("loop");
+    let session = Session::run(
+        &program,
+        &["break square", "run", "break main", "run", "continue", "continue", "continue", "continue"],
+    );
+    assert_eq!(
+        session.stops(),
+        [
+            ("square", 4, "breakpoint"),
+            ("main", 8, "breakpoint"),
+            ("square", 4, "breakpoint"),
+            ("square", 4, "breakpoint"),
+            ("square", 4, "breakpoint"),
+        ]
+    );
+    assert_eq!(session.breakpoint_hits(), [0, 1, 0, 0, 0]);
+    assert_eq!(session.output(), "total = 14\n");
+    assert_eq!(session.exit_codes(), [14]);
+}
+
+#[test]
+fn test_step_and_finish() {
+    let program = c_program("function_calls");
+    let session = Session::run(&program, &["break func1", "run", "next", "step", "finish", "next", "print a"]);
+    assert_eq!(
+        session.stops(),
+        [
+            ("func1", 17, "breakpoint"),
+            ("func1", 18, "step"),
+            ("func2", 10, "step"),
+            ("func1", 19, "step"),
+            ("func1", 20, "step"),
+        ]
+    );
+    assert_eq!(session.values(), [("a", "42")]);
+}
+
+#[test]
+fn test_next() {
+    let program = c_program("loop");
+    let session = Session::run(&program, &["break main", "run", "next", "next", "next", "next", "next"]);
+    assert_eq!(
+        session.stops(),
+        [
+            ("main", 8, "breakpoint"),
+            ("main", 9, "step"),
+            ("main", 10, "step"),
+            ("main", 9, "step"),
+            ("main", 10, "step"),
+            ("main", 9, "step"),
+        ]
+    );
+}
+
+#[test]
+fn test_rust_program() {
+    let program = rust_program("shapes");
+    let session = Session::run(
+        &program,
+        &["break area", "run", "backtrace", "print rect", "finish", "next", "print total", "continue"],
+    );
+    assert_eq!(
+        session.stops(),
+        [
+            ("shapes::area", 7, "breakpoint"),
+            ("shapes::main", 12, "step"),
+            ("shapes::main", 13, "step"),
+        ]
+    );
+    assert_eq!(session.backtraces(), [vec![("shapes::area", 7), ("shapes::main", 12)]]);
+    assert_eq!(
+        session.values(),
+        [("rect", "&Rect { width: 3, height: 4 }"), ("total", "12")]
+    );
+    assert_eq!(session.output(), "area = 12\n");
+    assert_eq!(session.exit_codes(), [12]);
+}
+
+/// The session keeps the breakpoints typed at the prompt, once each, but not the ones command
+/// files set, and batch runs leave it alone.
+#[test]
+fn test_session() {
+    let program = c_program("function_calls");
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("session");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join(".deet")).unwrap();
+    let script = dir.join("breakpoints");
+    fs::write(&script, "break func3\n").unwrap();
+    let script = script.to_str().unwrap();
+    for _ in 0..3 {
+        run_interactive(&program, &dir, &["-x", script], &["break func2", "quit"]);
+    }
+    let saved = fs::read_to_string(dir.join(".deet/session")).unwrap();
+    let commands: Vec<&str> = saved.lines().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(commands, ["break func2"]);
+
+    run_interactive(&program, &dir, &["-batch", "-x", script], &["break func1"]);
+    assert_eq!(fs::read_to_string(dir.join(".deet/session")).unwrap(), saved);
+}
@@ -0,0 +1,14 @@
+#include <stdio.h>
+
+int square(int x) {
+    return x * x;
+}
+
+int main() {
+    int total = 0;
+    for (int i = 1; i <= 3; i++) {
+        total += square(i);
+    }
+    printf("total = %d\n", total);
+    return total;
+}
@@ -0,0 +1,11 @@
+#include <stdio.h>
+
+int main() {
+    char line[256];
+    if (fgets(line, sizeof line, stdin) != NULL) {
+        printf("read: %s", line);
+        return 1;
+    }
+    printf("no input\n");
+    return 0;
+}
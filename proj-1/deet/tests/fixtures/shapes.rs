@@ -0,0 +1,15 @@
+struct Rect {
+    width: u32,
+    height: u32,
+}
+
+fn area(rect: &Rect) -> u32 {
+    rect.width * rect.height
+}
+
+fn main() {
+    let rect = Rect { width: 3, height: 4 };
+    let total = area(&rect);
+    println!("area = {}", total);
+    std::process::exit(total as i32);
+}